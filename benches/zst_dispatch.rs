@@ -0,0 +1,52 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Compares the cost of dispatching zero-sized marker messages against messages which carry data,
+//! and against signal handlers, which take no message at all.
+//!
+//! Run with `cargo bench --bench zst_dispatch`.
+
+extern crate handler_map;
+
+use std::cell::Cell;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use handler_map::HandlerMap;
+
+const ITERATIONS: u32 = 10_000_000;
+
+#[derive(Clone, Copy)]
+struct Marker;
+
+fn measure<F: FnMut()>(name: &str, mut f: F) -> Duration {
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let took = start.elapsed();
+    println!("{:<16} {:>8.2} ns/call", name, took.as_nanos() as f64 / f64::from(ITERATIONS));
+    took
+}
+
+fn main() {
+    let count = Cell::new(0u64);
+    let mut map = HandlerMap::new();
+    map.insert(|_: Marker| count.set(count.get() + 1));
+    map.insert(|msg: u64| count.set(count.get() + msg));
+    map.insert(|msg: [u64; 8]| count.set(count.get() + msg[0]));
+    map.insert_signal::<Marker, _>(|| count.set(count.get() + 1));
+
+    measure("call(Marker)", || assert!(map.call(black_box(Marker))));
+    measure("call(u64)", || assert!(map.call(black_box(1u64))));
+    measure("call([u64; 8])", || assert!(map.call(black_box([1u64; 8]))));
+    measure("signal::<Marker>", || assert!(map.signal::<Marker>()));
+
+    black_box(count.get());
+}
@@ -0,0 +1,349 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for handlers which return futures.
+//!
+//! The map doesn't run these futures itself. Instead, `call_async` hands the future back to the
+//! caller, so that it can be driven by whatever executor the caller is already using.
+
+use std::any::{Any, TypeId};
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use box_fn::BoxFn;
+use {HandlerMap, HandlerStore, Slot, SlotHandler};
+
+/// The type-erased future returned by an async handler.
+pub(crate) type BoxFuture<'a> = Pin<Box<dyn Future<Output = ()> + 'a>>;
+
+/// Error returned by the future from `call_async_deadline` when the handler didn't finish before
+/// its deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline;
+
+impl fmt::Display for Deadline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("handler did not complete before its deadline")
+    }
+}
+
+impl Error for Deadline {}
+
+/// Future which races a handler's future against a deadline.
+struct WithDeadline<'a> {
+    fut: BoxFuture<'a>,
+    deadline: Instant,
+}
+
+impl<'a> Future for WithDeadline<'a> {
+    type Output = Result<(), Deadline>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let Poll::Ready(()) = self.fut.as_mut().poll(cx) {
+            Poll::Ready(Ok(()))
+        } else if Instant::now() >= self.deadline {
+            Poll::Ready(Err(Deadline))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Registers a new async handler into the map.
+    ///
+    /// Async handlers return a future when called, which is returned by `call_async` to be run by
+    /// the caller. Since the future is returned out of the map, any data it needs must either be
+    /// moved into it from the message, or be captured by the handler for the map's lifetime
+    /// `'a`.
+    ///
+    /// An async handler takes the place of any other handler registered for the same type, and
+    /// vice-versa.
+    ///
+    /// In a map whose handlers are all `'static`, such as one that's shared with a runtime like
+    /// Tokio, this means the returned futures are `'static` as well, and can be spawned onto the
+    /// runtime rather than awaited in place:
+    ///
+    /// ```rust,edition2018
+    /// use handler_map::HandlerMap;
+    /// use std::future::Future;
+    /// use std::pin::pin;
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicU32, Ordering};
+    /// use std::task::{Context, Poll, Waker};
+    ///
+    /// struct Fetch(u32);
+    ///
+    /// let fetched = Arc::new(AtomicU32::new(0));
+    /// let mut map: HandlerMap<'static> = HandlerMap::new();
+    /// {
+    ///     let fetched = fetched.clone();
+    ///     map.insert_async(move |msg: Fetch| {
+    ///         let fetched = fetched.clone();
+    ///         async move { fetched.fetch_add(msg.0, Ordering::SeqCst); }
+    ///     });
+    /// }
+    ///
+    /// let fut = map.call_async(Fetch(5)).unwrap();
+    /// assert!(map.call_async(5u32).is_none());
+    ///
+    /// // drive the future with whatever executor is at hand; here, it completes on its first poll
+    /// let mut cx = Context::from_waker(Waker::noop());
+    /// assert_eq!(pin!(fut).poll(&mut cx), Poll::Ready(()));
+    /// assert_eq!(fetched.load(Ordering::SeqCst), 5);
+    /// ```
+    pub fn insert_async<T, Fut, F>(&mut self, handler: F)
+    where
+        T: Any,
+        Fut: Future<Output = ()> + 'a,
+        F: Fn(T) -> Fut + 'a,
+    {
+        let handler = move |msg: T| -> BoxFuture<'a> { Box::pin(handler(msg)) };
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        self.insert_slot(id, Slot::new::<T>(SlotHandler::Async(ptr)));
+    }
+
+    /// Calls the async handler with the given message, returning its future if it was
+    /// registered.
+    ///
+    /// The handler's future is not polled before it's returned; it's up to the caller to drive it
+    /// to completion.
+    ///
+    /// The message goes through the same checks as it would in `call`: it's counted by
+    /// `with_metrics` and passed to the tee set with `set_tee`, and this returns `None` without
+    /// running the handler if its group was disabled with `set_group_enabled`, or if it was marked
+    /// with `mark_single_shot` and has already been run. Since the future is run by the caller, the
+    /// observer set with `set_timing_observer` is told how long the handler took to return its
+    /// future, not how long the future took to complete.
+    pub fn call_async<T: Any>(&self, msg: T) -> Option<impl Future<Output = ()> + 'a> {
+        self.start_async(msg)
+    }
+
+    /// Calls the async handler with the given message, returning a future that fails if the
+    /// handler doesn't finish before the given deadline.
+    ///
+    /// The returned future resolves to `Err(Deadline)` if it is polled at or after `deadline`
+    /// without the handler's future having completed. Since this crate doesn't depend on any
+    /// particular runtime, it doesn't set a timer for the deadline: the expiry is only noticed the
+    /// next time the future is polled. If the handler may stall without waking its task, combine
+    /// this with the timer facilities of your executor.
+    ///
+    /// The handler is looked up and run in the same way as `call_async`.
+    pub fn call_async_deadline<T: Any>(&self, msg: T, deadline: Instant)
+        -> Option<impl Future<Output = Result<(), Deadline>> + 'a>
+    {
+        self.start_async(msg).map(|fut| WithDeadline { fut, deadline })
+    }
+
+    /// Runs the async handler for the given message, if it's registered and may run, returning
+    /// its future.
+    fn start_async<T: Any>(&self, msg: T) -> Option<BoxFuture<'a>> {
+        let id = TypeId::of::<T>();
+        self.record_dispatch(id, &msg);
+
+        let slot = self.slots.get(&id)?;
+        let act = match slot.handler {
+            SlotHandler::Async(ref act) => act,
+            _ => return None,
+        };
+        if self.is_disabled(slot) || slot.admit(&msg).is_err() {
+            return None;
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("handler", msg = slot.name).entered();
+
+        let start = self.timing_observer.as_ref().map(|_| Instant::now());
+        let fut = unsafe { act.call_erased(msg) };
+        if let (Some(observer), Some(start)) = (&self.timing_observer, start) {
+            observer(id, start.elapsed());
+        }
+        Some(fut)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::time::{Duration, Instant};
+
+    use {CallOutcome, Deadline, HandlerMap};
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    /// Polls the given future in a loop until it completes.
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = Box::pin(fut);
+        loop {
+            if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+                return out;
+            }
+        }
+    }
+
+    /// Future which stays pending until the given time.
+    struct Sleep(Instant);
+
+    impl Future for Sleep {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            if Instant::now() >= self.0 {
+                Poll::Ready(())
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn async_handler() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        struct MyMessage(usize);
+
+        /// Future which adds to an accumulator when it's polled.
+        struct Add(Rc<Cell<usize>>, usize);
+
+        impl Future for Add {
+            type Output = ();
+
+            fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
+                self.0.set(self.0.get() + self.1);
+                Poll::Ready(())
+            }
+        }
+
+        let acc = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        {
+            let acc = acc.clone();
+            map.insert_async(move |msg: MyMessage| Add(acc.clone(), msg.0));
+        }
+
+        let fut = map.call_async(MyMessage(5)).unwrap();
+        assert_eq!(acc.get(), 0);
+        block_on(fut);
+        assert_eq!(acc.get(), 5);
+
+        assert!(!map.call(MyMessage(5)));
+        assert!(map.call_async(()).is_none());
+    }
+
+    #[test]
+    fn single_shot() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        struct Connect;
+
+        let runs = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        {
+            let runs = runs.clone();
+            map.insert_async(move |_: Connect| {
+                runs.set(runs.get() + 1);
+                Sleep(Instant::now())
+            });
+        }
+        assert!(map.mark_single_shot::<Connect>());
+
+        block_on(map.call_async(Connect).unwrap());
+        assert!(map.call_async(Connect).is_none());
+        assert!(map.call_async(Connect).is_none());
+        assert!(map.is_registered::<Connect>());
+        assert_eq!(runs.get(), 1);
+
+        assert!(map.reset_single_shot::<Connect>());
+        block_on(map.call_async(Connect).unwrap());
+        assert_eq!(runs.get(), 2);
+    }
+
+    #[test]
+    fn call_async_checks() {
+        use std::cell::{Cell, RefCell};
+        use std::rc::Rc;
+
+        struct Ping;
+
+        let runs = Rc::new(Cell::new(0));
+        let teed = Rc::new(Cell::new(0));
+        let timed = Rc::new(RefCell::new(Vec::new()));
+        let mut map = HandlerMap::with_metrics();
+        {
+            let runs = runs.clone();
+            map.insert_async(move |_: Ping| {
+                runs.set(runs.get() + 1);
+                Sleep(Instant::now())
+            });
+        }
+        {
+            let teed = teed.clone();
+            map.set_tee(move |_| teed.set(teed.get() + 1));
+        }
+        {
+            let timed = timed.clone();
+            map.set_timing_observer(move |id, _| timed.borrow_mut().push(id));
+        }
+
+        // `call` doesn't run async handlers, and doesn't treat their messages as unhandled
+        map.enable_dead_letter(4);
+        assert_eq!(map.dispatch(Ping), CallOutcome::NotCallable);
+        assert!(!map.call(Ping));
+        assert_eq!(map.dead_letter_count(), 0);
+        assert_eq!(runs.get(), 0);
+
+        assert!(map.mark_single_shot::<Ping>());
+        for _ in 0..3 {
+            if let Some(fut) = map.call_async(Ping) {
+                block_on(fut);
+            }
+        }
+        assert_eq!(runs.get(), 1);
+        assert_eq!(*timed.borrow(), [std::any::TypeId::of::<Ping>()]);
+
+        assert!(map.reset_single_shot::<Ping>());
+        assert!(map.call_async_deadline(Ping, Instant::now()).is_some());
+        assert!(map.call_async_deadline(Ping, Instant::now()).is_none());
+        assert_eq!(runs.get(), 2);
+
+        assert_eq!(teed.get(), 7);
+        assert_eq!(map.stats().total_calls, Some(7));
+    }
+
+    #[test]
+    fn deadline() {
+        struct Fast;
+        struct Slow;
+
+        let mut map = HandlerMap::new();
+        map.insert_async(|_: Fast| Sleep(Instant::now()));
+        map.insert_async(|_: Slow| Sleep(Instant::now() + Duration::from_secs(60)));
+
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(block_on(map.call_async_deadline(Fast, deadline).unwrap()), Ok(()));
+
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(block_on(map.call_async_deadline(Slow, deadline).unwrap()), Err(Deadline));
+    }
+}
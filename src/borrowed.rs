@@ -0,0 +1,176 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for handlers of message types which borrow data, and so can't be keyed by their own
+//! `TypeId`.
+//!
+//! `TypeId` is only available for `'static` types, so a message type like `Request<'m>` is keyed
+//! by the `TypeId` of `Request<'static>` instead, as given by its `Borrowed` implementation.
+//! Handlers for these types are stored separately from the ones registered with `insert`.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use box_fn::BoxFn;
+use {HandlerMap, HandlerStore};
+
+/// Handlers registered with `insert_borrowed`, keyed by the `TypeId` of their message type's
+/// `Borrowed::Static` type. Each receives a `*const T` for whichever lifetime its message has.
+pub(crate) type BorrowedHandlers<'a> = HashMap<TypeId, BoxFn<'a, *const ()>>;
+
+/// A message type which may borrow data, and so isn't `'static`.
+///
+/// This trait relates each lifetime of a message type to the others, so that `HandlerMap` can key
+/// the type by the `TypeId` of its `'static` form, and pass its messages to handlers which accept
+/// any lifetime. It's implemented for the type generically over its lifetime:
+///
+/// ```rust
+/// use handler_map::Borrowed;
+///
+/// struct Request<'m> {
+///     path: &'m str,
+/// }
+///
+/// unsafe impl<'m> Borrowed for Request<'m> {
+///     type Static = Request<'static>;
+///     type With<'b> = Request<'b>;
+/// }
+/// ```
+///
+/// # Safety
+///
+/// `Static` and `With<'b>` must be the implementing type itself, with its lifetime parameter
+/// replaced by `'static` and by `'b`, respectively. A type with several lifetime parameters must
+/// replace all of them with the same lifetime. `HandlerMap` relies on this to reinterpret a
+/// reference to a message as a reference to the type its handler expects: since the two can then
+/// only differ in their lifetimes, which don't affect the layout of a type, the handler reads the
+/// same data it would have read from a message of the type it was written for.
+///
+/// Two distinct message types must not share a `Static` type, since it's what the map uses to
+/// tell them apart.
+pub unsafe trait Borrowed {
+    /// The implementing type, with its lifetime replaced by `'static`.
+    type Static: Any;
+    /// The implementing type, with its lifetime replaced by `'b`.
+    type With<'b>: Borrowed<Static = Self::Static>;
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Registers a new handler into the map for a message type which may borrow data, and so
+    /// isn't `'static`.
+    ///
+    /// The handler receives its message by reference, and must accept messages with any lifetime.
+    /// This keeps the handler from holding on to the borrowed data after it returns, so the same
+    /// handler can safely receive messages which borrow from different places, each of which only
+    /// lives as long as its call to `call_borrowed`. The message type `T` is given by naming it,
+    /// with any lifetime; it's only used to find its `Borrowed::Static` type.
+    ///
+    /// Handlers for borrowed message types are kept separately from the handler registered with
+    /// `insert`, even for the same type, and are only run by `call_borrowed`.
+    ///
+    /// ```rust
+    /// use handler_map::{Borrowed, HandlerMap};
+    /// use std::cell::RefCell;
+    ///
+    /// struct Request<'m> {
+    ///     path: &'m str,
+    /// }
+    ///
+    /// unsafe impl<'m> Borrowed for Request<'m> {
+    ///     type Static = Request<'static>;
+    ///     type With<'b> = Request<'b>;
+    /// }
+    ///
+    /// let seen = RefCell::new(Vec::new());
+    /// let mut map = HandlerMap::new();
+    /// map.insert_borrowed::<Request, _>(|req| seen.borrow_mut().push(req.path.len()));
+    ///
+    /// let path = String::from("/index.html");
+    /// assert!(map.call_borrowed(&Request { path: &path }));
+    /// assert_eq!(*seen.borrow(), [11]);
+    /// ```
+    ///
+    /// A handler which tries to keep the borrowed data is rejected:
+    ///
+    /// ```rust,compile_fail
+    /// # use handler_map::{Borrowed, HandlerMap};
+    /// # use std::cell::RefCell;
+    /// # struct Request<'m> { path: &'m str }
+    /// # unsafe impl<'m> Borrowed for Request<'m> {
+    /// #     type Static = Request<'static>;
+    /// #     type With<'b> = Request<'b>;
+    /// # }
+    /// let kept = RefCell::new(Vec::new());
+    /// let mut map = HandlerMap::new();
+    /// map.insert_borrowed::<Request, _>(|req| kept.borrow_mut().push(req.path));
+    /// ```
+    pub fn insert_borrowed<T, F>(&mut self, handler: F)
+    where
+        T: Borrowed,
+        F: for<'m> Fn(&T::With<'m>) + 'a,
+    {
+        // The message is reinterpreted as `With<'static>`, which the `Borrowed` contract makes the
+        // same type as the one it was sent as, modulo lifetimes. Since the handler is generic over
+        // the lifetime, its body can't make use of the `'static` one it's instantiated with here.
+        let handler = move |msg: *const ()| handler(unsafe { &*(msg as *const T::With<'static>) });
+        let ptr = BoxFn::from(Box::new(handler)).erase();
+        self.borrowed.insert(TypeId::of::<T::Static>(), ptr);
+    }
+
+    /// Un-registers the handler for the given borrowed message type, returning whether one was
+    /// registered.
+    pub fn remove_borrowed<T: Borrowed>(&mut self) -> bool {
+        self.borrowed.remove(&TypeId::of::<T::Static>()).is_some()
+    }
+
+    /// Calls the handler registered with `insert_borrowed` with a reference to the given message,
+    /// returning whether the handler was registered.
+    ///
+    /// This never falls back to the handler registered with `insert` for the same type, or to the
+    /// fallback handler set with `set_fallback`.
+    pub fn call_borrowed<T: Borrowed>(&self, msg: &T) -> bool {
+        if let Some(act) = self.borrowed.get(&TypeId::of::<T::Static>()) {
+            act.call(msg as *const T as *const ());
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use {Borrowed, HandlerMap};
+
+    struct Line<'m> {
+        words: Vec<&'m str>,
+    }
+
+    unsafe impl<'m> Borrowed for Line<'m> {
+        type Static = Line<'static>;
+        type With<'b> = Line<'b>;
+    }
+
+    #[test]
+    fn call_borrowed() {
+        let counts = RefCell::new(Vec::new());
+        let mut map = HandlerMap::new();
+        map.insert_borrowed::<Line, _>(|line| counts.borrow_mut().push(line.words.len()));
+        map.insert(|_: Line<'static>| panic!("the owned handler shouldn't run"));
+
+        for text in ["one two", "three four five"].iter().map(|s| s.to_string()) {
+            let line = Line { words: text.split(' ').collect() };
+            assert!(map.call_borrowed(&line));
+        }
+        assert_eq!(*counts.borrow(), [2, 3]);
+
+        assert!(map.remove_borrowed::<Line>());
+        assert!(!map.call_borrowed(&Line { words: Vec::new() }));
+        assert!(map.is_registered::<Line<'static>>());
+    }
+}
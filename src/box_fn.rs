@@ -3,14 +3,19 @@
 // not distributed with this file, You can obtain one at
 // http://mozilla.org/MPL/2.0/.
 
-//! Internal implementation of a boxed `Fn(T)` function pointer/closure that can have its argument
-//! type (and its own type) erased.
+//! Implementation of a boxed `Fn(T)` function pointer/closure that can have its argument type
+//! (and its own type) erased.
 //!
-//! These types aren't really meant to be used outside this crate, as they rely on assumptions
-//! based on the uses of `TypeId` in `HandlerMap`.
+//! This is how `HandlerMap` stores its handlers. It's only public when the `unsafe-internals`
+//! feature is enabled, for building other kinds of dispatch on top of the same erasure. Erasing a
+//! `BoxFn`'s types is safe, but calling it afterward with `call_erased` or `call_erased_ret` is
+//! not: nothing checks that it's called with the types it was created with, so anything built on
+//! these needs to track that itself, the way `HandlerMap` does with the `TypeId` of each message
+//! type.
 
 use std;
 use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
 
 /// Opaque handle type that represents an erased type parameter.
 ///
@@ -18,98 +23,217 @@ use std::marker::PhantomData;
 /// until then we can use this.
 ///
 /// Care should be taken that we don't use a concrete instance of this. It should only be used
-/// through a reference, so we can maintain something else's lifetime.
-pub(crate) struct Opaque(());
+/// through a pointer, to stand in for a value of some other type. Outside this crate, it can't be
+/// created at all.
+pub struct Opaque(());
 
 /// Collection of functions representing the operations we want to use on a boxed closure, namely,
-/// calling it and dropping it.
-struct BoxFnVtable<A: ?Sized, F: ?Sized = Opaque> {
-    call: fn(&F, A),
+/// calling it and dropping it, and cloning it if it was created with `BoxFn::cloneable`.
+///
+/// Erasing a `BoxFn`'s types reinterprets its vtable as one with different type parameters, so
+/// the layout is fixed with `repr(C)` to make sure the fields line up between them. The function
+/// pointers themselves are only ever called with the argument and return types they were created
+/// with; see `BoxFn::call_erased`.
+///
+/// The closure is passed to these functions as a raw pointer, rather than a reference. A reference
+/// to `Opaque` would only be allowed to reach the zero bytes of an `Opaque`, not the closure
+/// behind it, and raw pointers to any sized type are passed the same way, so the functions can be
+/// called through a vtable whose closure type has been erased.
+#[repr(C)]
+struct BoxFnVtable<A: ?Sized, R = (), F: ?Sized = Opaque> {
+    call: unsafe fn(*const F, A) -> R,
     drop_box: unsafe fn(*mut F),
+    clone_box: Option<unsafe fn(*const F) -> *mut F>,
+}
+
+/// Calls the closure behind the given pointer, for a `BoxFnVtable`.
+unsafe fn call_box<F: Fn(A) -> R, A, R>(f: *const F, arg: A) -> R {
+    (*f)(arg)
+}
+
+/// Drops the box behind the given pointer, for a `BoxFnVtable`.
+unsafe fn drop_box<F>(f: *mut F) {
+    drop(Box::from_raw(f));
+}
+
+/// Clones the closure behind the given pointer into a new box, for a `BoxFnVtable`.
+unsafe fn clone_box<F: Clone>(f: *const F) -> *mut F {
+    Box::into_raw(Box::new((*f).clone()))
 }
 
 /// Custom handle to a boxed closure, allowing for preserving or erasing the closure or argument
 /// types.
 ///
-/// To create an instance of `BoxFn`, convert an instance of `Box<F: Fn(A)>` using
+/// To create an instance of `BoxFn`, convert an instance of `Box<F: Fn(A) -> R>` using
 /// `From`/`Into`.
-pub(crate) struct BoxFn<'a, A: 'a + ?Sized, F: 'a + ?Sized = Opaque> {
-    data: &'a mut F,
-    vtable: &'a BoxFnVtable<A, F>,
+///
+/// Since the closure's type can be erased, a `BoxFn` is never `Send` or `Sync`, even if the
+/// closure it was created from is: once erased, there's no way to tell whether it was.
+pub struct BoxFn<'a, A: 'a + ?Sized, R: 'a = (), F: 'a + ?Sized = Opaque> {
+    /// The closure, as given by `Box::into_raw`. This is kept as a raw pointer rather than a
+    /// reference, so that it can still reach the whole closure after its type is erased.
+    data: *mut F,
+    vtable: &'a BoxFnVtable<A, R, F>,
+    /// Whether to catch a panic from dropping the closure, as set by `set_catch_drop_panics`.
+    catch_drop_panics: bool,
     _invariant: PhantomData<&'a mut &'a ()>,
+    _not_send: PhantomData<*const ()>,
 }
 
-impl<'a, A: ?Sized, F: ?Sized> Drop for BoxFn<'a, A, F> {
+impl<'a, A: ?Sized, R, F: ?Sized> Drop for BoxFn<'a, A, R, F> {
+    /// Drops the closure, along with everything it captured.
+    ///
+    /// If `set_catch_drop_panics` was used to enable it, a panic from dropping something the
+    /// closure captured is caught here, after the panic hook has reported it. Otherwise, the panic
+    /// is passed on as usual.
     fn drop(&mut self) {
-        unsafe {
-            (self.vtable.drop_box)(self.data);
+        let drop_box = self.vtable.drop_box;
+        let data = self.data;
+        if self.catch_drop_panics {
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| unsafe { drop_box(data) }));
+        } else {
+            unsafe { drop_box(data) }
         }
     }
 }
 
-impl<'a, A, F: Fn(A) + 'a> From<Box<F>> for BoxFn<'a, A, F> {
+impl<'a, A, R, F: Fn(A) -> R + 'a> From<Box<F>> for BoxFn<'a, A, R, F> {
     fn from(f: Box<F>) -> Self {
-        unsafe fn drop_box<F>(f: *mut F) {
-            drop(Box::from_raw(f));
-        }
-        fn call<F: Fn(A), A>(f: &F, arg: A) {
-            f(arg)
+        BoxFn {
+            data: Box::into_raw(f),
+            vtable: &BoxFnVtable {
+                call: call_box,
+                drop_box,
+                clone_box: None,
+            },
+            catch_drop_panics: false,
+            _invariant: PhantomData,
+            _not_send: PhantomData,
         }
+    }
+}
+
+impl<'a, A, R, F: Fn(A) -> R + Clone + 'a> BoxFn<'a, A, R, F> {
+    /// Converts a boxed closure into a `BoxFn` which can be cloned with `try_clone`, even after its
+    /// types are erased.
+    pub fn cloneable(f: Box<F>) -> Self {
         BoxFn {
-            data: unsafe { &mut *Box::into_raw(f) },
+            data: Box::into_raw(f),
             vtable: &BoxFnVtable {
-                call,
+                call: call_box,
                 drop_box,
+                clone_box: Some(clone_box),
             },
+            catch_drop_panics: false,
             _invariant: PhantomData,
+            _not_send: PhantomData,
         }
     }
 }
 
-impl<'a, A, F> BoxFn<'a, A, F> {
-    /// Erases the closure type, converting `BoxFn<'a, T, F>` to `BoxFn<'a, T, Opaque>`.
-    pub fn erase(self) -> BoxFn<'a, A> {
+impl<'a, A, R, F> BoxFn<'a, A, R, F> {
+    /// Erases the closure type, converting `BoxFn<'a, T, R, F>` to `BoxFn<'a, T, R, Opaque>`.
+    ///
+    /// The erased closure can still be called safely with `call`, since its argument and return
+    /// types are still known.
+    pub fn erase(self) -> BoxFn<'a, A, R> {
         unsafe {
-            let data = &mut *(self.data as *mut _ as *mut Opaque);
-            let vtable = &*(self.vtable as *const _ as *const BoxFnVtable<A>);
+            let data = self.data as *mut Opaque;
+            let vtable = &*(self.vtable as *const _ as *const BoxFnVtable<A, R>);
+            let catch_drop_panics = self.catch_drop_panics;
             std::mem::forget(self);
             BoxFn {
                 data,
                 vtable,
+                catch_drop_panics,
                 _invariant: PhantomData,
+                _not_send: PhantomData,
             }
         }
     }
 }
 
-impl<'a, A> BoxFn<'a, A> {
-    /// Erases the argument type, converting `BoxFn<'a, A, Opaque>` to `BoxFn<'a, Opaque, Opaque>`.
-    pub fn erase_arg(self) -> BoxFn<'a, Opaque> {
+impl<'a, A, R> BoxFn<'a, A, R> {
+    /// Erases the argument type, converting `BoxFn<'a, A, R, Opaque>` to
+    /// `BoxFn<'a, Opaque, R, Opaque>`.
+    ///
+    /// This is safe by itself, but afterward, the closure can only be called with `call_erased`,
+    /// which relies on the caller to remember the argument type.
+    pub fn erase_arg(self) -> BoxFn<'a, Opaque, R> {
+        unsafe {
+            let data = self.data;
+            let vtable = &*(self.vtable as *const _ as *const BoxFnVtable<Opaque, R>);
+            let catch_drop_panics = self.catch_drop_panics;
+            std::mem::forget(self);
+            BoxFn {
+                data,
+                vtable,
+                catch_drop_panics,
+                _invariant: PhantomData,
+                _not_send: PhantomData,
+            }
+        }
+    }
+}
+
+impl<'a, R> BoxFn<'a, Opaque, R> {
+    /// Erases the return type, converting `BoxFn<'a, Opaque, R, Opaque>` to
+    /// `BoxFn<'a, Opaque, Opaque, Opaque>`.
+    ///
+    /// This is safe by itself, but afterward, the closure can only be called with
+    /// `call_erased_ret`, which relies on the caller to remember the argument and return types.
+    pub fn erase_ret(self) -> BoxFn<'a, Opaque, Opaque> {
         unsafe {
-            let data = &mut *(self.data as *mut _);
-            let vtable = &*(self.vtable as *const _ as *const BoxFnVtable<Opaque>);
+            let data = self.data;
+            let vtable = &*(self.vtable as *const _ as *const BoxFnVtable<Opaque, Opaque>);
+            let catch_drop_panics = self.catch_drop_panics;
             std::mem::forget(self);
             BoxFn {
                 data,
                 vtable,
+                catch_drop_panics,
                 _invariant: PhantomData,
+                _not_send: PhantomData,
             }
         }
     }
 }
 
-impl<'a, A, F: ?Sized> BoxFn<'a, A, F> {
+impl<'a, A, R, F: ?Sized> BoxFn<'a, A, R, F> {
+    /// Clones the closure, if this `BoxFn` was created with `cloneable`.
+    pub fn try_clone(&self) -> Option<BoxFn<'a, A, R, F>> {
+        self.vtable.clone_box.map(|clone_box| BoxFn {
+            data: unsafe { clone_box(self.data) },
+            vtable: self.vtable,
+            catch_drop_panics: self.catch_drop_panics,
+            _invariant: PhantomData,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Sets whether a panic from dropping the closure, because something it captured panics in
+    /// its destructor, is caught when this `BoxFn` is dropped.
+    ///
+    /// When enabled, the panic is reported by the panic hook and then caught, so a collection of
+    /// `BoxFn`s carries on dropping the others, and the process doesn't abort when the `BoxFn` is
+    /// dropped while unwinding from another panic. This is disabled by default, and is kept when
+    /// the `BoxFn`'s types are erased or it's cloned with `try_clone`.
+    pub fn set_catch_drop_panics(&mut self, enabled: bool) {
+        self.catch_drop_panics = enabled;
+    }
+
     /// Calls the closure with the given argument.
     ///
-    /// This is the equivalent of calling a `Box<Fn(T)>`, but since the `Fn` trait is unstable to
-    /// implement, we have this function.
-    #[allow(dead_code)] // not used in this crate, but added for completeness
-    pub(crate) fn call(&self, arg: A) {
-        (self.vtable.call)(self.data, arg);
+    /// This is the equivalent of calling a `Box<Fn(T) -> R>`, but since the `Fn` trait is
+    /// unstable to implement, we have this function.
+    pub fn call(&self, arg: A) -> R {
+        // `data` points to the closure the vtable was created for until this is dropped, and the
+        // argument and return types haven't been erased, so they're the ones it was created with
+        unsafe { (self.vtable.call)(self.data, arg) }
     }
 }
 
-impl<'a> BoxFn<'a, Opaque> {
+impl<'a, R> BoxFn<'a, Opaque, R> {
     /// Calls an erased closure with the given argument.
     ///
     /// # Safety
@@ -117,10 +241,160 @@ impl<'a> BoxFn<'a, Opaque> {
     /// Callers must ensure that the argument type given to this function is actually the type that
     /// was used to originally create this `BoxFn` before its types were erased. Failure to uphold
     /// this constraint can cause the function to be called with invalid data.
-    pub(crate) unsafe fn call_erased<A: 'a>(&self, arg: A) {
+    ///
+    /// The argument is passed by value through a function pointer with the original argument
+    /// type, not through `Opaque`, so the call uses the same ABI as the function that was
+    /// erased, whatever the size or layout of the argument.
+    pub unsafe fn call_erased<A: 'a>(&self, arg: A) -> R {
         std::mem::transmute::<
-            fn(&Opaque, Opaque),
-            fn(&Opaque, A),
-        >(self.vtable.call)(self.data, arg);
+            unsafe fn(*const Opaque, Opaque) -> R,
+            unsafe fn(*const Opaque, A) -> R,
+        >(self.vtable.call)(self.data, arg)
+    }
+}
+
+impl<'a> BoxFn<'a, Opaque, Opaque> {
+    /// Calls a closure with an erased argument and return type with the given argument.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that the argument and return types given to this function are actually
+    /// the types that were used to originally create this `BoxFn` before its types were erased.
+    /// Failure to uphold this constraint can cause the function to be called with invalid data, or
+    /// to return invalid data.
+    pub unsafe fn call_erased_ret<A: 'a, R: 'a>(&self, arg: A) -> R {
+        std::mem::transmute::<
+            unsafe fn(*const Opaque, Opaque) -> Opaque,
+            unsafe fn(*const Opaque, A) -> R,
+        >(self.vtable.call)(self.data, arg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use HandlerMap;
+
+    // these tests go through the erased calls in `HandlerMap`, and are meant to be run under Miri
+    // as well as normally, to check the pointer casts in `BoxFn`
+
+    #[test]
+    fn zero_sized() {
+        struct Marker;
+
+        let calls = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert(|_: Marker| calls.set(calls.get() + 1));
+        map.insert(|_: ()| calls.set(calls.get() + 10));
+        map.insert_signal::<Marker, _>(|| calls.set(calls.get() + 100));
+
+        assert!(map.call(Marker));
+        assert!(map.call(()));
+        assert!(map.signal::<Marker>());
+        assert_eq!(calls.get(), 111);
+    }
+
+    #[test]
+    fn multi_word() {
+        let sum = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert(|msg: [u64; 8]| sum.set(sum.get() + msg.iter().sum::<u64>()));
+        map.insert(|msg: (String, Vec<u64>)| sum.set(sum.get() + msg.0.len() as u64 + msg.1[0]));
+
+        assert!(map.call([1u64; 8]));
+        assert!(map.call((String::from("four"), vec![100u64])));
+        assert_eq!(sum.get(), 112);
+    }
+
+    #[test]
+    fn pointer_size_boundary() {
+        use std::mem::size_of;
+
+        const WORD: usize = size_of::<usize>();
+
+        // messages right at, and just past, the size of the pointer `Opaque` is passed behind
+        let sum = Cell::new(0usize);
+        let mut map = HandlerMap::new();
+        map.insert(|msg: [u8; WORD]| sum.set(sum.get() + msg.iter().filter(|&&b| b == 1).count()));
+        map.insert(|msg: [u8; WORD + 1]| sum.set(sum.get() + msg[WORD] as usize));
+        map.insert(|msg: (usize, u8)| sum.set(sum.get() + msg.0 + msg.1 as usize));
+
+        assert!(map.call([1u8; WORD]));
+        let mut wide = [0u8; WORD + 1];
+        wide[WORD] = 100;
+        assert!(map.call(wide));
+        assert!(map.call((1000usize, 10u8)));
+        assert_eq!(sum.get(), WORD + 100 + 1010);
+    }
+
+    #[test]
+    fn drop_messages() {
+        struct Counted(Rc<Cell<u32>>);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let drops = Rc::new(Cell::new(0));
+        let kept = Cell::new(None);
+        let mut map = HandlerMap::new();
+        assert!(!map.call(Counted(drops.clone())));
+        assert_eq!(drops.get(), 1);
+
+        map.insert(|msg: Counted| assert_eq!(msg.0.get(), 1));
+        assert!(map.call(Counted(drops.clone())));
+        assert_eq!(drops.get(), 2);
+
+        // a handler that keeps the message alive past the call
+        map.insert(|msg: Counted| kept.set(Some(msg)));
+        assert!(map.call(Counted(drops.clone())));
+        assert_eq!(drops.get(), 2);
+        drop(kept.take());
+        assert_eq!(drops.get(), 3);
+    }
+
+    #[test]
+    fn insert_remove_cycles() {
+        let state = Rc::new(Cell::new(0u64));
+        let mut map = HandlerMap::new();
+        for i in 0..100u64 {
+            let state = state.clone();
+            map.insert(move |msg: u64| state.set(state.get() + msg + i));
+            assert!(map.call(1u64));
+            if i % 3 == 0 {
+                assert!(map.remove::<u64>());
+                assert!(!map.call(1u64));
+            }
+        }
+        assert_eq!(state.get(), 100 + (0..100).sum::<u64>());
+        drop(map);
+        assert_eq!(Rc::strong_count(&state), 1);
+    }
+
+    #[test]
+    fn drop_on_clear() {
+        let captured = Rc::new(());
+        let mut map = HandlerMap::new();
+        {
+            let captured = captured.clone();
+            map.insert(move |_: u8| drop(captured.clone()));
+        }
+        {
+            let captured = captured.clone();
+            map.insert(move |_: [u8; 3]| drop(captured.clone()));
+        }
+        {
+            let captured = captured.clone();
+            map.insert(move |_: ()| drop(captured.clone()));
+        }
+        assert_eq!(Rc::strong_count(&captured), 4);
+        assert!(map.call(0u8));
+
+        map.clear();
+        assert_eq!(Rc::strong_count(&captured), 1);
+        assert!(!map.call(0u8));
     }
 }
@@ -0,0 +1,116 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! A builder to construct a `HandlerMap` in one expression.
+
+use std::any::{Any, TypeId};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use {FrozenHandlerMap, Handler, HandlerMap, HandlerStore, Registrar, Slot};
+
+/// Builder for a `HandlerMap`, created by `HandlerMap::builder`.
+///
+/// Each method registers handlers the same way as the `HandlerMap` method of the same purpose,
+/// and returns the builder so that calls can be chained. This lets a map be built and frozen in
+/// one expression, without a mutable binding:
+///
+/// ```rust
+/// use handler_map::{HandlerMap, RegisterFn};
+///
+/// struct Connect;
+/// struct Disconnect;
+///
+/// let map = HandlerMap::builder()
+///     .handler(|_: Connect| println!("connected"))
+///     .handler(|_: Disconnect| println!("disconnected"))
+///     .register(RegisterFn(|map: &mut HandlerMap| map.insert(|_: u32| {})))
+///     .build_frozen();
+///
+/// assert!(map.call(Connect));
+/// assert!(map.call(5u32));
+/// ```
+pub struct HandlerMapBuilder<'a, S = RandomState, H = HashMap<TypeId, Slot<'a>, S>> {
+    map: HandlerMap<'a, S, H>,
+}
+
+impl<'a> HandlerMap<'a> {
+    /// Creates a builder for a new map with no handlers.
+    pub fn builder() -> HandlerMapBuilder<'a> {
+        HandlerMapBuilder {
+            map: HandlerMap::new(),
+        }
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMapBuilder<'a, S, H> {
+    /// Registers a new handler into the map, like `HandlerMap::insert`.
+    pub fn handler<T: Any, F: Handler<T> + 'a>(mut self, handler: F) -> Self {
+        self.map.insert(handler);
+        self
+    }
+
+    /// Registers the handlers from the given `Registrar`, like `HandlerMap::register`.
+    pub fn register<M, R: Registrar<'a, M, S, H>>(mut self, registrar: R) -> Self {
+        self.map.register(registrar);
+        self
+    }
+
+    /// Returns the map with the handlers registered so far.
+    pub fn build(self) -> HandlerMap<'a, S, H> {
+        self.map
+    }
+
+    /// Returns the map with the handlers registered so far, converted into a `FrozenHandlerMap`
+    /// as if by `HandlerMap::freeze`.
+    ///
+    /// Like the map itself, the frozen map is neither `Send` nor `Sync`; use
+    /// `SyncHandlerMap::freeze` to build a frozen map which can be shared between threads.
+    pub fn build_frozen(self) -> FrozenHandlerMap<HandlerMap<'a, S, H>> {
+        self.map.freeze()
+    }
+}
+
+impl<'a, S, H> From<HandlerMap<'a, S, H>> for HandlerMapBuilder<'a, S, H>
+where
+    S: BuildHasher,
+    H: HandlerStore<'a>,
+{
+    /// Creates a builder which adds to the handlers already registered in the given map.
+    fn from(map: HandlerMap<'a, S, H>) -> HandlerMapBuilder<'a, S, H> {
+        HandlerMapBuilder { map }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use HandlerMap;
+
+    #[test]
+    fn builder() {
+        struct Add(u32);
+        struct Reset;
+
+        let total = Cell::new(0);
+        let map = HandlerMap::builder()
+            .handler(|msg: Add| total.set(total.get() + msg.0))
+            .handler(|_: Reset| panic!("should have been replaced"))
+            .register((|_: Reset| total.set(0), |_: u8| {}))
+            .build();
+
+        assert_eq!(map.len(), 3);
+        assert!(map.call(Add(4)));
+        assert_eq!(total.get(), 4);
+        assert!(map.call(Reset));
+        assert_eq!(total.get(), 0);
+
+        let frozen = HandlerMap::builder().handler(|_: Reset| {}).build_frozen();
+        assert!(frozen.call(Reset));
+        assert!(!frozen.call(Add(1)));
+    }
+}
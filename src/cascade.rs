@@ -0,0 +1,199 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for "cascade" handlers, which can emit follow-up messages to be dispatched after they
+//! return.
+
+use std::any::{type_name, Any, TypeId};
+use std::error::Error;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::mem;
+
+use box_fn::BoxFn;
+use {CallOutcome, HandlerMap, HandlerStore, Slot, SlotHandler};
+
+/// Function which dispatches a boxed follow-up message. These are monomorphized for the original
+/// message type when it's emitted.
+type Redispatch<'a, S, H> =
+    fn(&HandlerMap<'a, S, H>, Box<dyn Any>, &mut Cascade<'a, S, H>) -> bool;
+
+/// A message emitted by a cascade handler, along with the name of its type and the function to
+/// dispatch it.
+type Emitted<'a, S, H> = (Box<dyn Any>, &'static str, Redispatch<'a, S, H>);
+
+/// Collector for the follow-up messages emitted by a cascade handler.
+///
+/// See `HandlerMap::insert_cascade` for details.
+pub struct Cascade<'a, S = RandomState, H = HashMap<TypeId, Slot<'a>, S>> {
+    /// The emitted messages, in the order they were emitted.
+    pending: Vec<Emitted<'a, S, H>>,
+}
+
+impl<'a, S: BuildHasher + 'a, H: HandlerStore<'a> + 'a> Cascade<'a, S, H> {
+    /// Queues a follow-up message, to be dispatched after the current handler returns.
+    pub fn emit<T: Any>(&mut self, msg: T) {
+        fn redispatch<'a, T: Any, S: BuildHasher + 'a, H: HandlerStore<'a> + 'a>(
+            map: &HandlerMap<'a, S, H>,
+            msg: Box<dyn Any>,
+            cascade: &mut Cascade<'a, S, H>,
+        ) -> bool {
+            match msg.downcast::<T>() {
+                Ok(msg) => map.cascade_one(*msg, cascade),
+                Err(_) => false,
+            }
+        }
+
+        self.pending.push((Box::new(msg), type_name::<T>(), redispatch::<T, S, H>));
+    }
+}
+
+/// Error returned by `HandlerMap::call_cascade` when a cascade of follow-up messages went deeper
+/// than the allowed limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthExceeded {
+    /// The depth of the follow-up message that exceeded the limit. The original message has depth
+    /// zero, messages emitted by its handler have depth one, and so on.
+    pub depth: usize,
+}
+
+impl fmt::Display for DepthExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "message cascade exceeded the depth limit at depth {}", self.depth)
+    }
+}
+
+impl Error for DepthExceeded {}
+
+impl<'a, S: BuildHasher + 'a, H: HandlerStore<'a> + 'a> HandlerMap<'a, S, H> {
+    /// Registers a new "cascade" handler into the map, which can emit follow-up messages through
+    /// the given `Cascade`.
+    ///
+    /// Cascade handlers are only run by `call_cascade`, which dispatches their follow-up messages
+    /// after they return; `call` and `dispatch` treat them as unregistered. A cascade handler
+    /// takes the place of any other handler registered for the same type, and vice-versa.
+    pub fn insert_cascade<T, F>(&mut self, handler: F)
+    where
+        T: Any,
+        F: Fn(T, &mut Cascade<'a, S, H>) + 'a,
+    {
+        let handler = move |(msg, cascade): (T, *mut Cascade<'a, S, H>)| {
+            handler(msg, unsafe { &mut *cascade })
+        };
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        self.insert_slot(id, Slot::new::<T>(SlotHandler::Cascade(ptr)));
+    }
+
+    /// Calls the handler for the given message, then dispatches any follow-up messages emitted by
+    /// cascade handlers, returning how many messages in total were handled.
+    ///
+    /// Follow-up messages are dispatched breadth-first, in the order they were emitted. They can
+    /// be received by any kind of handler that `call` would run, as well as by other cascade
+    /// handlers, which can emit further messages in turn. The original message has depth zero,
+    /// and each follow-up message has a depth one greater than the message whose handler emitted
+    /// it. If any follow-up message would have a depth greater than `max_depth`, dispatch stops
+    /// and `DepthExceeded` is returned, without dispatching the remaining messages. This makes it
+    /// safe to use with message types whose handlers can emit each other in a cycle.
+    pub fn call_cascade<T: Any>(&self, msg: T, max_depth: usize) -> Result<usize, DepthExceeded> {
+        let mut cascade = Cascade { pending: Vec::new() };
+        let mut handled = self.cascade_one(msg, &mut cascade) as usize;
+        let mut depth = 0;
+
+        while !cascade.pending.is_empty() {
+            depth += 1;
+            if depth > max_depth {
+                return Err(DepthExceeded { depth });
+            }
+
+            for (msg, _, redispatch) in mem::take(&mut cascade.pending) {
+                handled += redispatch(self, msg, &mut cascade) as usize;
+            }
+        }
+
+        Ok(handled)
+    }
+
+    /// Dispatches a message as part of a cascade, running it through a cascade handler if that's
+    /// what's registered for it.
+    fn cascade_one<T: Any>(&self, msg: T, cascade: &mut Cascade<'a, S, H>) -> bool {
+        let id = TypeId::of::<T>();
+        let slot = self.slots.get(&id);
+        if let Some(&Slot { name, handler: SlotHandler::Cascade(ref act), .. }) = slot {
+            let start = cascade.pending.len();
+            unsafe { act.call_erased((msg, cascade as *mut Cascade<'a, S, H>)); }
+
+            let mut edges = self.cascade_edges.borrow_mut();
+            for &(ref emitted, emitted_name, _) in &cascade.pending[start..] {
+                edges.insert((id, (**emitted).type_id()), (name, emitted_name));
+            }
+            true
+        } else {
+            match self.dispatch(msg) {
+                CallOutcome::Handled | CallOutcome::Fallback | CallOutcome::AlreadyConsumed |
+                    CallOutcome::Declined => true,
+                CallOutcome::Unregistered | CallOutcome::ValidationFailed(_) |
+                    CallOutcome::Disabled | CallOutcome::NotCallable => false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use {Cascade, DepthExceeded, HandlerMap};
+
+    #[test]
+    fn cascade() {
+        struct Start;
+        struct Middle;
+        struct End;
+
+        let ended = Rc::new(Cell::new(false));
+        let mut map = HandlerMap::new();
+        map.insert_cascade(|_: Start, c: &mut Cascade| {
+            c.emit(Middle);
+            c.emit(Middle);
+        });
+        map.insert_cascade(|_: Middle, c: &mut Cascade| c.emit(End));
+        {
+            let ended = ended.clone();
+            map.insert(move |_: End| ended.set(true));
+        }
+
+        assert_eq!(map.call_cascade(Start, 2), Ok(5));
+        assert!(ended.get());
+        assert!(!map.call(Start));
+        assert!(!map.mark_single_shot::<Start>());
+        assert!(map.mark_single_shot::<End>());
+    }
+
+    #[test]
+    fn cycle() {
+        struct Ping;
+        struct Pong;
+
+        let count = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        {
+            let count = count.clone();
+            map.insert_cascade(move |_: Ping, c: &mut Cascade| {
+                count.set(count.get() + 1);
+                c.emit(Pong);
+            });
+        }
+        map.insert_cascade(|_: Pong, c: &mut Cascade| c.emit(Ping));
+
+        assert_eq!(map.call_cascade(Ping, 5), Err(DepthExceeded { depth: 6 }));
+        // pings at depths 0, 2, and 4 were handled before the limit was reached
+        assert_eq!(count.get(), 3);
+    }
+}
@@ -0,0 +1,134 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for cloning a map whose handlers were all registered as cloneable.
+
+use std::any::{Any, TypeId};
+use std::hash::BuildHasher;
+
+use box_fn::BoxFn;
+use metrics::CallMetrics;
+use {Handler, HandlerMap, HandlerStore, Slot, SlotHandler};
+
+impl<'a> Slot<'a> {
+    /// Clones this slot, if its handler was registered with `insert_cloneable`.
+    fn try_clone(&self) -> Option<Slot<'a>> {
+        let handler = match self.handler {
+            SlotHandler::Value(ref act) => SlotHandler::Value(act.try_clone()?),
+            _ => return None,
+        };
+        if self.validator.is_some() || self.dead.is_some() || self.teardown.is_some() {
+            return None;
+        }
+
+        Some(Slot {
+            id: self.id,
+            name: self.name,
+            handler,
+            single_shot: self.single_shot.clone(),
+            validator: None,
+            fn_addr: self.fn_addr,
+            group: self.group,
+            dead: None,
+            teardown: None,
+            make_default: self.make_default,
+            run_boxed: self.run_boxed,
+            run_dyn: self.run_dyn,
+        })
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Registers a new handler into the map which can be cloned along with the map by
+    /// `try_clone`.
+    ///
+    /// Apart from that, this is the same as `insert`.
+    pub fn insert_cloneable<T: Any, F: Handler<T> + Clone + 'a>(&mut self, handler: F) {
+        let ptr = BoxFn::cloneable(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        self.insert_slot(id, Slot::new::<T>(SlotHandler::Value(ptr)));
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a> + Default> HandlerMap<'a, S, H> {
+    /// Creates a copy of this map with clones of all its handlers, if all of them were registered
+    /// with `insert_cloneable`.
+    ///
+    /// If any handler in the map was registered some other way, including with `insert_multi`,
+    /// `insert_signal`, `insert_borrowed`, or `set_fallback`, or with a validator from
+    /// `insert_validated`, this returns `None`. A conversion registered with
+    /// `register_conversion`, or an observer set with `set_error_observer`, `set_tee`,
+    /// `on_replace`, or `set_timing_observer`, also prevents the map from being cloned.
+    /// Settings like the error policy, the handler limit, and the disabled groups are copied, as
+    /// is the state of single-shot handlers. The copy shares the parent set with `with_parent`.
+    /// If the map has a dead-letter queue or was created with `with_metrics`, the copy has an
+    /// empty queue with the same capacity, and starts counting calls from zero.
+    /// Messages waiting to be dispatched by `flush`, and changes waiting to be made by
+    /// `apply_deferred`, aren't copied.
+    pub fn try_clone(&self) -> Option<HandlerMap<'a, S, H>> {
+        if !self.listeners.is_empty() || !self.result_listeners.is_empty() ||
+            !self.stoppable_listeners.is_empty() || !self.ref_listeners.is_empty() ||
+            !self.responders.is_empty() ||
+            !self.named.is_empty() || !self.signals.is_empty() || !self.borrowed.is_empty() ||
+            !self.conversions.is_empty() ||
+            self.fallback.is_some() || self.error_observer.is_some() ||
+            self.replace_observer.is_some() || self.timing_observer.is_some() || self.tee.is_some()
+        {
+            return None;
+        }
+
+        let mut slots = H::default();
+        slots.reserve(self.slots.len());
+        for (id, slot) in self.slots.iter() {
+            slots.insert(*id, slot.try_clone()?);
+        }
+
+        let mut map = HandlerMap::from_store(slots);
+        map.error_policy = self.error_policy;
+        map.cascade_edges = self.cascade_edges.clone();
+        map.max_handlers = self.max_handlers;
+        map.disabled_groups = self.disabled_groups.clone();
+        map.catch_drop_panics = self.catch_drop_panics;
+        map.parent = self.parent.clone();
+        map.metrics = self.metrics.as_ref().map(|_| CallMetrics::default());
+        if let Some(ref dead) = self.dead_letters {
+            map.enable_dead_letter(dead.borrow().capacity());
+        }
+        Some(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use HandlerMap;
+
+    #[test]
+    fn try_clone() {
+        struct Tick;
+
+        let ticks = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        {
+            let ticks = ticks.clone();
+            map.insert_cloneable(move |_: Tick| ticks.set(ticks.get() + 1));
+        }
+        map.insert_cloneable(|_: u32| {});
+
+        let copy = map.try_clone().unwrap();
+        assert!(copy.call(Tick));
+        assert!(map.call(Tick));
+        assert_eq!(ticks.get(), 2);
+        assert_eq!(Rc::strong_count(&ticks), 3);
+        drop(copy);
+        assert_eq!(Rc::strong_count(&ticks), 2);
+
+        map.insert(|_: u8| {});
+        assert!(map.try_clone().is_none());
+    }
+}
@@ -0,0 +1,77 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Functions to compose several handlers into one before registering it.
+
+/// Combines two handlers into one, which passes each message to `first` and then to `second`.
+///
+/// Since both handlers receive the message by value, `first` receives a clone of it, and `second`
+/// receives the original.
+///
+/// ```rust
+/// use handler_map::{combine, HandlerMap};
+/// use std::cell::Cell;
+/// use std::rc::Rc;
+///
+/// #[derive(Clone)]
+/// struct MyMessage(u32);
+///
+/// let log = Rc::new(Cell::new(0));
+/// let total = Rc::new(Cell::new(0));
+///
+/// let mut map = HandlerMap::new();
+/// {
+///     let log = log.clone();
+///     let total = total.clone();
+///     map.insert(combine(
+///         move |_: MyMessage| log.set(log.get() + 1),
+///         move |msg: MyMessage| total.set(total.get() + msg.0),
+///     ));
+/// }
+///
+/// assert!(map.call(MyMessage(5)));
+/// assert!(map.call(MyMessage(10)));
+///
+/// assert_eq!(log.get(), 2);
+/// assert_eq!(total.get(), 15);
+/// ```
+pub fn combine<T, F, G>(first: F, second: G) -> impl Fn(T)
+where
+    T: Clone,
+    F: Fn(T),
+    G: Fn(T),
+{
+    move |msg: T| {
+        first(msg.clone());
+        second(msg);
+    }
+}
+
+/// Combines two handlers which may produce a result into one, which only calls `second` if
+/// `first` returns `None`.
+///
+/// Since both handlers receive the message by value, `first` receives a clone of it, and `second`
+/// receives the original if it's called.
+///
+/// ```rust
+/// use handler_map::or;
+///
+/// let parse = or(
+///     |s: String| s.parse::<u32>().ok(),
+///     |s: String| if s == "many" { Some(u32::max_value()) } else { None },
+/// );
+///
+/// assert_eq!(parse("5".to_string()), Some(5));
+/// assert_eq!(parse("many".to_string()), Some(u32::max_value()));
+/// assert_eq!(parse("some".to_string()), None);
+/// ```
+pub fn or<T, R, F, G>(first: F, second: G) -> impl Fn(T) -> Option<R>
+where
+    T: Clone,
+    F: Fn(T) -> Option<R>,
+    G: Fn(T) -> Option<R>,
+{
+    move |msg: T| first(msg.clone()).or_else(|| second(msg))
+}
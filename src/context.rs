@@ -0,0 +1,113 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! A handler map whose handlers receive a shared context along with their message.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use box_fn::{BoxFn, Opaque};
+
+/// Struct that maps types with functions or closures that receive them, along with a context
+/// value supplied by the caller.
+///
+/// This is like `HandlerMap`, but each handler also receives a reference to a context value of
+/// type `Ctx`, which is given to `call` rather than being captured by each handler. This allows
+/// handlers to share state without capturing it in an `Rc` or `Arc`. The context value only needs
+/// to live for the duration of each call, not as long as the map.
+///
+/// ```rust
+/// use handler_map::ContextHandlerMap;
+/// use std::cell::Cell;
+///
+/// struct AppState {
+///     clicks: Cell<u32>,
+/// }
+///
+/// struct Click;
+///
+/// let mut map = ContextHandlerMap::new();
+/// map.insert(|state: &AppState, _: Click| state.clicks.set(state.clicks.get() + 1));
+///
+/// let state = AppState { clicks: Cell::new(0) };
+/// assert!(map.call(&state, Click));
+/// assert_eq!(state.clicks.get(), 1);
+/// ```
+pub struct ContextHandlerMap<'a, Ctx> {
+    /// Handlers registered with `insert`, which receive a `(*const Ctx, T)`.
+    handlers: HashMap<TypeId, BoxFn<'a, Opaque>>,
+    _ctx: PhantomData<fn(&Ctx)>,
+}
+
+impl<'a, Ctx> Default for ContextHandlerMap<'a, Ctx> {
+    fn default() -> ContextHandlerMap<'a, Ctx> {
+        ContextHandlerMap {
+            handlers: HashMap::new(),
+            _ctx: PhantomData,
+        }
+    }
+}
+
+impl<'a, Ctx: 'a> ContextHandlerMap<'a, Ctx> {
+    /// Creates a new map with no handlers.
+    pub fn new() -> ContextHandlerMap<'a, Ctx> {
+        Self::default()
+    }
+
+    /// Registers a new handler into the map.
+    pub fn insert<T: Any, F: Fn(&Ctx, T) + 'a>(&mut self, handler: F) {
+        let handler = move |(ctx, msg): (*const Ctx, T)| handler(unsafe { &*ctx }, msg);
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        self.handlers.insert(id, ptr);
+    }
+
+    /// Un-registers the handler for the given type from this map, returning whether a handler
+    /// was registered.
+    pub fn remove<T: Any>(&mut self) -> bool {
+        self.handlers.remove(&TypeId::of::<T>()).is_some()
+    }
+
+    /// Returns true if the given message type has a handler registered in the map.
+    pub fn is_registered<T: Any>(&self) -> bool {
+        self.handlers.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Calls the handler with the given context and message, returning whether the handler was
+    /// registered.
+    pub fn call<T: Any>(&self, ctx: &Ctx, msg: T) -> bool {
+        if let Some(act) = self.handlers.get(&TypeId::of::<T>()) {
+            unsafe { act.call_erased((ctx as *const Ctx, msg)); }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use ContextHandlerMap;
+
+    #[test]
+    fn borrowed_context() {
+        struct Append(&'static str);
+
+        let mut map = ContextHandlerMap::new();
+        map.insert(|log: &RefCell<Vec<&str>>, msg: Append| log.borrow_mut().push(msg.0));
+
+        for _ in 0..2 {
+            let log = RefCell::new(Vec::new());
+            assert!(map.call(&log, Append("a")));
+            assert!(map.call(&log, Append("b")));
+            assert!(!map.call(&log, 5u8));
+            assert_eq!(*log.borrow(), vec!["a", "b"]);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! A handler map whose handlers receive a mutable context along with their message.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use box_fn::{BoxFn, Opaque};
+
+/// Struct that maps types with functions or closures that receive them, along with a mutable
+/// reference to a context value supplied by the caller.
+///
+/// This is like `ContextHandlerMap`, but each handler receives a `&mut Ctx` instead of a `&Ctx`,
+/// so it can change the context without it needing interior mutability. Since the handlers don't
+/// capture the context, `call` only borrows the map immutably; it's the context that's borrowed
+/// mutably for the duration of each call.
+///
+/// ```rust
+/// use handler_map::HandlerMapMut;
+///
+/// struct World {
+///     score: u32,
+/// }
+///
+/// struct Scored(u32);
+///
+/// let mut map = HandlerMapMut::new();
+/// map.insert(|world: &mut World, msg: Scored| world.score += msg.0);
+///
+/// let mut world = World { score: 0 };
+/// assert!(map.call(&mut world, Scored(5)));
+/// assert_eq!(world.score, 5);
+/// ```
+pub struct HandlerMapMut<'a, Ctx> {
+    /// Handlers registered with `insert`, which receive a `(*mut Ctx, T)`.
+    handlers: HashMap<TypeId, BoxFn<'a, Opaque>>,
+    _ctx: PhantomData<fn(&mut Ctx)>,
+}
+
+impl<'a, Ctx> Default for HandlerMapMut<'a, Ctx> {
+    fn default() -> HandlerMapMut<'a, Ctx> {
+        HandlerMapMut {
+            handlers: HashMap::new(),
+            _ctx: PhantomData,
+        }
+    }
+}
+
+impl<'a, Ctx: 'a> HandlerMapMut<'a, Ctx> {
+    /// Creates a new map with no handlers.
+    pub fn new() -> HandlerMapMut<'a, Ctx> {
+        Self::default()
+    }
+
+    /// Registers a new handler into the map.
+    pub fn insert<T: Any, F: Fn(&mut Ctx, T) + 'a>(&mut self, handler: F) {
+        let handler = move |(ctx, msg): (*mut Ctx, T)| handler(unsafe { &mut *ctx }, msg);
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        self.handlers.insert(id, ptr);
+    }
+
+    /// Un-registers the handler for the given type from this map, returning whether a handler
+    /// was registered.
+    pub fn remove<T: Any>(&mut self) -> bool {
+        self.handlers.remove(&TypeId::of::<T>()).is_some()
+    }
+
+    /// Returns true if the given message type has a handler registered in the map.
+    pub fn is_registered<T: Any>(&self) -> bool {
+        self.handlers.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Calls the handler with the given context and message, returning whether the handler was
+    /// registered.
+    pub fn call<T: Any>(&self, ctx: &mut Ctx, msg: T) -> bool {
+        if let Some(act) = self.handlers.get(&TypeId::of::<T>()) {
+            unsafe { act.call_erased((ctx as *mut Ctx, msg)); }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use HandlerMapMut;
+
+    #[test]
+    fn mutable_context() {
+        struct Counter {
+            total: u32,
+            calls: u32,
+        }
+
+        struct Add(u32);
+
+        let mut map = HandlerMapMut::new();
+        map.insert(|ctx: &mut Counter, msg: Add| {
+            ctx.total += msg.0;
+            ctx.calls += 1;
+        });
+        map.insert(|ctx: &mut Counter, _: ()| ctx.total = 0);
+
+        let mut counter = Counter { total: 0, calls: 0 };
+        for n in 1..4 {
+            assert!(map.call(&mut counter, Add(n)));
+        }
+        assert!(!map.call(&mut counter, 5u8));
+        assert_eq!((counter.total, counter.calls), (6, 3));
+
+        assert!(map.call(&mut counter, ()));
+        assert_eq!(counter.total, 0);
+        assert!(map.remove::<Add>());
+        assert!(!map.call(&mut counter, Add(1)));
+    }
+}
@@ -0,0 +1,251 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for conversions between message types, which let `call` route a message with no
+//! handler to the handler for the type it converts to.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use box_fn::BoxFn;
+use {CallOutcome, HandlerMap, HandlerStore, Slot, SlotHandler};
+
+/// Conversion registered with `register_conversion`.
+pub(crate) struct Conversion<'a> {
+    /// The `TypeId` of the type the message is converted to.
+    pub(crate) target: TypeId,
+    /// The names of the types the message is converted from and to, for `export_graph`.
+    pub(crate) names: (&'static str, &'static str),
+    /// The conversion itself, which receives the original message and returns the converted one,
+    /// both boxed.
+    convert: BoxFn<'a, Box<dyn Any>, Box<dyn Any>>,
+}
+
+/// Conversions registered with `register_conversion`, keyed by the type they convert from, in the
+/// order they were registered.
+pub(crate) type Conversions<'a> = HashMap<TypeId, Vec<Conversion<'a>>>;
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Registers a conversion from messages of type `A` to messages of type `B`, which `call` uses
+    /// when there's no handler for `A`.
+    ///
+    /// When `call`, `dispatch`, `call_or_return`, or `call_any`, or any of the methods built on
+    /// them, receives a message with no handler registered for its type, the conversions from that
+    /// type are tried in the order they were registered. The first one whose target type has a
+    /// handler in this map that `call` would run, in a group that isn't disabled, converts the
+    /// message, and the converted message is passed to that handler. Only this map's own handlers
+    /// are considered, and conversions aren't chained: a message converted to `B` isn't converted
+    /// again, even if `B` has no handler but has conversions of its own. If no conversion applies,
+    /// the message goes on to the parent map or the fallback handler as usual.
+    ///
+    /// Registering another conversion between the same two types replaces the existing one,
+    /// keeping its place in the order. Conversions don't count as handlers, so `is_registered::<A>`
+    /// stays false, and `call_lazy::<A>` won't build a message to convert.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::cell::Cell;
+    ///
+    /// struct LoginV1 { user: String }
+    /// struct LoginV2 { user: String, token: Option<String> }
+    ///
+    /// let logins = Cell::new(0);
+    /// let mut map = HandlerMap::new();
+    /// map.insert(|_: LoginV2| logins.set(logins.get() + 1));
+    /// map.register_conversion(|old: LoginV1| LoginV2 { user: old.user, token: None });
+    ///
+    /// assert!(map.call(LoginV1 { user: "ferris".into() }));
+    /// assert!(!map.is_registered::<LoginV1>());
+    /// assert_eq!(logins.get(), 1);
+    /// ```
+    pub fn register_conversion<A, B, F>(&mut self, conv: F)
+    where
+        A: Any,
+        B: Any,
+        F: Fn(A) -> B + 'a,
+    {
+        let convert = move |msg: Box<dyn Any>| -> Box<dyn Any> {
+            // conversions are only looked up by the `TypeId` of their source type, so this can't
+            // fail
+            Box::new(conv(*msg.downcast::<A>().unwrap()))
+        };
+        let conversion = Conversion {
+            target: TypeId::of::<B>(),
+            names: (std::any::type_name::<A>(), std::any::type_name::<B>()),
+            convert: BoxFn::from(Box::new(convert)).erase(),
+        };
+
+        let convs = self.conversions.entry(TypeId::of::<A>()).or_default();
+        match convs.iter_mut().find(|c| c.target == conversion.target) {
+            Some(existing) => *existing = conversion,
+            None => convs.push(conversion),
+        }
+    }
+
+    /// Un-registers the conversion from `A` to `B` set with `register_conversion`, returning
+    /// whether it was registered.
+    pub fn remove_conversion<A: Any, B: Any>(&mut self) -> bool {
+        let source = TypeId::of::<A>();
+        let target = TypeId::of::<B>();
+        let convs = match self.conversions.get_mut(&source) {
+            Some(convs) => convs,
+            None => return false,
+        };
+
+        let before = convs.len();
+        convs.retain(|c| c.target != target);
+        let removed = convs.len() != before;
+        if convs.is_empty() {
+            self.conversions.remove(&source);
+        }
+        removed
+    }
+
+    /// Converts the given message with the first applicable conversion, and runs the handler for
+    /// the converted message, returning its outcome. If no conversion applies, the message is
+    /// handed back.
+    pub(crate) fn dispatch_converted<T: Any>(&self, msg: T) -> Result<CallOutcome, T> {
+        match self.find_conversion(TypeId::of::<T>()) {
+            Some((conv, slot)) => Ok(self.run_conversion(conv, slot, Box::new(msg))),
+            None => Err(msg),
+        }
+    }
+
+    /// Converts the given boxed message in the same way as `dispatch_converted`.
+    pub(crate) fn dispatch_converted_boxed(&self, msg: Box<dyn Any>)
+        -> Result<CallOutcome, Box<dyn Any>>
+    {
+        match self.find_conversion((*msg).type_id()) {
+            Some((conv, slot)) => Ok(self.run_conversion(conv, slot, msg)),
+            None => Err(msg),
+        }
+    }
+
+    /// Returns the first conversion from the message type with the given `TypeId` whose target
+    /// type has an enabled handler that `call` would run, along with that handler's slot.
+    fn find_conversion(&self, id: TypeId) -> Option<(&Conversion<'a>, &Slot<'a>)> {
+        self.conversions.get(&id)?.iter().find_map(|conv| match self.slots.get(&conv.target) {
+            Some(&Slot { handler: SlotHandler::Async(_), .. }) |
+                Some(&Slot { handler: SlotHandler::Cascade(_), .. }) => None,
+            Some(slot) if !self.is_disabled(slot) => Some((conv, slot)),
+            _ => None,
+        })
+    }
+
+    /// Converts the given boxed message, and runs the handler in the given slot with the result.
+    fn run_conversion(&self, conv: &Conversion<'a>, slot: &Slot<'a>, msg: Box<dyn Any>)
+        -> CallOutcome
+    {
+        let converted = conv.convert.call(msg);
+        // `find_conversion` skips the handlers that `call` doesn't run, which are the only ones
+        // that hand the message back
+        (slot.run_boxed)(slot, converted, &|| self.type_names())
+            .unwrap_or(CallOutcome::Unregistered)
+    }
+}
+
+/// Moves the conversions in `theirs` into `ours`, as if they were registered afterward, except
+/// that a conversion between the same two types as one in `ours` is dropped if `keep_self` is set.
+pub(crate) fn merge_conversions<'a>(
+    ours: &mut Conversions<'a>,
+    theirs: Conversions<'a>,
+    keep_self: bool,
+) {
+    for (source, theirs) in theirs {
+        let convs = ours.entry(source).or_default();
+        for conv in theirs {
+            match convs.iter_mut().find(|c| c.target == conv.target) {
+                Some(existing) => if !keep_self {
+                    *existing = conv;
+                },
+                None => convs.push(conv),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use {CallOutcome, HandlerMap};
+
+    #[test]
+    fn register_conversion() {
+        struct V1(u8);
+        struct V2(u16);
+        struct V3(u32);
+
+        let log = RefCell::new(Vec::new());
+        let mut map = HandlerMap::new();
+        map.insert(|msg: V3| log.borrow_mut().push(("v3", msg.0)));
+        map.register_conversion(|msg: V1| V2(msg.0 as u16));
+        map.register_conversion(|msg: V2| V3(msg.0 as u32));
+
+        // conversions are single-hop, so V1 -> V2 -> V3 isn't followed
+        assert!(!map.call(V1(1)));
+        assert!(map.call(V2(2)));
+
+        // the first conversion whose target is handled wins
+        map.register_conversion(|msg: V1| V3(msg.0 as u32 * 10));
+        assert!(map.call(V1(3)));
+        map.insert(|msg: V2| log.borrow_mut().push(("v2", msg.0 as u32)));
+        assert!(map.call(V1(4)));
+
+        // a direct handler takes priority over conversions
+        map.insert(|msg: V1| log.borrow_mut().push(("v1", msg.0 as u32)));
+        assert!(map.call(V1(5)));
+        assert!(map.remove::<V1>());
+
+        // replacing a conversion keeps its place in the order
+        map.register_conversion(|msg: V1| V2(msg.0 as u16 + 100));
+        assert!(map.call(V1(6)));
+
+        map.insert_in_group(1, |_: V2| panic!("group is disabled"));
+        map.set_group_enabled(1, false);
+        assert_eq!(map.dispatch(V1(7)), CallOutcome::Handled);
+
+        assert!(map.remove_conversion::<V1, V3>());
+        assert!(!map.remove_conversion::<V1, V3>());
+        assert_eq!(map.dispatch(V1(8)), CallOutcome::Unregistered);
+
+        assert_eq!(*log.borrow(), [
+            ("v3", 2), ("v3", 30), ("v2", 4), ("v1", 5), ("v2", 106), ("v3", 70),
+        ]);
+    }
+
+    #[test]
+    fn conversion_entry_points() {
+        use std::any::Any;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        struct Old(u32);
+        struct New(u32);
+
+        let total = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert(|msg: New| total.set(total.get() + msg.0));
+        map.register_conversion(|msg: Old| New(msg.0));
+
+        assert!(map.call_or_return(Old(1)).is_ok());
+        assert!(map.try_dispatch(Old(2)).is_ok());
+        assert_eq!(map.call_any(Box::new(Old(4))).ok(), Some(true));
+        assert!(map.call_boxed(Box::new(Old(8))).is_ok());
+        assert_eq!(map.dispatch_batch(vec![Box::new(Old(16)) as Box<dyn Any>]), [true]);
+        assert_eq!(total.get(), 31);
+
+        // a child map without the conversion passes the message on to its parent, which has it
+        let child = HandlerMap::new().with_parent(Rc::new(map));
+        assert_eq!(child.call_any(Box::new(Old(32))).ok(), Some(true));
+        assert!(child.call_or_return(Old(64)).is_ok());
+        assert_eq!(total.get(), 127);
+
+        let map = HandlerMap::new();
+        assert!(map.call_or_return(Old(0)).is_err());
+        assert!(map.call_any(Box::new(Old(0))).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for capturing unhandled messages in a "dead-letter queue", so they can be inspected or
+//! re-dispatched later.
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::hash::BuildHasher;
+
+use {HandlerMap, HandlerStore};
+
+/// Function which attempts to dispatch a boxed message, returning whether it was handled. These
+/// are monomorphized for the original message type when its message is queued.
+type Redeliver<'a, S, H> = fn(&HandlerMap<'a, S, H>, Box<dyn Any>) -> bool;
+
+/// Bounded queue of messages which were dispatched without a handler to receive them.
+pub(crate) struct DeadLetterQueue<'a, S, H> {
+    capacity: usize,
+    queue: VecDeque<(Box<dyn Any>, Redeliver<'a, S, H>)>,
+}
+
+impl<'a, S, H> Default for DeadLetterQueue<'a, S, H> {
+    fn default() -> DeadLetterQueue<'a, S, H> {
+        DeadLetterQueue {
+            capacity: 0,
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> DeadLetterQueue<'a, S, H> {
+    /// Returns the maximum number of messages the queue holds.
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Adds the given message to the queue, evicting the oldest message if the queue is full.
+    pub(crate) fn push<T: Any>(&mut self, msg: T) {
+        fn redeliver<'a, T, S, H>(map: &HandlerMap<'a, S, H>, msg: Box<dyn Any>) -> bool
+        where
+            T: Any,
+            S: BuildHasher,
+            H: HandlerStore<'a>,
+        {
+            match msg.downcast::<T>() {
+                Ok(msg) => map.call(*msg),
+                Err(_) => false,
+            }
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+        if self.queue.len() == self.capacity {
+            self.queue.pop_front();
+        }
+        self.queue.push_back((Box::new(msg), redeliver::<T, S, H>));
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Starts capturing unhandled messages in a dead-letter queue, holding at most `capacity`
+    /// messages.
+    ///
+    /// While the queue is enabled, every message passed to `call` or `dispatch` that doesn't have
+    /// a handler registered for its type is boxed and saved in the queue, rather than being
+    /// dropped. These messages can later be re-dispatched with `redeliver`, or removed with
+    /// `take_dead_letters`.
+    ///
+    /// When the queue is full, the oldest message in it is evicted (and dropped) to make room for
+    /// the new one. If the queue was already enabled, this only changes its capacity, evicting
+    /// the oldest messages if it now holds too many.
+    pub fn enable_dead_letter(&mut self, capacity: usize) {
+        let dead = self.dead_letters.get_or_insert_with(Default::default).get_mut();
+        dead.capacity = capacity;
+        while dead.queue.len() > capacity {
+            dead.queue.pop_front();
+        }
+    }
+
+    /// Stops capturing unhandled messages, and drops any messages that were still in the
+    /// dead-letter queue.
+    pub fn disable_dead_letter(&mut self) {
+        self.dead_letters = None;
+    }
+
+    /// Returns the number of messages currently waiting in the dead-letter queue.
+    pub fn dead_letter_count(&self) -> usize {
+        self.dead_letters.as_ref().map_or(0, |dead| dead.borrow().queue.len())
+    }
+
+    /// Removes all the messages currently in the dead-letter queue, returning them in the order
+    /// they were received.
+    pub fn take_dead_letters(&mut self) -> Vec<Box<dyn Any>> {
+        match self.dead_letters {
+            Some(ref mut dead) => dead.get_mut().queue.drain(..).map(|(msg, _)| msg).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Attempts to dispatch every message in the dead-letter queue again, returning how many of
+    /// them were handled.
+    ///
+    /// Messages are re-dispatched in the order they were received. Messages which are still
+    /// unhandled are placed back in the queue.
+    pub fn redeliver(&self) -> usize {
+        let pending = match self.dead_letters {
+            Some(ref dead) => dead.borrow_mut().queue.drain(..).collect::<Vec<_>>(),
+            None => return 0,
+        };
+
+        pending.into_iter()
+            .map(|(msg, redeliver)| redeliver(self, msg))
+            .filter(|&handled| handled)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use HandlerMap;
+
+    #[test]
+    fn redeliver() {
+        struct Early;
+        struct Other;
+
+        let mut map = HandlerMap::new();
+        map.enable_dead_letter(8);
+
+        assert!(!map.call(Early));
+        assert!(!map.call(Early));
+        assert!(!map.call(Other));
+        assert_eq!(map.dead_letter_count(), 3);
+
+        assert_eq!(map.redeliver(), 0);
+        assert_eq!(map.dead_letter_count(), 3);
+
+        map.insert(|_: Early| {});
+        assert_eq!(map.redeliver(), 2);
+        assert_eq!(map.dead_letter_count(), 1);
+
+        let left = map.take_dead_letters();
+        assert_eq!(left.len(), 1);
+        assert!(left[0].is::<Other>());
+        assert_eq!(map.dead_letter_count(), 0);
+    }
+
+    #[test]
+    fn eviction() {
+        let mut map = HandlerMap::new();
+        map.enable_dead_letter(2);
+
+        assert!(!map.call(1u32));
+        assert!(!map.call(2u32));
+        assert!(!map.call(3u32));
+
+        let left = map.take_dead_letters();
+        let left = left.iter().map(|msg| *msg.downcast_ref::<u32>().unwrap()).collect::<Vec<_>>();
+        assert_eq!(left, vec![2, 3]);
+    }
+}
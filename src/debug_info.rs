@@ -0,0 +1,232 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Introspection of the handlers registered in a `HandlerMap`.
+
+use std::any::{self, Any, TypeId};
+use std::fmt;
+use std::hash::BuildHasher;
+
+use {HandlerMap, HandlerStore, SlotHandler};
+
+/// The calling convention a handler was registered with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HandlerKind {
+    /// A handler registered with `insert` or one of its variants, which receives its message by
+    /// value.
+    Value,
+    /// A handler registered with `insert_async`, which returns a future.
+    Async,
+    /// A handler registered with `insert_with_registry`, which also receives the names of the
+    /// registered message types.
+    Registry,
+    /// A handler registered with `insert_cascade`, which can emit follow-up messages.
+    Cascade,
+    /// A handler registered with `insert_ref`, which receives its message by reference.
+    Ref,
+    /// A handler registered with `insert_mut_ref`, which receives its message by mutable
+    /// reference.
+    MutRef,
+    /// A handler registered with `insert_filter`, which can decline its message.
+    Filter,
+    /// A handler registered with `insert_raw`, which receives its message boxed.
+    Raw,
+    /// A handler registered with `insert_once`, which is consumed the first time it's run.
+    Once,
+}
+
+/// Summary of the information a `HandlerMap` has about one of its handlers, as returned by
+/// `HandlerMap::iter_debug`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlerDebugInfo {
+    /// The `TypeId` of the message type.
+    pub type_id: TypeId,
+    /// The name of the message type, as given by `std::any::type_name`.
+    pub type_name: &'static str,
+    /// The calling convention the handler was registered with.
+    pub kind: HandlerKind,
+    /// Whether the handler was registered with a validator via `insert_validated`.
+    pub validated: bool,
+    /// If the handler was marked with `mark_single_shot`, whether it has already been run.
+    ///
+    /// This is `None` if the handler isn't single-shot.
+    pub single_shot_consumed: Option<bool>,
+    /// The group the handler was registered in with `insert_in_group`.
+    ///
+    /// This is `None` for handlers which weren't registered with `insert_in_group`.
+    pub group: Option<u32>,
+}
+
+/// Whether a message type has a handler, along with the names the map can report for it, as
+/// returned by `HandlerMap::describe`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlerDescription {
+    /// Whether a handler is registered for the message type.
+    pub registered: bool,
+    /// The name of the message type, as given by `std::any::type_name`.
+    pub type_name: &'static str,
+    /// The name the map recorded for the message type when its handler was registered, or `None`
+    /// if no handler is registered.
+    ///
+    /// This is the same as `type_name`, except for handlers registered with `insert_raw`, whose
+    /// message type isn't known by name, and which are recorded as `"<raw>"`.
+    pub registered_name: Option<&'static str>,
+}
+
+impl HandlerDescription {
+    /// Returns true if a handler is registered, and the map recorded the message type under the
+    /// same name as `type_name`.
+    pub fn names_match(&self) -> bool {
+        self.registered_name == Some(self.type_name)
+    }
+}
+
+impl HandlerKind {
+    /// Returns the calling convention of the given handler.
+    pub(crate) fn of(handler: &SlotHandler) -> HandlerKind {
+        match *handler {
+            SlotHandler::Value(_) => HandlerKind::Value,
+            SlotHandler::Async(_) => HandlerKind::Async,
+            SlotHandler::Registry(_) => HandlerKind::Registry,
+            SlotHandler::Cascade(_) => HandlerKind::Cascade,
+            SlotHandler::Ref(_) => HandlerKind::Ref,
+            SlotHandler::MutRef(_) => HandlerKind::MutRef,
+            SlotHandler::Filter(_) => HandlerKind::Filter,
+            SlotHandler::Raw(_) => HandlerKind::Raw,
+            SlotHandler::Once(..) => HandlerKind::Once,
+        }
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Returns an iterator over a summary of every handler registered in this map.
+    ///
+    /// This collects the metadata the map has captured about each handler into one place, for
+    /// use in dashboards and other diagnostics. The order of the handlers is unspecified.
+    pub fn iter_debug<'b>(&'b self) -> impl Iterator<Item = HandlerDebugInfo> + use<'a, 'b, S, H> {
+        self.slots.iter().map(|(id, slot)| HandlerDebugInfo {
+            type_id: *id,
+            type_name: slot.name,
+            kind: HandlerKind::of(&slot.handler),
+            validated: slot.validator.is_some(),
+            single_shot_consumed: slot.single_shot.as_ref().map(|consumed| consumed.get()),
+            group: slot.group,
+        })
+    }
+
+    /// Returns whether the given message type has a handler registered, along with its name, for
+    /// use in diagnostics like error messages.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    ///
+    /// struct Ping;
+    /// struct Pong;
+    ///
+    /// let mut map = HandlerMap::new();
+    /// map.insert(|_: Ping| {});
+    ///
+    /// let pong = map.describe::<Pong>();
+    /// assert!(!pong.registered);
+    /// assert!(pong.type_name.ends_with("Pong"));
+    /// assert!(map.describe::<Ping>().names_match());
+    /// ```
+    pub fn describe<T: Any>(&self) -> HandlerDescription {
+        let registered_name = self.slots.get(&TypeId::of::<T>()).map(|slot| slot.name);
+        HandlerDescription {
+            registered: registered_name.is_some(),
+            type_name: any::type_name::<T>(),
+            registered_name,
+        }
+    }
+}
+
+/// Lists the names of the registered message types, sorted alphabetically, along with how many
+/// handlers were registered with `insert_multi`. The handlers themselves can't be printed.
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> fmt::Debug for HandlerMap<'a, S, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let multi = self.listeners.values().map(Vec::len).sum::<usize>();
+        f.debug_struct("HandlerMap")
+            .field("handlers", &self.type_names())
+            .field("multi_handlers", &multi)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::{TypeId, type_name};
+    use std::future;
+
+    use {HandlerMap, HandlerKind};
+
+    #[test]
+    fn iter_debug() {
+        struct Sync;
+        struct Async;
+        struct Grouped;
+
+        let mut map = HandlerMap::new();
+        map.insert(|_: Sync| {});
+        map.insert_async(|_: Async| future::ready(()));
+        map.insert_in_group(7, |_: Grouped| {});
+        map.mark_single_shot::<Sync>();
+        assert!(map.call(Sync));
+
+        let mut info = map.iter_debug().collect::<Vec<_>>();
+        info.sort_by_key(|info| info.type_name);
+
+        assert_eq!(info.len(), 3);
+        assert_eq!(info[0].type_id, TypeId::of::<Async>());
+        assert_eq!(info[0].type_name, type_name::<Async>());
+        assert_eq!(info[0].kind, HandlerKind::Async);
+        assert_eq!(info[0].single_shot_consumed, None);
+        assert_eq!(info[0].group, None);
+        assert_eq!(info[1].type_id, TypeId::of::<Grouped>());
+        assert_eq!(info[1].group, Some(7));
+        assert_eq!(info[2].type_id, TypeId::of::<Sync>());
+        assert_eq!(info[2].kind, HandlerKind::Value);
+        assert_eq!(info[2].single_shot_consumed, Some(true));
+        assert!(!info[2].validated);
+        assert_eq!(info[2].group, None);
+    }
+
+    #[test]
+    fn describe() {
+        struct Known;
+        struct Raw;
+        struct Missing;
+
+        let mut map = HandlerMap::new();
+        map.insert(|_: Known| {});
+        map.insert_raw(TypeId::of::<Raw>(), |_| {});
+
+        let known = map.describe::<Known>();
+        assert!(known.registered);
+        assert_eq!(known.type_name, type_name::<Known>());
+        assert!(known.names_match());
+
+        let raw = map.describe::<Raw>();
+        assert!(raw.registered);
+        assert_eq!(raw.registered_name, Some("<raw>"));
+        assert!(!raw.names_match());
+
+        let missing = map.describe::<Missing>();
+        assert!(!missing.registered);
+        assert_eq!(missing.registered_name, None);
+        assert!(!missing.names_match());
+    }
+
+    #[test]
+    fn debug() {
+        let mut map = HandlerMap::new();
+        map.insert(|_: u8| {});
+        map.insert(|_: bool| {});
+        map.insert_multi(|_: u8| {});
+
+        assert_eq!(format!("{:?}", map),
+                   r#"HandlerMap { handlers: ["bool", "u8"], multi_handlers: 1 }"#);
+    }
+}
@@ -0,0 +1,184 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for queueing messages to be dispatched later, all at once, and changes to the map to
+//! be made once no handler is running.
+
+use std::any::{Any, TypeId};
+use std::hash::BuildHasher;
+use std::mem;
+
+use {ErasedHandler, Handler, HandlerMap, HandlerStore};
+
+/// A change to the map requested with `defer_insert` or `defer_remove`.
+pub(crate) enum PendingOp<'a> {
+    Insert(ErasedHandler<'a>),
+    Remove(TypeId),
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Adds a message to the map's queue of deferred messages, to be dispatched by the next call
+    /// to `flush`.
+    pub fn enqueue<T: Any>(&mut self, msg: T) {
+        self.deferred.push(Box::new(msg));
+    }
+
+    /// Returns the number of messages waiting to be dispatched by `flush`.
+    pub fn queued_count(&self) -> usize {
+        self.deferred.len()
+    }
+
+    /// Dispatches every message added with `enqueue` since the last flush, in the order they were
+    /// added, returning how many of them were handled.
+    ///
+    /// Each message is dispatched with `call_any`. Messages which aren't handled are dropped, so
+    /// the number of dropped messages is the difference between `queued_count` beforehand and the
+    /// value returned here.
+    pub fn flush(&mut self) -> usize {
+        mem::take(&mut self.deferred).into_iter()
+            .map(|msg| self.call_any(msg).unwrap_or(false))
+            .filter(|&handled| handled)
+            .count()
+    }
+
+    /// Requests that the given handler be registered, the next time `apply_deferred` is called.
+    ///
+    /// Since `call` only borrows the map immutably, a handler can't register or remove handlers
+    /// in the map that's running it. If it can reach that map through a shared reference, for
+    /// example through an `Rc<RefCell<HandlerMap>>` borrowed immutably for the call, it can
+    /// request the change with this method or `defer_remove` instead. The change is made when the
+    /// map's owner calls `apply_deferred` after the call returns, so the handler registered this
+    /// way can't be called before then.
+    ///
+    /// The changes aren't made automatically at the end of `call`: since it only borrows the map
+    /// immutably, it can't change the map, and the methods which do borrow it mutably, like
+    /// `call_mut`, don't let a handler reach the map to request a change in the first place.
+    pub fn defer_insert<T: Any, F: Handler<T> + 'a>(&self, handler: F) {
+        self.pending_ops.borrow_mut().push(PendingOp::Insert(ErasedHandler::new(handler)));
+    }
+
+    /// Requests that the handler for the given message type be un-registered, the next time
+    /// `apply_deferred` is called.
+    ///
+    /// The handler is removed the same way as with `remove`, so a teardown closure registered with
+    /// `insert_with_teardown` is run. See `defer_insert` for details.
+    pub fn defer_remove<T: Any>(&self) {
+        self.pending_ops.borrow_mut().push(PendingOp::Remove(TypeId::of::<T>()));
+    }
+
+    /// Makes the changes requested with `defer_insert` and `defer_remove`, in the order they were
+    /// requested, returning how many there were.
+    pub fn apply_deferred(&mut self) -> usize {
+        let ops = mem::take(self.pending_ops.get_mut());
+        let count = ops.len();
+        for op in ops {
+            match op {
+                PendingOp::Insert(handler) => {
+                    let (id, slot) = handler.into_parts();
+                    self.insert_slot(id, slot);
+                }
+                PendingOp::Remove(id) => {
+                    self.remove_by_id(id);
+                }
+            }
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    use HandlerMap;
+
+    #[test]
+    fn flush() {
+        let log = RefCell::new(Vec::new());
+        let mut map = HandlerMap::new();
+        map.insert(|n: u32| log.borrow_mut().push(n));
+
+        map.enqueue(1u32);
+        map.enqueue("unhandled");
+        map.enqueue(2u32);
+        assert!(log.borrow().is_empty());
+        assert_eq!(map.queued_count(), 3);
+
+        assert_eq!(map.flush(), 2);
+        assert_eq!(map.queued_count(), 0);
+        assert_eq!(*log.borrow(), [1, 2]);
+        assert_eq!(map.flush(), 0);
+    }
+
+    #[test]
+    fn defer_insert() {
+        struct Start;
+        struct Follow;
+
+        let follows = Rc::new(Cell::new(0));
+        let map = Rc::new(RefCell::new(HandlerMap::new()));
+        {
+            let weak = Rc::downgrade(&map);
+            let follows = follows.clone();
+            map.borrow_mut().insert(move |_: Start| {
+                let map = weak.upgrade().unwrap();
+                let map = map.borrow();
+                let follows = follows.clone();
+                map.defer_insert(move |_: Follow| follows.set(follows.get() + 1));
+                map.defer_remove::<Start>();
+                assert!(!map.call(Follow));
+            });
+        }
+
+        assert!(map.borrow().call(Start));
+        assert!(!map.borrow().call(Follow));
+        assert_eq!(follows.get(), 0);
+
+        assert_eq!(map.borrow_mut().apply_deferred(), 2);
+        assert!(map.borrow().call(Follow));
+        assert!(!map.borrow().is_registered::<Start>());
+        assert_eq!(follows.get(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn nested_mutation() {
+        struct Start;
+
+        let map = Rc::new(RefCell::new(HandlerMap::new()));
+        {
+            let weak = Rc::downgrade(&map);
+            map.borrow_mut().insert(move |_: Start| {
+                let map = weak.upgrade().unwrap();
+                map.borrow_mut().remove::<Start>();
+            });
+        }
+
+        let _ = map.borrow().call(Start);
+    }
+
+    #[test]
+    fn defer_remove_teardown() {
+        struct Close;
+
+        let closed = Rc::new(Cell::new(false));
+        let map = Rc::new(RefCell::new(HandlerMap::new()));
+        {
+            let weak = Rc::downgrade(&map);
+            let closed = closed.clone();
+            map.borrow_mut().insert_with_teardown(
+                move |_: Close| weak.upgrade().unwrap().borrow().defer_remove::<Close>(),
+                move || closed.set(true),
+            );
+        }
+
+        assert!(map.borrow().call(Close));
+        assert!(!closed.get());
+        assert_eq!(map.borrow_mut().apply_deferred(), 1);
+        assert!(closed.get());
+        assert!(!map.borrow().is_registered::<Close>());
+    }
+}
@@ -0,0 +1,137 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! A handler map whose handlers all receive the same message type, keyed by a runtime value.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use box_fn::BoxFn;
+use Handler;
+
+/// Struct that maps runtime keys with functions or closures that receive a message of type `T`.
+///
+/// This is like `HandlerMap`, but instead of picking a handler by the type of each message, it
+/// picks one by a key given alongside the message, like the kind field of a network packet. Since
+/// every handler receives the same message type, they're called directly, without needing to
+/// reinterpret the message as any other type.
+///
+/// ```rust
+/// use handler_map::DiscriminantMap;
+/// use std::cell::RefCell;
+///
+/// struct Packet {
+///     kind: u16,
+///     payload: Vec<u8>,
+/// }
+///
+/// let pings = RefCell::new(Vec::new());
+/// let mut map = DiscriminantMap::new();
+/// map.insert(1, |p: Packet| pings.borrow_mut().push(p.payload));
+/// map.insert(2, |_: Packet| println!("disconnect"));
+///
+/// let packet = Packet { kind: 1, payload: vec![1, 2, 3] };
+/// let kind = packet.kind;
+/// assert!(map.call(&kind, packet));
+/// assert!(!map.call(&7, Packet { kind: 7, payload: vec![] }));
+/// assert_eq!(*pings.borrow(), [vec![1, 2, 3]]);
+/// ```
+pub struct DiscriminantMap<'a, K, T: 'a> {
+    handlers: HashMap<K, BoxFn<'a, T>>,
+}
+
+impl<'a, K: Hash + Eq, T: 'a> Default for DiscriminantMap<'a, K, T> {
+    fn default() -> DiscriminantMap<'a, K, T> {
+        DiscriminantMap {
+            handlers: HashMap::new(),
+        }
+    }
+}
+
+impl<'a, K: Hash + Eq, T: 'a> DiscriminantMap<'a, K, T> {
+    /// Creates a new map with no handlers.
+    pub fn new() -> DiscriminantMap<'a, K, T> {
+        Self::default()
+    }
+
+    /// Registers a new handler into the map for the given key, replacing any handler already
+    /// registered for it.
+    pub fn insert<F: Handler<T> + 'a>(&mut self, key: K, handler: F) {
+        let ptr = BoxFn::from(Box::new(handler)).erase();
+        self.handlers.insert(key, ptr);
+    }
+
+    /// Un-registers the handler for the given key from this map, returning whether a handler was
+    /// registered.
+    pub fn remove(&mut self, key: &K) -> bool {
+        self.handlers.remove(key).is_some()
+    }
+
+    /// Returns true if the given key has a handler registered in the map.
+    pub fn is_registered(&self, key: &K) -> bool {
+        self.handlers.contains_key(key)
+    }
+
+    /// Returns the number of handlers registered in the map.
+    pub fn len(&self) -> usize {
+        self.handlers.len()
+    }
+
+    /// Returns true if there are no handlers registered in the map.
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    /// Calls the handler for the given key with the given message, returning whether a handler
+    /// was registered for the key.
+    ///
+    /// If there's no handler for the key, the message is dropped.
+    pub fn call(&self, key: &K, msg: T) -> bool {
+        if let Some(act) = self.handlers.get(key) {
+            act.call(msg);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use DiscriminantMap;
+
+    #[test]
+    fn discriminant() {
+        struct Packet {
+            kind: &'static str,
+            len: u32,
+        }
+
+        let (reads, writes) = (Cell::new(0), Cell::new(0));
+        let mut map = DiscriminantMap::new();
+        map.insert("read", |p: Packet| reads.set(reads.get() + p.len));
+        map.insert("write", |_: Packet| panic!("should have been replaced"));
+        map.insert("write", |p: Packet| writes.set(writes.get() + p.len));
+        assert_eq!(map.len(), 2);
+
+        for p in [
+            Packet { kind: "read", len: 3 },
+            Packet { kind: "write", len: 5 },
+            Packet { kind: "read", len: 1 },
+        ] {
+            let kind = p.kind;
+            assert!(map.call(&kind, p));
+        }
+        assert!(!map.call(&"seek", Packet { kind: "seek", len: 1 }));
+        assert_eq!((reads.get(), writes.get()), (4, 5));
+
+        assert!(map.remove(&"read"));
+        assert!(!map.is_registered(&"read"));
+        assert!(!map.call(&"read", Packet { kind: "read", len: 1 }));
+        assert!(!map.is_empty());
+    }
+}
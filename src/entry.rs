@@ -0,0 +1,161 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! An `entry`-style API for conditionally registering handlers, mirroring `HashMap::entry`.
+
+use std::any::{Any, TypeId};
+use std::hash::BuildHasher;
+use std::marker::PhantomData;
+
+use box_fn::BoxFn;
+use {ErasedHandler, Handler, HandlerMap, HandlerStore, Slot, SlotHandler};
+
+/// A view into the handler slot for a single message type `T`, which may or may not have a
+/// handler registered.
+///
+/// This is created by `HandlerMap::entry`.
+pub enum Entry<'b, 'a: 'b, T: Any> {
+    /// A handler is already registered for `T`.
+    Occupied(OccupiedEntry<'b, 'a, T>),
+    /// No handler is registered for `T`.
+    Vacant(VacantEntry<'b, 'a, T>),
+}
+
+/// A view into the slot for a message type that already has a handler registered.
+pub struct OccupiedEntry<'b, 'a: 'b, T: Any> {
+    /// The map's store, which holds a slot for `T`.
+    store: &'b mut (dyn HandlerStore<'a> + 'b),
+    _msg: PhantomData<fn(T)>,
+}
+
+/// A view into the slot for a message type that doesn't have a handler registered.
+pub struct VacantEntry<'b, 'a: 'b, T: Any> {
+    /// The map's store, which doesn't hold a slot for `T`.
+    store: &'b mut (dyn HandlerStore<'a> + 'b),
+    _msg: PhantomData<fn(T)>,
+}
+
+impl<'b, 'a: 'b, T: Any> Entry<'b, 'a, T> {
+    /// Registers the given handler if no handler was registered for `T`.
+    pub fn or_insert<F: Handler<T> + 'a>(self, handler: F) {
+        if let Entry::Vacant(entry) = self {
+            entry.insert(handler);
+        }
+    }
+
+    /// Registers the handler returned by `make` if no handler was registered for `T`.
+    ///
+    /// `make` is only called if a handler needs to be registered.
+    pub fn or_insert_with<F, M>(self, make: M)
+    where
+        F: Handler<T> + 'a,
+        M: FnOnce() -> F,
+    {
+        if let Entry::Vacant(entry) = self {
+            entry.insert(make());
+        }
+    }
+
+    /// Registers a handler that does nothing if no handler was registered for `T`.
+    ///
+    /// This is useful when it's acceptable for a message type to be "registered but ignored", so
+    /// that calls for it never miss.
+    pub fn or_default(self) {
+        self.or_insert(|_: T| {});
+    }
+}
+
+impl<'b, 'a: 'b, T: Any> OccupiedEntry<'b, 'a, T> {
+    /// Returns the name of the message type, as given by `std::any::type_name`.
+    pub fn type_name(&self) -> &'static str {
+        self.store.get(&TypeId::of::<T>()).expect("entry is occupied").name
+    }
+
+    /// Replaces the registered handler with the given one, returning the old handler.
+    ///
+    /// Like `insert`, this clears any validator or single-shot state attached to the old handler.
+    pub fn replace<F: Handler<T> + 'a>(&mut self, handler: F) -> ErasedHandler<'a> {
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let old = self.store.insert(TypeId::of::<T>(), Slot::new::<T>(SlotHandler::Value(ptr)));
+        ErasedHandler::from_slot(old.expect("entry is occupied"))
+    }
+
+    /// Un-registers the handler, returning it.
+    pub fn remove(self) -> ErasedHandler<'a> {
+        ErasedHandler::from_slot(self.store.remove(&TypeId::of::<T>()).expect("entry is occupied"))
+    }
+}
+
+impl<'b, 'a: 'b, T: Any> VacantEntry<'b, 'a, T> {
+    /// Registers the given handler for `T`.
+    pub fn insert<F: Handler<T> + 'a>(self, handler: F) {
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        self.store.insert(TypeId::of::<T>(), Slot::new::<T>(SlotHandler::Value(ptr)));
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Gets the entry for the message type `T`, to inspect or modify its handler.
+    pub fn entry<'b, T: Any>(&'b mut self) -> Entry<'b, 'a, T> {
+        let store = &mut self.slots;
+        if store.contains(&TypeId::of::<T>()) {
+            Entry::Occupied(OccupiedEntry { store, _msg: PhantomData })
+        } else {
+            Entry::Vacant(VacantEntry { store, _msg: PhantomData })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use HandlerMap;
+
+    #[test]
+    fn occupied() {
+        use Entry;
+
+        struct Msg;
+
+        let mut map = HandlerMap::new();
+        map.entry::<Msg>().or_insert_with(|| |_: Msg| {});
+        map.entry::<Msg>().or_insert_with(|| -> fn(Msg) { panic!("already registered") });
+
+        let old = match map.entry::<Msg>() {
+            Entry::Occupied(mut entry) => entry.replace(|_: Msg| {}),
+            Entry::Vacant(_) => panic!("handler should have been registered"),
+        };
+        assert!(old.into_fn::<Msg>().is_ok());
+
+        match map.entry::<Msg>() {
+            Entry::Occupied(entry) => { entry.remove(); }
+            Entry::Vacant(_) => panic!("handler should have been registered"),
+        }
+        assert!(!map.is_registered::<Msg>());
+    }
+
+    #[test]
+    fn or_default() {
+        struct Ignored;
+        struct Handled;
+
+        let acc = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        {
+            let acc = acc.clone();
+            map.insert(move |_: Handled| acc.set(acc.get() + 1));
+        }
+
+        map.entry::<Ignored>().or_default();
+        map.entry::<Handled>().or_default();
+
+        assert!(map.is_registered::<Ignored>());
+        assert!(map.call(Ignored));
+        assert!(map.call(Handled));
+        assert_eq!(acc.get(), 1);
+    }
+}
@@ -0,0 +1,68 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for routing each variant of a message enum to its own handler.
+
+use std::hash::BuildHasher;
+
+use {HandlerMap, HandlerStore};
+
+/// An enum of messages, each variant of which is dispatched to its own handler.
+///
+/// This is usually implemented with the `handler_enum!` macro, which also defines a message type
+/// for each variant, for handlers to be registered for.
+pub trait HandlerEnum {
+    /// Calls the handler registered in `map` for this value's variant, returning whether the
+    /// handler was registered.
+    fn dispatch<'a, S: BuildHasher, H: HandlerStore<'a>>(self, map: &HandlerMap<'a, S, H>) -> bool;
+}
+
+/// Calls the handler registered in `map` for the variant of the given enum value, returning
+/// whether the handler was registered.
+///
+/// See `handler_enum!` for an example.
+pub fn dispatch_enum<'a, S, H, E>(map: &HandlerMap<'a, S, H>, event: E) -> bool
+where
+    S: BuildHasher,
+    H: HandlerStore<'a>,
+    E: HandlerEnum,
+{
+    event.dispatch(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use {dispatch_enum, HandlerMap};
+
+    handler_enum! {
+        #[derive(Debug, Clone)]
+        enum Command {
+            Push(String) => Push,
+            Shout(String) => Shout,
+            Clear(()) => Clear,
+        }
+    }
+
+    #[test]
+    fn route_variants() {
+        let log = RefCell::new(Vec::new());
+        let mut map = HandlerMap::new();
+        map.insert(|Push(s)| log.borrow_mut().push(s));
+        map.insert(|Shout(s)| log.borrow_mut().push(s.to_uppercase()));
+
+        let commands = vec![
+            Command::Push("a".into()),
+            Command::Shout("b".into()),
+            Command::Clear(()),
+        ];
+        let handled = commands.into_iter().filter(|cmd| dispatch_enum(&map, cmd.clone())).count();
+
+        assert_eq!(handled, 2);
+        assert_eq!(*log.borrow(), ["a", "B"]);
+        assert!(!map.is_registered::<String>());
+    }
+}
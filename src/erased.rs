@@ -0,0 +1,627 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Public handle to a handler that has been removed from (or not yet added to) a `HandlerMap`.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
+use std::vec;
+
+use box_fn::BoxFn;
+use convert;
+use {Handler, HandlerMap, HandlerStore, Slot, SlotHandler};
+
+/// A type-erased handler, along with the identity of the message type it receives.
+///
+/// An `ErasedHandler` can be moved between maps without re-boxing its closure. Since it remembers
+/// which message type it was created for, it can only ever be registered for that type.
+///
+/// This also allows handlers for different message types to be collected together, for example
+/// to build a map with `FromIterator` or `Extend`:
+///
+/// ```rust
+/// use handler_map::{ErasedHandler, HandlerMap};
+///
+/// let map = vec![
+///     ErasedHandler::new(|_: u8| {}),
+///     ErasedHandler::new(|_: String| {}),
+/// ].into_iter().collect::<HandlerMap>();
+///
+/// assert!(map.is_registered::<u8>());
+/// assert!(map.is_registered::<String>());
+/// ```
+pub struct ErasedHandler<'a> {
+    /// The handler's slot, boxed to keep `ErasedHandler` small when it's returned in an `Err`.
+    slot: Box<Slot<'a>>,
+}
+
+/// Which handler to keep when both maps given to `HandlerMap::merge_with` have a handler for the
+/// same message type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Winner {
+    /// Keep the handler from the map being merged into.
+    KeepSelf,
+    /// Replace it with the handler from the map being merged in.
+    TakeOther,
+}
+
+impl<'a> ErasedHandler<'a> {
+    /// Erases the given handler, in the same way as `HandlerMap::insert` would.
+    pub fn new<T: Any, F: Handler<T> + 'a>(handler: F) -> ErasedHandler<'a> {
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        ErasedHandler::from_slot(Slot::new::<T>(SlotHandler::Value(ptr)))
+    }
+
+    pub(crate) fn from_slot(slot: Slot<'a>) -> ErasedHandler<'a> {
+        ErasedHandler { slot: Box::new(slot) }
+    }
+
+    pub(crate) fn into_parts(self) -> (TypeId, Slot<'a>) {
+        (self.slot.id, *self.slot)
+    }
+
+    /// Returns the `TypeId` of the message type this handler receives.
+    pub fn type_id(&self) -> TypeId {
+        self.slot.id
+    }
+
+    /// Returns the name of the message type this handler receives, as given by
+    /// `std::any::type_name`.
+    pub fn type_name(&self) -> &'static str {
+        self.slot.name
+    }
+
+    /// Converts this handler back into a callable closure, if it receives messages of type `T`.
+    ///
+    /// This only succeeds for handlers which receive their message by value, like those from
+    /// `insert` or `ErasedHandler::new`; otherwise, the handler is returned unchanged. The
+    /// returned closure only calls the handler itself: any validator or single-shot state that was
+    /// attached to it in a map is discarded.
+    ///
+    /// This allows two handlers for the same type to be composed into one, for example in the
+    /// resolver given to `HandlerMap::merge_with_resolver`.
+    pub fn into_fn<T: Any>(self) -> Result<impl Fn(T) + 'a, ErasedHandler<'a>> {
+        if self.slot.id != TypeId::of::<T>() {
+            return Err(self);
+        }
+
+        match self.slot.handler {
+            SlotHandler::Value(act) => Ok(move |msg: T| unsafe { act.call_erased(msg) }),
+            handler => Err(ErasedHandler::from_slot(Slot { handler, ..*self.slot })),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for ErasedHandler<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ErasedHandler")
+            .field("type_name", &self.slot.name)
+            .finish()
+    }
+}
+
+/// Registers each handler for its message type, replacing any handler already registered for
+/// that type, in the same way as `insert`.
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> Extend<ErasedHandler<'a>> for HandlerMap<'a, S, H> {
+    fn extend<I: IntoIterator<Item = ErasedHandler<'a>>>(&mut self, iter: I) {
+        for handler in iter {
+            let (id, slot) = handler.into_parts();
+            self.insert_slot(id, slot);
+        }
+    }
+}
+
+/// Un-registers every handler registered with `insert` and its variants, yielding each one along
+/// with the `TypeId` of its message type, in the order of the map's `HandlerStore`, which is no
+/// particular order unless the map was created with `HandlerMap::ordered` or another store.
+///
+/// Any other contents of the map, like the handlers registered with `insert_multi` or the fallback
+/// handler, are dropped when iteration starts. Handlers which haven't been yielded yet are
+/// dropped along with the iterator.
+impl<'a, S, H: HandlerStore<'a>> IntoIterator for HandlerMap<'a, S, H> {
+    type Item = (TypeId, ErasedHandler<'a>);
+    type IntoIter = IntoIter<'a>;
+
+    fn into_iter(mut self) -> IntoIter<'a> {
+        IntoIter {
+            inner: self.slots.drain().into_iter(),
+        }
+    }
+}
+
+/// Iterator over the handlers of a `HandlerMap`, created by its `IntoIterator` implementation.
+pub struct IntoIter<'a> {
+    inner: vec::IntoIter<(TypeId, Slot<'a>)>,
+}
+
+impl<'a> Iterator for IntoIter<'a> {
+    type Item = (TypeId, ErasedHandler<'a>);
+
+    fn next(&mut self) -> Option<(TypeId, ErasedHandler<'a>)> {
+        self.inner.next().map(|(id, slot)| (id, ErasedHandler::from_slot(slot)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for IntoIter<'a> {}
+
+impl<'a, S, H> FromIterator<ErasedHandler<'a>> for HandlerMap<'a, S, H>
+where
+    S: BuildHasher,
+    H: HandlerStore<'a> + Default,
+{
+    fn from_iter<I: IntoIterator<Item = ErasedHandler<'a>>>(iter: I) -> HandlerMap<'a, S, H> {
+        let mut map = HandlerMap::default();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Un-registers the handler for the given message type, returning it as an `ErasedHandler`.
+    ///
+    /// The handler can then be registered in another map with `put_handler`, without re-boxing
+    /// it. Any validator or single-shot state attached to it goes with it.
+    pub fn take_handler<T: Any>(&mut self) -> Option<ErasedHandler<'a>> {
+        self.slots.remove(&TypeId::of::<T>()).map(ErasedHandler::from_slot)
+    }
+
+    /// Un-registers every handler whose message type's `TypeId` satisfies the given predicate,
+    /// returning them as `ErasedHandler`s along with their `TypeId`s, in no particular order.
+    ///
+    /// This is the opposite of `retain`, except that the removed handlers are handed back
+    /// instead of being dropped, so they can be registered in another map with `put_handler`.
+    pub fn drain_by<P: FnMut(&TypeId) -> bool>(&mut self, mut pred: P)
+        -> Vec<(TypeId, ErasedHandler<'a>)>
+    {
+        let ids = self.slots.iter().map(|(id, _)| *id).filter(|id| pred(id)).collect::<Vec<_>>();
+        ids.into_iter()
+            .filter_map(|id| {
+                self.slots.remove(&id).map(|slot| (id, ErasedHandler::from_slot(slot)))
+            })
+            .collect()
+    }
+
+    /// Registers the given handler for the message type with the given `TypeId`, replacing any
+    /// handler already registered for it, in the same way as `insert`.
+    ///
+    /// If the handler receives some other message type, it's handed back instead, since calling
+    /// it with messages of the wrong type would pass it invalid data.
+    pub fn put_handler(&mut self, id: TypeId, handler: ErasedHandler<'a>)
+        -> Result<(), ErasedHandler<'a>>
+    {
+        if handler.slot.id != id {
+            return Err(handler);
+        }
+
+        self.insert_slot(id, *handler.slot);
+        Ok(())
+    }
+
+    /// Moves all the handlers from `other` into this map, replacing any handlers in this map for
+    /// the same message types.
+    ///
+    /// This follows the same rules as `insert`: when both maps have a handler for a message type,
+    /// the one from `other` wins. Handlers are moved without being re-boxed. See
+    /// `merge_with_resolver` for details on the other contents of `other`.
+    pub fn merge(&mut self, other: HandlerMap<'a, S, H>) {
+        self.merge_with_resolver(other, |_, _, theirs| theirs);
+    }
+
+    /// Moves all the handlers from `other` into this map, keeping the handlers already in this
+    /// map for the same message types.
+    ///
+    /// When both maps have a handler for a message type, the one from `other` is dropped. See
+    /// `merge_with_resolver` for details on the other contents of `other`.
+    pub fn merge_keep(&mut self, other: HandlerMap<'a, S, H>) {
+        self.merge_inner(other, true, |_, ours, _| ours);
+    }
+
+    /// Moves all the handlers from `other` into this map, calling `resolve` to decide which
+    /// handler to keep when both maps have a handler for the same message type.
+    ///
+    /// The resolver is only called for those conflicting message types, with the type's `TypeId`.
+    /// Handlers for the other types in `other` are moved in as they are. This is the same as
+    /// `merge_with_resolver`, for resolvers which only need to pick one of the two handlers; see
+    /// that method for details on the other contents of `other`.
+    ///
+    /// ```rust
+    /// use handler_map::{HandlerMap, Winner};
+    /// use std::any::TypeId;
+    ///
+    /// let mut core = HandlerMap::new();
+    /// core.insert(|_: u8| println!("core u8"));
+    /// core.insert(|_: u16| println!("core u16"));
+    ///
+    /// let mut plugin = HandlerMap::new();
+    /// plugin.insert(|_: u8| println!("plugin u8"));
+    /// plugin.insert(|_: u16| println!("plugin u16"));
+    /// plugin.insert(|_: u32| println!("plugin u32"));
+    ///
+    /// core.merge_with(plugin, |id| if id == TypeId::of::<u8>() {
+    ///     Winner::TakeOther
+    /// } else {
+    ///     Winner::KeepSelf
+    /// });
+    /// assert_eq!(core.len(), 3);
+    /// ```
+    pub fn merge_with<F>(&mut self, other: HandlerMap<'a, S, H>, mut resolve: F)
+    where
+        F: FnMut(TypeId) -> Winner,
+    {
+        self.merge_with_resolver(other, |id, ours, theirs| match resolve(id) {
+            Winner::KeepSelf => ours,
+            Winner::TakeOther => theirs,
+        });
+    }
+
+    /// Moves all the handlers from `other` into this map, calling `resolve` to decide what to do
+    /// when both maps have a handler for the same message type.
+    ///
+    /// The resolver receives the type's `TypeId`, then the handler from this map, then the
+    /// handler from `other`, and returns the handler to keep. It can return either of them, or a
+    /// new handler composed from both with `ErasedHandler::into_fn` and `ErasedHandler::new`.
+    /// Composing handlers is only possible when both take their message by value, since the
+    /// composed handler has to be able to call both of them with the same message type. The
+    /// returned handler is always registered for its own message type, even if the resolver
+    /// returns a handler for some other type.
+    ///
+    /// Every other kind of handler in `other` is moved in as well:
+    ///
+    /// - Handlers registered with `insert_multi`, `insert_multi_with_result`,
+    ///   `insert_multi_stoppable`, or `insert_multi_ref` are added after the ones already in this
+    ///   map, as if they were registered afterward.
+    /// - Handlers registered with `insert_with_result`, `insert_named`, `insert_signal`, or
+    ///   `insert_borrowed`, conversions registered with `register_conversion`, and the fallback
+    ///   handler set with `set_fallback`, replace the ones in this map for the same key, as if
+    ///   they were registered afterward. `merge_keep` keeps this map's ones instead.
+    ///
+    /// The rest of `other`'s state is dropped: its dead-letter queue, the messages and changes
+    /// waiting for `flush` and `apply_deferred`, its observers, its parent map, and its settings,
+    /// like the error policy, the handler limit, and the disabled groups.
+    pub fn merge_with_resolver<F>(&mut self, other: HandlerMap<'a, S, H>, resolve: F)
+    where
+        F: FnMut(TypeId, ErasedHandler<'a>, ErasedHandler<'a>) -> ErasedHandler<'a>,
+    {
+        self.merge_inner(other, false, resolve);
+    }
+
+    /// Moves the contents of `other` into this map, as described in `merge_with_resolver`.
+    ///
+    /// `resolve` is used for the handlers registered with `insert`. For the other keyed handlers,
+    /// `keep_self` decides which one to keep when both maps have one for the same key.
+    fn merge_inner<F>(&mut self, other: HandlerMap<'a, S, H>, keep_self: bool, mut resolve: F)
+    where
+        F: FnMut(TypeId, ErasedHandler<'a>, ErasedHandler<'a>) -> ErasedHandler<'a>,
+    {
+        let HandlerMap {
+            mut slots, listeners, result_listeners, stoppable_listeners, ref_listeners, responders,
+            named, signals, borrowed, conversions, fallback, ..
+        } = other;
+
+        for (id, theirs) in slots.drain() {
+            let (id, mut slot) = match self.slots.remove(&id) {
+                Some(ours) => {
+                    let ours = ErasedHandler::from_slot(ours);
+                    let theirs = ErasedHandler::from_slot(theirs);
+                    resolve(id, ours, theirs).into_parts()
+                }
+                None => (id, theirs),
+            };
+            slot.set_catch_drop_panics(self.catch_drop_panics);
+            self.slots.insert(id, slot);
+        }
+
+        for (id, theirs) in listeners {
+            let ours = self.listeners.entry(id).or_default();
+            for listener in theirs {
+                listener.insert_into(ours);
+            }
+        }
+        for (id, theirs) in result_listeners {
+            self.result_listeners.entry(id).or_default().extend(theirs);
+        }
+        for (id, theirs) in stoppable_listeners {
+            self.stoppable_listeners.entry(id).or_default().extend(theirs);
+        }
+        for (id, theirs) in ref_listeners {
+            self.ref_listeners.entry(id).or_default().extend(theirs);
+        }
+
+        merge_table(&mut self.responders, responders, keep_self);
+        merge_table(&mut self.named, named, keep_self);
+        merge_table(&mut self.signals, signals, keep_self);
+        merge_table(&mut self.borrowed, borrowed, keep_self);
+        convert::merge_conversions(&mut self.conversions, conversions, keep_self);
+        if fallback.is_some() && !(keep_self && self.fallback.is_some()) {
+            self.fallback = fallback;
+        }
+    }
+}
+
+/// Moves the entries of `theirs` into `ours`, replacing the entries in `ours` with the same key
+/// unless `keep_self` is set.
+fn merge_table<K: Hash + Eq, V>(ours: &mut HashMap<K, V>, theirs: HashMap<K, V>, keep_self: bool) {
+    for (key, value) in theirs {
+        if keep_self {
+            ours.entry(key).or_insert(value);
+        } else {
+            ours.insert(key, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use {ErasedHandler, HandlerMap};
+
+    #[test]
+    fn into_iter() {
+        use std::any::TypeId;
+
+        struct Guard(Rc<Cell<u32>>);
+
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let dropped = Rc::new(Cell::new(0));
+
+        let mut map = HandlerMap::new();
+        for _ in 0..2 {
+            let guard = Guard(dropped.clone());
+            map.insert(move |_: u32| { let _ = &guard; });
+        }
+        let guard = Guard(dropped.clone());
+        map.insert(move |_: u8| { let _ = &guard; });
+        assert_eq!(dropped.get(), 1);
+
+        let mut iter = map.into_iter();
+        assert_eq!(iter.len(), 2);
+        let (id, handler) = iter.next().unwrap();
+        assert_eq!(id, handler.type_id());
+        assert!(id == TypeId::of::<u32>() || id == TypeId::of::<u8>());
+        assert_eq!(dropped.get(), 1);
+
+        let mut other = HandlerMap::new();
+        other.put_handler(id, handler).unwrap();
+        drop(iter);
+        assert_eq!(dropped.get(), 2);
+        drop(other);
+        assert_eq!(dropped.get(), 3);
+    }
+
+    #[test]
+    fn take_and_put_handler() {
+        use std::any::TypeId;
+
+        let acc = Cell::new(0);
+        let mut from = HandlerMap::new();
+        from.insert(|n: u32| acc.set(acc.get() + n));
+
+        let handler = from.take_handler::<u32>().unwrap();
+        assert!(!from.is_registered::<u32>());
+        assert!(from.take_handler::<u32>().is_none());
+
+        let mut to = HandlerMap::new();
+        let handler = to.put_handler(TypeId::of::<u8>(), handler).unwrap_err();
+        assert!(!to.is_registered::<u8>());
+        to.put_handler(TypeId::of::<u32>(), handler).unwrap();
+
+        assert!(to.call(5u32));
+        assert_eq!(acc.get(), 5);
+    }
+
+    #[test]
+    fn drain_by() {
+        use std::any::TypeId;
+
+        let acc = Rc::new(Cell::new(0));
+        let mut from = HandlerMap::new();
+        {
+            let acc = acc.clone();
+            from.insert(move |n: u32| acc.set(acc.get() + n));
+        }
+        from.insert(|_: u8| {});
+        from.insert(|_: String| {});
+
+        let ints = [TypeId::of::<u32>(), TypeId::of::<u8>()];
+        let drained = from.drain_by(|id| ints.contains(id));
+        assert_eq!(drained.len(), 2);
+        assert_eq!(from.len(), 1);
+        assert!(from.is_registered::<String>());
+        assert_eq!(Rc::strong_count(&acc), 2);
+
+        let mut to = HandlerMap::new();
+        for (id, handler) in drained {
+            to.put_handler(id, handler).unwrap();
+        }
+        assert!(to.call(5u32));
+        assert!(to.call(5u8));
+        assert_eq!(acc.get(), 5);
+        assert!(from.drain_by(|_| false).is_empty());
+    }
+
+    #[test]
+    fn merge_with_resolver() {
+        #[derive(Clone)]
+        struct Shared;
+        struct OnlyTheirs;
+
+        let acc = Rc::new(Cell::new(0));
+        let mut ours = HandlerMap::new();
+        let mut theirs = HandlerMap::new();
+        {
+            let acc1 = acc.clone();
+            let acc2 = acc.clone();
+            let acc3 = acc.clone();
+            ours.insert(move |_: Shared| acc1.set(acc1.get() + 1));
+            theirs.insert(move |_: Shared| acc2.set(acc2.get() + 10));
+            theirs.insert(move |_: OnlyTheirs| acc3.set(acc3.get() + 100));
+        }
+
+        ours.merge_with_resolver(theirs, |_, a, b| {
+            let a = a.into_fn::<Shared>().unwrap();
+            let b = b.into_fn::<Shared>().unwrap();
+            ErasedHandler::new(move |msg: Shared| {
+                a(msg.clone());
+                b(msg);
+            })
+        });
+
+        assert!(ours.call(Shared));
+        assert_eq!(acc.get(), 11);
+        assert!(ours.call(OnlyTheirs));
+        assert_eq!(acc.get(), 111);
+    }
+
+    #[test]
+    fn merge() {
+        struct Shared;
+        struct OnlyTheirs;
+
+        let winner = Rc::new(Cell::new(""));
+        let make = |name: &'static str| {
+            let mut map = HandlerMap::new();
+            let a = winner.clone();
+            let b = winner.clone();
+            map.insert(move |_: Shared| a.set(name));
+            map.insert(move |_: OnlyTheirs| b.set(name));
+            map
+        };
+
+        let mut map = make("ours");
+        map.remove::<OnlyTheirs>();
+        map.merge(make("theirs"));
+        assert!(map.call(Shared));
+        assert_eq!(winner.get(), "theirs");
+
+        let mut map = make("ours");
+        map.remove::<OnlyTheirs>();
+        map.merge_keep(make("theirs"));
+        assert!(map.call(Shared));
+        assert_eq!(winner.get(), "ours");
+        assert!(map.call(OnlyTheirs));
+        assert_eq!(winner.get(), "theirs");
+    }
+
+    #[test]
+    fn merge_everything() {
+        use std::any::Any;
+
+        struct Ping;
+        struct Old;
+        struct New;
+
+        let log = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let make = |name: &'static str| {
+            let mut map = HandlerMap::new();
+            let (a, b, c, d) = (log.clone(), log.clone(), log.clone(), log.clone());
+            map.insert_named("ping", move |_: Ping| a.borrow_mut().push((name, "named")));
+            map.insert_signal::<Ping, _>(move || b.borrow_mut().push((name, "signal")));
+            map.insert_with_result(move |_: Ping| name);
+            map.insert(move |_: New| c.borrow_mut().push((name, "new")));
+            map.register_conversion(|_: Old| New);
+            map.set_fallback(move |_: &dyn Any| d.borrow_mut().push((name, "fallback")));
+            map
+        };
+
+        for &(keep, winner) in &[(false, "theirs"), (true, "ours")] {
+            let mut ours = HandlerMap::new();
+            if keep {
+                ours = make("ours");
+                ours.merge_keep(make("theirs"));
+            } else {
+                ours.merge(make("theirs"));
+            }
+
+            log.borrow_mut().clear();
+            assert!(ours.call_named("ping", Ping));
+            assert!(ours.signal::<Ping>());
+            assert_eq!(ours.call_with_result::<_, &str>(Ping), Some(winner));
+            assert!(ours.call(Old));
+            assert!(ours.call(5u8));
+            assert_eq!(*log.borrow(), [
+                (winner, "named"), (winner, "signal"), (winner, "new"), (winner, "fallback"),
+            ]);
+        }
+    }
+
+    #[test]
+    fn merge_with() {
+        use std::any::TypeId;
+
+        use Winner;
+
+        struct Keep;
+        struct Take;
+        struct OnlyTheirs;
+
+        let winner = Rc::new(Cell::new(""));
+        let make = |name: &'static str| {
+            let mut map = HandlerMap::new();
+            let (a, b, c) = (winner.clone(), winner.clone(), winner.clone());
+            map.insert(move |_: Keep| a.set(name));
+            map.insert(move |_: Take| b.set(name));
+            map.insert(move |_: OnlyTheirs| c.set(name));
+            map
+        };
+
+        let mut map = make("ours");
+        map.remove::<OnlyTheirs>();
+        let mut conflicts = Vec::new();
+        map.merge_with(make("theirs"), |id| {
+            conflicts.push(id);
+            if id == TypeId::of::<Take>() { Winner::TakeOther } else { Winner::KeepSelf }
+        });
+
+        conflicts.sort();
+        let mut expected = vec![TypeId::of::<Keep>(), TypeId::of::<Take>()];
+        expected.sort();
+        assert_eq!(conflicts, expected);
+
+        assert!(map.call(Keep));
+        assert_eq!(winner.get(), "ours");
+        assert!(map.call(Take));
+        assert_eq!(winner.get(), "theirs");
+        assert!(map.call(OnlyTheirs));
+        assert_eq!(winner.get(), "theirs");
+    }
+
+    #[test]
+    fn extend() {
+        let calls = Rc::new(Cell::new(0));
+        let handler = |n: u32| {
+            let calls = calls.clone();
+            move |_: u8| calls.set(calls.get() + n)
+        };
+
+        let mut map = HandlerMap::new();
+        map.insert(handler(1));
+        map.extend(vec![ErasedHandler::new(handler(10)), ErasedHandler::new(|_: u16| {})]);
+
+        assert_eq!(map.len(), 2);
+        assert!(map.call(0u8));
+        assert_eq!(calls.get(), 10);
+    }
+
+    #[test]
+    fn into_fn_mismatch() {
+        let handler = ErasedHandler::new(|_: u32| {});
+        let handler = handler.into_fn::<u64>().err().unwrap();
+        assert!(handler.into_fn::<u32>().is_ok());
+    }
+}
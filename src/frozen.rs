@@ -0,0 +1,148 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! An immutable, cheaply cloneable handle to a handler map whose handlers have all been
+//! registered.
+
+use std::any::{Any, TypeId};
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use {HandlerMap, HandlerStore, SyncHandlerMap};
+
+/// A handler map which can no longer be changed, created by `HandlerMap::freeze` or
+/// `SyncHandlerMap::freeze`.
+///
+/// Handlers can't be registered or removed through this handle, only called. The map is shared
+/// through an `Arc`, so cloning the handle is cheap, and every clone calls the same handlers.
+///
+/// A frozen `HandlerMap` is neither `Send` nor `Sync`, for the same reasons as the map itself. A
+/// frozen `SyncHandlerMap` is both, so it can be handed to as many threads as needed:
+///
+/// ```rust
+/// use handler_map::SyncHandlerMap;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::thread;
+///
+/// struct Job;
+///
+/// static COUNT: AtomicUsize = AtomicUsize::new(0);
+///
+/// let mut map = SyncHandlerMap::new();
+/// map.insert(|_: Job| { COUNT.fetch_add(1, Ordering::SeqCst); });
+/// let map = map.freeze();
+///
+/// let workers = (0..4).map(|_| {
+///     let map = map.clone();
+///     thread::spawn(move || assert!(map.call(Job)))
+/// }).collect::<Vec<_>>();
+///
+/// for worker in workers {
+///     worker.join().unwrap();
+/// }
+///
+/// assert_eq!(COUNT.load(Ordering::SeqCst), 4);
+/// ```
+#[derive(Debug)]
+pub struct FrozenHandlerMap<M = HandlerMap<'static>> {
+    map: Arc<M>,
+}
+
+impl<M> Clone for FrozenHandlerMap<M> {
+    fn clone(&self) -> Self {
+        FrozenHandlerMap {
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Converts this map into a `FrozenHandlerMap`, which can be cheaply cloned but no longer
+    /// changed.
+    pub fn freeze(self) -> FrozenHandlerMap<HandlerMap<'a, S, H>> {
+        FrozenHandlerMap {
+            map: Arc::new(self),
+        }
+    }
+}
+
+impl SyncHandlerMap {
+    /// Converts this map into a `FrozenHandlerMap`, which can be cheaply cloned but no longer
+    /// changed, and shared between threads.
+    pub fn freeze(self) -> FrozenHandlerMap<SyncHandlerMap> {
+        FrozenHandlerMap {
+            map: Arc::new(self),
+        }
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> FrozenHandlerMap<HandlerMap<'a, S, H>> {
+    /// Returns true if the given message type has a handler registered in the map.
+    pub fn is_registered<T: Any>(&self) -> bool {
+        self.map.is_registered::<T>()
+    }
+
+    /// Returns an iterator over the `TypeId`s of the message types with a handler registered in
+    /// the map, in an unspecified order.
+    pub fn registered_types<'b>(&'b self) -> impl Iterator<Item = TypeId> + use<'a, 'b, S, H> {
+        self.map.registered_types()
+    }
+
+    /// Calls the handler with the given message, returning whether the handler was registered.
+    pub fn call<T: Any>(&self, msg: T) -> bool {
+        self.map.call(msg)
+    }
+}
+
+impl FrozenHandlerMap<SyncHandlerMap> {
+    /// Returns true if the given message type has a handler registered in the map.
+    pub fn is_registered<T: Any + Send>(&self) -> bool {
+        self.map.is_registered::<T>()
+    }
+
+    /// Returns an iterator over the `TypeId`s of the message types with a handler registered in
+    /// the map, in an unspecified order.
+    pub fn registered_types<'b>(&'b self) -> impl Iterator<Item = TypeId> + use<'b> {
+        self.map.registered_types()
+    }
+
+    /// Calls the handler with the given message, returning whether the handler was registered.
+    ///
+    /// This can be called from several threads at once, even for the same message type.
+    pub fn call<T: Any + Send>(&self, msg: T) -> bool {
+        self.map.call(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+    use std::cell::Cell;
+
+    use {FrozenHandlerMap, HandlerMap, SyncHandlerMap};
+
+    #[test]
+    fn freeze() {
+        let total = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert(|n: u32| total.set(total.get() + n));
+
+        let frozen = map.freeze();
+        let copy = frozen.clone();
+        assert!(frozen.call(1u32));
+        assert!(copy.call(2u32));
+        assert!(!copy.call(3u8));
+        assert_eq!(total.get(), 3);
+
+        assert!(copy.is_registered::<u32>());
+        assert_eq!(frozen.registered_types().collect::<Vec<_>>(), [TypeId::of::<u32>()]);
+    }
+
+    #[test]
+    fn frozen_sync_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<FrozenHandlerMap<SyncHandlerMap>>();
+    }
+}
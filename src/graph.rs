@@ -0,0 +1,163 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Export of the relationships between the message types in a `HandlerMap`, for visualization.
+
+use std::any::TypeId;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use {HandlerKind, HandlerMap, HandlerStore};
+
+/// The cascade edges seen by `call_cascade`, keyed by the `TypeId`s of the emitting and emitted
+/// message types, along with the names of those types.
+pub(crate) type CascadeEdges = RefCell<HashMap<(TypeId, TypeId), (&'static str, &'static str)>>;
+
+/// The relationships between the message types in a `HandlerMap`, as returned by
+/// `HandlerMap::export_graph`.
+///
+/// This is a plain adjacency structure, meant to be rendered with an external tool, for example
+/// by converting it to DOT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DispatchGraph {
+    /// The message types in the graph, sorted by name.
+    pub nodes: Vec<GraphNode>,
+    /// The relationships between the message types, sorted by the names of the types they
+    /// connect.
+    pub edges: Vec<GraphEdge>,
+}
+
+/// A message type in a `DispatchGraph`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphNode {
+    /// The `TypeId` of the message type.
+    pub type_id: TypeId,
+    /// The name of the message type, as given by `std::any::type_name`.
+    pub type_name: &'static str,
+    /// The calling convention of the handler registered for the message type.
+    ///
+    /// This is `None` if the type only appears in the graph as the target of an edge, without a
+    /// handler registered for it.
+    pub kind: Option<HandlerKind>,
+}
+
+/// A relationship between two message types in a `DispatchGraph`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphEdge {
+    /// The `TypeId` of the message type the relationship starts from.
+    pub from: TypeId,
+    /// The `TypeId` of the message type the relationship leads to.
+    pub to: TypeId,
+    /// What kind of relationship this is.
+    pub kind: EdgeKind,
+}
+
+/// The kind of relationship represented by a `GraphEdge`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EdgeKind {
+    /// The handler for the `from` type, registered with `insert_cascade`, emitted a message of
+    /// the `to` type.
+    Cascade,
+    /// A conversion from the `from` type to the `to` type was registered with
+    /// `register_conversion`, so messages of the `from` type with no handler can be passed to the
+    /// handler for the `to` type.
+    Conversion,
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Exports the message types in this map, and the relationships between them, as a graph.
+    ///
+    /// Every message type with a handler registered with `insert` or one of its variants becomes
+    /// a node. Since cascade handlers can emit any message type, their edges can't be known when
+    /// they're registered; instead, every time `call_cascade` dispatches a follow-up message, the
+    /// map records an edge from the emitting type to the emitted one. The graph therefore shows
+    /// the cascades that have been observed so far, not every cascade that's possible.
+    ///
+    /// Conversions registered with `register_conversion` are known ahead of time, so every one of
+    /// them becomes an edge, whether or not a message has been converted yet.
+    pub fn export_graph(&self) -> DispatchGraph {
+        let mut nodes = self.slots.iter().map(|(id, slot)| GraphNode {
+            type_id: *id,
+            type_name: slot.name,
+            kind: Some(HandlerKind::of(&slot.handler)),
+        }).collect::<Vec<_>>();
+
+        let mut edges = self.cascade_edges.borrow().iter()
+            .map(|(&ids, &names)| (ids, names, EdgeKind::Cascade))
+            .collect::<Vec<_>>();
+        for (&from, convs) in &self.conversions {
+            for conv in convs {
+                edges.push(((from, conv.target), conv.names, EdgeKind::Conversion));
+            }
+        }
+        edges.sort_by_key(|&(_, names, kind)| (names, kind));
+
+        // types without a handler, including cascade handlers that have since been removed, still
+        // need nodes for their edges to point to
+        for &((from, to), (from_name, to_name), _) in &edges {
+            for &(type_id, type_name) in &[(from, from_name), (to, to_name)] {
+                if !nodes.iter().any(|n| n.type_id == type_id) {
+                    nodes.push(GraphNode { type_id, type_name, kind: None });
+                }
+            }
+        }
+        nodes.sort_by_key(|n| n.type_name);
+
+        DispatchGraph {
+            nodes,
+            edges: edges.into_iter().map(|((from, to), _, kind)| GraphEdge { from, to, kind })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+
+    use {Cascade, EdgeKind, HandlerKind, HandlerMap};
+
+    #[test]
+    fn export_graph() {
+        struct Start;
+        struct Logged;
+        struct Dropped;
+        struct Legacy;
+
+        let mut map = HandlerMap::new();
+        map.insert_cascade(|_: Start, c: &mut Cascade| {
+            c.emit(Logged);
+            c.emit(Dropped);
+            c.emit(Logged);
+        });
+        map.insert(|_: Logged| {});
+
+        let graph = map.export_graph();
+        assert_eq!(graph.nodes.len(), 2);
+        assert!(graph.edges.is_empty());
+
+        assert_eq!(map.call_cascade(Start, 1), Ok(3));
+        map.register_conversion(|_: Legacy| Logged);
+        map.register_conversion(|_: Dropped| Logged);
+        let graph = map.export_graph();
+
+        let kinds = graph.nodes.iter().map(|n| (n.type_id, n.kind)).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![
+            (TypeId::of::<Dropped>(), None),
+            (TypeId::of::<Legacy>(), None),
+            (TypeId::of::<Logged>(), Some(HandlerKind::Value)),
+            (TypeId::of::<Start>(), Some(HandlerKind::Cascade)),
+        ]);
+
+        let edges = graph.edges.iter().map(|e| (e.from, e.to, e.kind)).collect::<Vec<_>>();
+        assert_eq!(edges, vec![
+            (TypeId::of::<Dropped>(), TypeId::of::<Logged>(), EdgeKind::Conversion),
+            (TypeId::of::<Legacy>(), TypeId::of::<Logged>(), EdgeKind::Conversion),
+            (TypeId::of::<Start>(), TypeId::of::<Dropped>(), EdgeKind::Cascade),
+            (TypeId::of::<Start>(), TypeId::of::<Logged>(), EdgeKind::Cascade),
+        ]);
+    }
+}
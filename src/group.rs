@@ -0,0 +1,108 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for tagging handlers with a group, so that all the handlers in a group can be disabled
+//! and re-enabled at once.
+
+use std::any::{Any, TypeId};
+use std::hash::BuildHasher;
+
+use box_fn::BoxFn;
+use {Handler, HandlerMap, HandlerStore, Slot, SlotHandler};
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Registers a new handler into the map as part of the given group.
+    ///
+    /// Apart from being part of the group, this is the same as `insert`. While the group is
+    /// disabled with `set_group_enabled`, the handler stays registered, but isn't run: `call`
+    /// returns false, and `dispatch` returns `CallOutcome::Disabled`.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::cell::Cell;
+    ///
+    /// const AUDIO: u32 = 1;
+    ///
+    /// struct PlaySound;
+    ///
+    /// let played = Cell::new(0);
+    /// let mut map = HandlerMap::new();
+    /// map.insert_in_group(AUDIO, |_: PlaySound| played.set(played.get() + 1));
+    ///
+    /// map.set_group_enabled(AUDIO, false);
+    /// assert!(!map.call(PlaySound));
+    /// assert!(map.is_registered::<PlaySound>());
+    ///
+    /// map.set_group_enabled(AUDIO, true);
+    /// assert!(map.call(PlaySound));
+    /// assert_eq!(played.get(), 1);
+    /// ```
+    pub fn insert_in_group<T: Any, F: Handler<T> + 'a>(&mut self, group: u32, handler: F) {
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        let mut slot = Slot::new::<T>(SlotHandler::Value(ptr));
+        slot.group = Some(group);
+        self.insert_slot(id, slot);
+    }
+
+    /// Enables or disables the handlers in the given group.
+    ///
+    /// Groups are enabled until they're disabled with this method, including groups which don't
+    /// have any handlers yet, so a handler added to a disabled group with `insert_in_group` starts
+    /// out disabled.
+    pub fn set_group_enabled(&mut self, group: u32, enabled: bool) {
+        if enabled {
+            self.disabled_groups.remove(&group);
+        } else {
+            self.disabled_groups.insert(group);
+        }
+    }
+
+    /// Returns false if the given group was disabled with `set_group_enabled`.
+    pub fn is_group_enabled(&self, group: u32) -> bool {
+        !self.disabled_groups.contains(&group)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+    use std::cell::Cell;
+
+    use {CallOutcome, HandlerMap};
+
+    #[test]
+    fn groups() {
+        struct Play;
+        struct Render;
+
+        let played = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert_in_group(1, |_: Play| played.set(played.get() + 1));
+        map.insert_in_group(2, |_: Render| {});
+        map.insert(|_: u32| {});
+
+        map.set_group_enabled(1, false);
+        assert!(!map.is_group_enabled(1));
+        assert_eq!(map.dispatch(Play), CallOutcome::Disabled);
+        assert!(!map.would_run(&Play));
+        assert!(map.call_or_return(Play).is_err());
+        assert!(map.call_any(Box::new(Play)).is_err());
+        assert!(map.call(Render));
+        assert!(map.call(5u32));
+        assert_eq!(played.get(), 0);
+
+        map.set_group_enabled(1, true);
+        assert!(map.call(Play));
+        assert_eq!(played.get(), 1);
+
+        map.set_group_enabled(2, false);
+        map.insert(|_: Render| {});
+        assert!(map.call(Render));
+        assert_eq!(map.len(), 3);
+        assert!(map.contains_type_id(TypeId::of::<Play>()));
+    }
+}
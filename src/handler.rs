@@ -0,0 +1,60 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! The `Handler` trait, which names the functions and closures that `insert` accepts.
+
+/// Functions and closures which can be registered with `HandlerMap::insert` to receive messages
+/// of type `T`.
+///
+/// This is implemented for everything that implements `Fn(T)`, and exists so that a handler of
+/// the wrong kind is reported in terms of `insert`, rather than the internals of the map. Every
+/// method that takes a handler by value, like `try_insert`, `insert_lazy`, or `insert_in_group`,
+/// uses this bound too, so the same error is reported wherever the handler is registered. In
+/// particular, closures which mutate their captured state only implement `FnMut(T)`; register
+/// those with `insert_mut` instead:
+///
+/// ```rust,compile_fail
+/// use handler_map::HandlerMap;
+///
+/// let mut count = 0;
+/// let mut map = HandlerMap::new();
+/// map.insert(move |_: u32| count += 1);
+/// ```
+///
+/// The other insert methods report the same error:
+///
+/// ```rust,compile_fail
+/// use handler_map::HandlerMap;
+///
+/// let mut count = 0;
+/// let mut map = HandlerMap::new();
+/// map.insert_in_group(1, move |_: u32| count += 1);
+/// ```
+///
+/// Handlers also can't return anything, since the map would have nowhere to send the value.
+/// This catches handlers which return a function or closure instead of calling it. Handlers which
+/// return a response for the caller can be registered with `insert_with_result` instead:
+///
+/// ```rust,compile_fail
+/// use handler_map::HandlerMap;
+///
+/// fn parse(msg: String) -> Option<u32> {
+///     msg.parse().ok()
+/// }
+///
+/// let mut map = HandlerMap::new();
+/// map.insert(parse);
+/// ```
+///
+/// For these handlers, the compiler reports that it expected the handler to return `()`, and
+/// points at the value being returned.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be used as a handler for messages of type `{T}`",
+    label = "handler must implement `Fn({T})`",
+    note = "handlers which mutate their own state implement `FnMut`; use `insert_mut` for those",
+)]
+pub trait Handler<T>: Fn(T) {}
+
+impl<T, F: Fn(T)> Handler<T> for F {}
@@ -95,19 +95,541 @@
 //!
 //! assert!(!map.is_registered::<MyMessage>());
 //! ```
+//!
+//! The crate requires the standard library. Its hasher, clocks, and unwinding support are part of
+//! the public API, for example in the default hasher of `HandlerMap` and in `call_timed`, so an
+//! `alloc`-only mode isn't provided.
+
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "tower")]
+extern crate tower_service;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 
+#[macro_use]
+mod macros;
+
+mod async_handler;
+mod borrowed;
+mod builder;
+#[cfg(feature = "unsafe-internals")]
+pub mod box_fn;
+#[cfg(not(feature = "unsafe-internals"))]
 mod box_fn;
+mod cascade;
+mod cloneable;
+mod combinators;
+mod context;
+mod context_mut;
+mod convert;
+mod dead_letter;
+mod deferred;
+mod debug_info;
+mod discriminant;
+mod entry;
+mod enum_dispatch;
+mod erased;
+mod frozen;
+mod graph;
+mod group;
+mod handler;
+mod metrics;
+mod multi;
+mod named;
+mod parent;
+mod responder;
+mod registrar;
+mod responder_map;
+mod scoped;
+#[cfg(feature = "tower")]
+mod service;
+mod signal;
+mod store;
+mod sync;
+mod teardown;
+mod weak;
 
 use std::any::{Any, TypeId};
-use std::collections::HashMap;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
+use async_handler::BoxFuture;
+use borrowed::BorrowedHandlers;
 use box_fn::{BoxFn, Opaque};
+use dead_letter::DeadLetterQueue;
+use deferred::PendingOp;
+use graph::CascadeEdges;
+use metrics::{CallMetrics, TimingObserver};
+use multi::{ErrorObserver, Listener, StoppableListener};
+use convert::Conversions;
+use named::NamedHandlers;
+use responder::Responder;
+use signal::SignalHandlers;
+use teardown::Teardown;
+
+pub use async_handler::Deadline;
+pub use borrowed::Borrowed;
+pub use builder::HandlerMapBuilder;
+pub use cascade::{Cascade, DepthExceeded};
+pub use combinators::{combine, or};
+pub use context::ContextHandlerMap;
+pub use context_mut::HandlerMapMut;
+pub use debug_info::{HandlerDebugInfo, HandlerDescription, HandlerKind};
+pub use discriminant::DiscriminantMap;
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use enum_dispatch::{dispatch_enum, HandlerEnum};
+pub use erased::{ErasedHandler, IntoIter, Winner};
+pub use frozen::FrozenHandlerMap;
+pub use graph::{DispatchGraph, EdgeKind, GraphEdge, GraphNode};
+pub use handler::Handler;
+pub use metrics::HandlerStats;
+pub use multi::{ErrorPolicy, HandlerInfo, SelectionOutOfRange};
+pub use registrar::{RegisterFn, Registrar};
+pub use responder::DispatchError;
+pub use responder_map::ResponderMap;
+pub use scoped::Subscription;
+#[cfg(feature = "tower")]
+pub use service::HandlerMapService;
+pub use store::HandlerStore;
+pub use sync::SyncHandlerMap;
 
 /// Struct that maps types with functions or closures that can receive them.
 ///
+/// Since handlers don't need to be `Send` or `Sync`, neither is `HandlerMap`. See
+/// `ThreadLocalHandlerMap` for details.
+///
+/// The handlers registered with `insert` and its variants are kept in the `HandlerStore` `H`,
+/// which is a `HashMap` using the hasher `S` unless the map was created with `ordered` or
+/// `with_store`.
+///
+/// Handlers only need to live as long as the map's lifetime parameter, so they can borrow local
+/// data, as long as the map is dropped before that data is:
+///
+/// ```rust,compile_fail
+/// use handler_map::HandlerMap;
+/// use std::cell::Cell;
+///
+/// let mut map = HandlerMap::new();
+/// {
+///     let count = Cell::new(0);
+///     map.insert(|_: u32| count.set(count.get() + 1));
+/// }
+/// assert!(map.call(1u32));
+/// ```
+///
+/// If dropping a handler panics, because something it captured panics in its destructor, the
+/// panic is passed on to whatever dropped it, and the map's remaining handlers may be leaked. Use
+/// `set_catch_drop_panics` to catch those panics instead, so that the map's other handlers are
+/// still dropped, and dropping the map while unwinding from another panic doesn't abort the
+/// process.
+///
+/// A handler may call back into the map that's running it, if it can reach it, but only through
+/// a shared reference, since `call` only borrows the map immutably, and every method which
+/// changes the map's handlers needs a mutable one. A map shared through an `Rc<RefCell<_>>`
+/// panics with a `BorrowMutError` if a handler tries to borrow it mutably during a call; use
+/// `defer_insert` and `defer_remove` to change the map from inside a handler instead. Dropping a
+/// `Subscription` from `insert_scoped` during a call only disables its handler, until it's removed
+/// by `prune_dead`. So a handler is never dropped while it's running, even if it asks for its own
+/// removal.
+///
 /// See the [module-level documentation](index.html) for more information.
-#[derive(Default)]
-pub struct HandlerMap<'a>(HashMap<TypeId, BoxFn<'a, Opaque>>);
+pub struct HandlerMap<'a, S = RandomState, H = HashMap<TypeId, Slot<'a>, S>> {
+    /// The identity of this map, returned by `id`.
+    id: HandlerMapId,
+    /// The handlers registered with `insert` and its variants, keyed by their message type.
+    slots: H,
+    /// Handlers registered with `insert_multi`, several of which can exist for each message type.
+    listeners: HashMap<TypeId, Vec<Listener<'a>>>,
+    /// Handlers registered with `insert_multi_with_result`, keyed by their message and return
+    /// types.
+    result_listeners: HashMap<(TypeId, TypeId), Vec<BoxFn<'a, Opaque, Opaque>>>,
+    /// Handlers registered with `insert_multi_stoppable`, run in order by `call_all_stoppable`.
+    stoppable_listeners: HashMap<TypeId, Vec<StoppableListener<'a>>>,
+    /// Handlers registered with `insert_multi_ref`, which receive a `*const T`, run in order by
+    /// `call_ref_all`.
+    ref_listeners: HashMap<TypeId, Vec<BoxFn<'a, Opaque>>>,
+    /// Handlers registered with `insert_with_result`, which receive a boxed message and return a
+    /// boxed response.
+    responders: HashMap<TypeId, Responder<'a>>,
+    /// Handlers registered with `insert_named`, keyed by their message type and name.
+    named: NamedHandlers<'a>,
+    /// Handlers registered with `insert_signal`, keyed by their marker type.
+    signals: SignalHandlers<'a>,
+    /// Handlers registered with `insert_borrowed`, keyed by the `'static` form of their message
+    /// type.
+    borrowed: BorrowedHandlers<'a>,
+    /// Conversions registered with `register_conversion`, keyed by the type they convert from.
+    conversions: Conversions<'a>,
+    /// What `call_all_fallible` does when a handler returns an error.
+    error_policy: ErrorPolicy,
+    /// Observer for the errors skipped by `call_all_fallible` under `ErrorPolicy::Continue`.
+    error_observer: Option<ErrorObserver<'a>>,
+    /// The follow-up messages seen by `call_cascade`, for `export_graph`.
+    cascade_edges: CascadeEdges,
+    /// The catch-all handler set with `set_fallback`, which receives messages with no handler.
+    fallback: Option<Fallback<'a>>,
+    /// Observer set with `set_tee`, which sees every message before it's dispatched.
+    tee: Option<Tee<'a>>,
+    /// Observer set with `on_replace`, which is told when `insert` replaces a handler.
+    replace_observer: Option<ReplaceObserver<'a>>,
+    /// If enabled with `enable_dead_letter`, the queue of messages which were dispatched without
+    /// a registered handler.
+    dead_letters: Option<RefCell<DeadLetterQueue<'a, S, H>>>,
+    /// Messages added with `enqueue`, waiting to be dispatched by `flush`.
+    deferred: Vec<Box<dyn Any>>,
+    /// Changes requested with `defer_insert` or `defer_remove`, waiting to be made by
+    /// `apply_deferred`.
+    pending_ops: RefCell<Vec<PendingOp<'a>>>,
+    /// The maximum number of message types that `try_insert_bounded` will register handlers for.
+    max_handlers: Option<usize>,
+    /// The groups disabled with `set_group_enabled`, whose handlers aren't run.
+    disabled_groups: HashSet<u32>,
+    /// The map set with `with_parent`, which receives the messages this map has no handler for.
+    parent: Option<Rc<HandlerMap<'a, S, H>>>,
+    /// If the map was created with `with_metrics`, the number of messages dispatched to it.
+    metrics: Option<CallMetrics>,
+    /// Observer set with `set_timing_observer`, which is told how long each handler took to run.
+    timing_observer: Option<TimingObserver<'a>>,
+    /// Whether panics from dropping the handlers registered with `insert` and its variants are
+    /// caught, as set by `set_catch_drop_panics`.
+    catch_drop_panics: bool,
+    /// Marker to ensure that the map is neither `Send` nor `Sync`, since the handlers inside it
+    /// may not be.
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Catch-all handler set with `HandlerMap::set_fallback`.
+type Fallback<'a> = Box<dyn Fn(&dyn Any) + 'a>;
+
+/// Observer set with `HandlerMap::set_tee`, which receives every message dispatched to the map.
+type Tee<'a> = Box<dyn Fn(&dyn Any) + 'a>;
+
+/// Observer set with `HandlerMap::on_replace`, which receives the message type whose handler was
+/// replaced.
+type ReplaceObserver<'a> = Box<dyn Fn(TypeId) + 'a>;
+
+/// A `HandlerMap` which must stay on the thread that created it.
+///
+/// This is the same type as `HandlerMap`; the alias exists to document the single-thread contract
+/// at use sites. Since the handlers in a `HandlerMap` may capture things like `Rc` or `RefCell`,
+/// the map is explicitly marked as neither `Send` nor `Sync`, rather than relying on the auto
+/// traits of its contents:
+///
+/// ```rust,compile_fail
+/// fn assert_send<T: Send>() {}
+/// assert_send::<handler_map::ThreadLocalHandlerMap>();
+/// ```
+///
+/// ```rust,compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<handler_map::ThreadLocalHandlerMap>();
+/// ```
+pub type ThreadLocalHandlerMap<'a, S = RandomState, H = HashMap<TypeId, Slot<'a>, S>> =
+    HandlerMap<'a, S, H>;
+
+/// A registered handler, along with the information about its message type that was captured
+/// when it was inserted.
+///
+/// Slots are opaque outside of this crate; they're only exposed so that a `HandlerStore` can hold
+/// them.
+pub struct Slot<'a> {
+    /// The `TypeId` of the message type, checked against the type of each message in debug builds
+    /// before it's passed to the handler.
+    id: TypeId,
+    /// The name of the message type, as given by `std::any::type_name`.
+    name: &'static str,
+    handler: SlotHandler<'a>,
+    /// If the type was marked with `mark_single_shot`, whether its handler has been run since
+    /// then.
+    single_shot: Option<Cell<bool>>,
+    /// If the handler was registered with `insert_validated`, the validator to run on messages
+    /// before they're passed to the handler. The validator receives a `*const T`.
+    validator: Option<BoxFn<'a, Opaque, Result<(), ValidationError>>>,
+    /// If the handler was registered with `insert_fn`, the address of the function.
+    fn_addr: Option<usize>,
+    /// If the handler was registered with `insert_in_group`, the group it belongs to.
+    group: Option<u32>,
+    /// If the handler was registered with `insert_weak`, whether it has found the value it was
+    /// registered for to be dropped, shared with the handler, which sets it. If the handler was
+    /// registered with `insert_scoped`, whether its `Subscription` was dropped, shared with the
+    /// guard, which sets it.
+    dead: Option<Rc<Cell<bool>>>,
+    /// If the handler was registered with `insert_with_teardown`, the closure to run when it's
+    /// removed.
+    teardown: Option<Teardown<'a>>,
+    /// If the handler was registered with `insert_with_default`, a function which creates a
+    /// default message for `exercise_all`.
+    make_default: Option<fn() -> Box<dyn Any>>,
+    /// Function which dispatches a boxed message to this slot, monomorphized for the message type
+    /// when the slot was created.
+    run_boxed: RunBoxed<'a>,
+    /// Function which downcasts a message passed by reference and runs it through
+    /// `Slot::run_ref`, monomorphized for the message type when the slot was created.
+    run_dyn: RunDyn<'a>,
+}
+
+/// Function which downcasts a boxed message and runs it through `Slot::run`.
+type RunBoxed<'a> = fn(&Slot<'a>, Box<dyn Any>, &dyn Fn() -> Vec<&'static str>)
+    -> Result<CallOutcome, Box<dyn Any>>;
+
+/// Function which downcasts a message passed by reference and runs it through `Slot::run_ref`.
+type RunDyn<'a> = fn(&Slot<'a>, &dyn Any) -> bool;
+
+impl<'a> Slot<'a> {
+    /// Creates a new slot for the message type `T`.
+    fn new<T: Any>(handler: SlotHandler<'a>) -> Slot<'a> {
+        fn run_boxed<'a, T: Any>(
+            slot: &Slot<'a>,
+            msg: Box<dyn Any>,
+            names: &dyn Fn() -> Vec<&'static str>,
+        ) -> Result<CallOutcome, Box<dyn Any>> {
+            let msg = msg.downcast::<T>()?;
+            if let SlotHandler::Raw(ref act) = slot.handler {
+                // pass the box straight through, rather than unboxing it for `run` to box again
+                if let Err(outcome) = slot.admit(&*msg) {
+                    return Ok(outcome);
+                }
+
+                #[cfg(feature = "tracing")]
+                let _span = tracing::info_span!("handler", msg = slot.name).entered();
+
+                act.call(msg);
+                return Ok(CallOutcome::Handled);
+            }
+            slot.run(*msg, names, None).map_err(|msg| Box::new(msg) as Box<dyn Any>)
+        }
+
+        fn run_dyn<'a, T: Any>(slot: &Slot<'a>, msg: &dyn Any) -> bool {
+            msg.downcast_ref::<T>().is_some_and(|msg| slot.run_ref(msg))
+        }
+
+        Slot {
+            id: TypeId::of::<T>(),
+            name: std::any::type_name::<T>(),
+            handler,
+            single_shot: None,
+            validator: None,
+            fn_addr: None,
+            group: None,
+            dead: None,
+            teardown: None,
+            make_default: None,
+            run_boxed: run_boxed::<T>,
+            run_dyn: run_dyn::<T>,
+        }
+    }
+
+    /// Creates a new slot for a handler registered with `insert_raw` for the message type with the
+    /// given `TypeId`.
+    fn raw(id: TypeId, handler: SlotHandler<'a>) -> Slot<'a> {
+        fn run_raw<'a>(
+            slot: &Slot<'a>,
+            msg: Box<dyn Any>,
+            _names: &dyn Fn() -> Vec<&'static str>,
+        ) -> Result<CallOutcome, Box<dyn Any>> {
+            // raw slots never have a validator, since `insert_validated` always replaces the slot
+            // with a typed one, so the only check to make is the single-shot state
+            if let Some(ref consumed) = slot.single_shot {
+                if consumed.replace(true) {
+                    return Ok(CallOutcome::AlreadyConsumed);
+                }
+            }
+
+            match slot.handler {
+                SlotHandler::Raw(ref act) => act.call(msg),
+                _ => unreachable!(),
+            }
+            Ok(CallOutcome::Handled)
+        }
+
+        // raw handlers receive their message boxed, so they can't be called by reference
+        fn run_dyn_raw<'a>(_slot: &Slot<'a>, _msg: &dyn Any) -> bool {
+            false
+        }
+
+        Slot {
+            id,
+            name: "<raw>",
+            handler,
+            single_shot: None,
+            validator: None,
+            fn_addr: None,
+            group: None,
+            dead: None,
+            teardown: None,
+            make_default: None,
+            run_boxed: run_raw,
+            run_dyn: run_dyn_raw,
+        }
+    }
+
+    /// Runs the handler in this slot with the given message, if it's a handler that `call` runs,
+    /// and if `elapsed` is given, stores the time the handler took to run in it.
+    ///
+    /// `names` is called to get the names of the registered message types, if the handler needs
+    /// them. If the handler isn't one that `call` runs, the message is handed back.
+    fn run<T: Any>(
+        &self,
+        mut msg: T,
+        names: &dyn Fn() -> Vec<&'static str>,
+        elapsed: Option<&mut Option<Duration>>,
+    ) -> Result<CallOutcome, T> {
+        self.debug_check_type::<T>();
+
+        match self.handler {
+            SlotHandler::Async(_) | SlotHandler::Cascade(_) => return Err(msg),
+            SlotHandler::Once(_, ref spent) if spent.get() => return Err(msg),
+            SlotHandler::Value(_) | SlotHandler::Registry(_) | SlotHandler::Ref(_) |
+                SlotHandler::MutRef(_) | SlotHandler::Filter(_) | SlotHandler::Raw(_) |
+                SlotHandler::Once(..) => {}
+        }
+
+        if let Err(outcome) = self.admit(&msg) {
+            return Ok(outcome);
+        }
+
+        let names = match self.handler {
+            SlotHandler::Registry(_) => names(),
+            _ => Vec::new(),
+        };
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("handler", msg = self.name).entered();
+
+        let start = elapsed.as_ref().map(|_| Instant::now());
+        let mut outcome = CallOutcome::Handled;
+        match self.handler {
+            SlotHandler::Value(ref act) => unsafe { act.call_erased(msg) },
+            SlotHandler::Filter(ref act) => {
+                if !unsafe { act.call_erased(msg) } {
+                    outcome = CallOutcome::Declined;
+                }
+            }
+            SlotHandler::Registry(ref act) => unsafe {
+                act.call_erased((&names[..] as *const [&'static str], msg))
+            },
+            SlotHandler::Ref(ref act) => unsafe { act.call_erased(&msg as *const T) },
+            SlotHandler::MutRef(ref act) => unsafe { act.call_erased(&mut msg as *mut T) },
+            SlotHandler::Raw(ref act) => act.call(Box::new(msg)),
+            SlotHandler::Once(ref act, ref spent) => {
+                spent.set(true);
+                unsafe { act.call_erased(msg) }
+            }
+            SlotHandler::Async(_) | SlotHandler::Cascade(_) => unreachable!(),
+        }
+        if let (Some(elapsed), Some(start)) = (elapsed, start) {
+            *elapsed = Some(start.elapsed());
+        }
+
+        Ok(outcome)
+    }
+
+    /// Runs the handler in this slot with a reference to the given message, if it was registered
+    /// with `insert_ref`, returning whether it was handled, in the same way as `call_ref`.
+    fn run_ref<T: Any>(&self, msg: &T) -> bool {
+        match self.handler {
+            SlotHandler::Ref(ref act) => match self.admit(msg) {
+                Ok(()) => {
+                    unsafe { act.call_erased(msg as *const T) };
+                    true
+                }
+                Err(outcome) => outcome == CallOutcome::AlreadyConsumed,
+            },
+            _ => false,
+        }
+    }
+
+    /// Runs the validator for this slot on a message, then marks a single-shot handler as
+    /// consumed, returning the outcome of the call if the handler shouldn't run.
+    fn admit<T: Any>(&self, msg: &T) -> Result<(), CallOutcome> {
+        self.debug_check_type::<T>();
+
+        if let Some(ref validator) = self.validator {
+            if let Err(e) = unsafe { validator.call_erased(msg as *const T) } {
+                return Err(CallOutcome::ValidationFailed(e));
+            }
+        }
+
+        if let Some(ref consumed) = self.single_shot {
+            if consumed.replace(true) {
+                return Err(CallOutcome::AlreadyConsumed);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// In debug builds, asserts that `T` is the message type this slot was created for, before a
+    /// message of that type is passed to its handler or validator.
+    fn debug_check_type<T: Any>(&self) {
+        debug_assert!(
+            self.id == TypeId::of::<T>(),
+            "handler for `{}` was called with a `{}`",
+            self.name,
+            std::any::type_name::<T>(),
+        );
+    }
+
+    /// Sets whether a panic from dropping this slot's handler or validator is caught, as set for
+    /// the whole map with `HandlerMap::set_catch_drop_panics`.
+    fn set_catch_drop_panics(&mut self, enabled: bool) {
+        match self.handler {
+            SlotHandler::Value(ref mut act) | SlotHandler::Registry(ref mut act) |
+                SlotHandler::Cascade(ref mut act) | SlotHandler::Ref(ref mut act) |
+                SlotHandler::MutRef(ref mut act) | SlotHandler::Once(ref mut act, _) => {
+                act.set_catch_drop_panics(enabled)
+            }
+            SlotHandler::Async(ref mut act) => act.set_catch_drop_panics(enabled),
+            SlotHandler::Filter(ref mut act) => act.set_catch_drop_panics(enabled),
+            SlotHandler::Raw(ref mut act) => act.set_catch_drop_panics(enabled),
+        }
+        if let Some(ref mut validator) = self.validator {
+            validator.set_catch_drop_panics(enabled);
+        }
+    }
+}
+
+/// An erased handler, tagged with the calling convention it was registered with.
+enum SlotHandler<'a> {
+    /// A handler registered with `insert`, which receives its message by value.
+    Value(BoxFn<'a, Opaque>),
+    /// A handler registered with `insert_async`, which returns a future to be run by the caller.
+    Async(BoxFn<'a, Opaque, BoxFuture<'a>>),
+    /// A handler registered with `insert_with_registry`, which receives the names of the
+    /// registered message types along with its message, as a `(*const [&'static str], T)`.
+    Registry(BoxFn<'a, Opaque>),
+    /// A handler registered with `insert_cascade`, which receives a collector for follow-up
+    /// messages along with its message, as a `(T, *mut Cascade<'a>)`.
+    Cascade(BoxFn<'a, Opaque>),
+    /// A handler registered with `insert_ref`, which receives its message by reference, as a
+    /// `*const T`.
+    Ref(BoxFn<'a, Opaque>),
+    /// A handler registered with `insert_mut_ref`, which receives its message by mutable
+    /// reference, as a `*mut T`.
+    MutRef(BoxFn<'a, Opaque>),
+    /// A handler registered with `insert_filter`, which returns whether it accepted its message.
+    Filter(BoxFn<'a, Opaque, bool>),
+    /// A handler registered with `insert_raw`, which receives its message boxed.
+    Raw(BoxFn<'a, Box<dyn Any>>),
+    /// A handler registered with `insert_once`, along with whether it has been run.
+    Once(BoxFn<'a, Opaque>, Cell<bool>),
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a> + Default> Default for HandlerMap<'a, S, H> {
+    fn default() -> HandlerMap<'a, S, H> {
+        HandlerMap::from_store(H::default())
+    }
+}
 
 impl<'a> HandlerMap<'a> {
     /// Creates a new map with no handlers.
@@ -115,91 +637,3258 @@ impl<'a> HandlerMap<'a> {
         Self::default()
     }
 
+    /// Creates a new map with no handlers, with room for at least `capacity` message types
+    /// before it needs to reallocate.
+    pub fn with_capacity(capacity: usize) -> HandlerMap<'a> {
+        let mut map = Self::new();
+        map.reserve(capacity);
+        map
+    }
+
+    /// Creates a new map with no handlers, which keeps the handlers registered with `insert` and
+    /// its variants ordered by the `TypeId` of their message type, rather than in a hash table.
+    ///
+    /// Everything which goes through these handlers in turn, like `registered_types`,
+    /// `iter_debug`, `retain`, `exercise_all`, or iterating over the map with `into_iter`, then
+    /// visits them in the same order every time the program is run, rather than an order that
+    /// changes with each new hasher. This is meant for things like tests which compare that
+    /// output against a recorded copy. The order itself is arbitrary, and may change when the
+    /// program is compiled differently, since it follows the values of the `TypeId`s.
+    ///
+    /// Apart from the order, an ordered map behaves the same as any other. Looking up a handler
+    /// takes time logarithmic in the number of handlers, rather than constant time, and the map
+    /// allocates as it grows, so `reserve` has no effect, and `capacity` is the same as `len`.
+    /// The map keeps its handlers in a `BTreeMap`, as its `HandlerStore`.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    ///
+    /// let mut map = HandlerMap::ordered();
+    /// map.insert(|_: u8| {});
+    /// map.insert(|_: String| {});
+    /// map.insert(|_: bool| {});
+    ///
+    /// let first = map.registered_types().collect::<Vec<_>>();
+    /// let mut sorted = first.clone();
+    /// sorted.sort();
+    /// assert_eq!(first, sorted);
+    /// ```
+    pub fn ordered() -> HandlerMap<'a, RandomState, BTreeMap<TypeId, Slot<'a>>> {
+        HandlerMap::from_store(BTreeMap::new())
+    }
+}
+
+impl<'a, S: BuildHasher> HandlerMap<'a, S> {
+    /// Creates a new map with no handlers, which uses the given hash builder to hash the
+    /// `TypeId`s of message types.
+    ///
+    /// The default hasher, `RandomState`, is resistant to denial-of-service attacks, which
+    /// `TypeId`s don't need protection from. In maps which are called often, a faster hasher can
+    /// reduce the cost of looking up each handler. This hasher is used to look up the handlers
+    /// registered with `insert` and its variants; the other kinds of handlers always use the
+    /// default hasher.
+    pub fn with_hasher(hasher: S) -> HandlerMap<'a, S> {
+        HandlerMap::from_store(HashMap::with_hasher(hasher))
+    }
+
+    /// Returns an estimate of how many buckets of the map's hash table are shared by more than one
+    /// registered message type, under the map's hasher.
+    ///
+    /// Message types which share a bucket take longer to look up, since all but one of them have
+    /// to be probed for elsewhere in the table. This is a diagnostic to help decide whether a map
+    /// would benefit from a different hasher given to `with_hasher`. The standard library doesn't
+    /// expose the layout of its hash table, so this models the table as `capacity` buckets, and
+    /// counts the buckets that the hash values of two or more message types fall into, modulo
+    /// `capacity`. The real table may have a different number of buckets and pick them from
+    /// different bits of the hash, so treat the result as a guide rather than an exact
+    /// measurement, and compare hashers with it rather than reading it in isolation.
+    ///
+    /// Like `len`, this only covers the handlers registered with `insert` and its variants. It's
+    /// only available for maps which keep those handlers in the default `HashMap`, rather than
+    /// another `HandlerStore`.
+    pub fn bucket_collisions(&self) -> usize {
+        let capacity = self.slots.capacity();
+        if capacity == 0 {
+            return 0;
+        }
+
+        let mut counts = HashMap::new();
+        for id in self.slots.keys() {
+            let bucket = self.slots.hasher().hash_one(id) % capacity as u64;
+            *counts.entry(bucket).or_insert(0) += 1;
+        }
+        counts.values().filter(|&&count| count > 1).count()
+    }
+}
+
+impl<'a, H: HandlerStore<'a>> HandlerMap<'a, RandomState, H> {
+    /// Creates a new map with no handlers, which keeps the handlers registered with `insert` and
+    /// its variants in the given store, rather than in a hash table.
+    ///
+    /// The store decides how handlers are looked up, so the map's hasher parameter is left as
+    /// `RandomState`, and only the other kinds of handlers use it. See `HandlerStore` for an
+    /// example.
+    pub fn with_store(store: H) -> HandlerMap<'a, RandomState, H> {
+        HandlerMap::from_store(store)
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Creates a new map with no handlers, which keeps the handlers registered with `insert` and
+    /// its variants in the given store.
+    fn from_store(store: H) -> HandlerMap<'a, S, H> {
+        HandlerMap {
+            id: HandlerMapId::next(),
+            slots: store,
+            listeners: HashMap::new(),
+            result_listeners: HashMap::new(),
+            stoppable_listeners: HashMap::new(),
+            ref_listeners: HashMap::new(),
+            responders: HashMap::new(),
+            named: HashMap::new(),
+            signals: HashMap::new(),
+            borrowed: HashMap::new(),
+            conversions: HashMap::new(),
+            error_policy: ErrorPolicy::default(),
+            error_observer: None,
+            cascade_edges: CascadeEdges::default(),
+            fallback: None,
+            tee: None,
+            replace_observer: None,
+            dead_letters: None,
+            deferred: Vec::new(),
+            pending_ops: RefCell::new(Vec::new()),
+            max_handlers: None,
+            disabled_groups: HashSet::new(),
+            parent: None,
+            metrics: None,
+            timing_observer: None,
+            catch_drop_panics: false,
+            _not_send: PhantomData,
+        }
+    }
+
     /// Registers a new handler into the map.
-    pub fn insert<T: Any, F: Fn(T) + 'a>(&mut self, handler: F) {
+    pub fn insert<T: Any, F: Handler<T> + 'a>(&mut self, handler: F) {
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        self.insert_slot(id, Slot::new::<T>(SlotHandler::Value(ptr)));
+    }
+
+    /// Registers a new handler into the map, unless a handler is already registered for its
+    /// message type.
+    ///
+    /// If a handler is already registered, the map is left unchanged, and the given handler is
+    /// handed back.
+    pub fn try_insert<T: Any, F: Handler<T> + 'a>(&mut self, handler: F) -> Result<(), F> {
+        if self.is_registered::<T>() {
+            Err(handler)
+        } else {
+            self.insert(handler);
+            Ok(())
+        }
+    }
+
+    /// Registers a handler for `T` which does nothing, replacing any handler already registered
+    /// for it.
+    ///
+    /// This marks `T` as a message type which is known, but deliberately dropped: `call` returns
+    /// true for it, and it never reaches the fallback handler or the dead-letter queue, which can
+    /// then be kept for message types that weren't expected at all. To register a no-op handler
+    /// only if `T` has no handler yet, use `entry::<T>().or_default()` instead.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::cell::Cell;
+    ///
+    /// struct Heartbeat;
+    /// struct Unknown;
+    ///
+    /// let missed = Cell::new(0);
+    /// let mut map = HandlerMap::new();
+    /// map.set_fallback(|_| missed.set(missed.get() + 1));
+    /// map.ignore::<Heartbeat>();
+    ///
+    /// assert!(map.call(Heartbeat));
+    /// assert!(map.call(Unknown));
+    /// assert_eq!(missed.get(), 1);
+    /// ```
+    pub fn ignore<T: Any>(&mut self) {
+        self.insert(|_: T| {});
+    }
+
+    /// Registers a new handler into the map, along with a way to create a default message for it,
+    /// so that it can be run by `exercise_all`.
+    ///
+    /// Apart from that, this is the same as `insert`.
+    pub fn insert_with_default<T: Any + Default, F: Handler<T> + 'a>(&mut self, handler: F) {
+        fn make_default<T: Any + Default>() -> Box<dyn Any> {
+            Box::new(T::default())
+        }
+
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let mut slot = Slot::new::<T>(SlotHandler::Value(ptr));
+        slot.make_default = Some(make_default::<T>);
+        self.insert_slot(TypeId::of::<T>(), slot);
+    }
+
+    /// Calls every handler registered with `insert_with_default` with a default message,
+    /// returning how many of them ran.
+    ///
+    /// Handlers registered some other way are skipped. This is meant for smoke tests, to check
+    /// that every handler can at least receive a message without panicking. Each message is
+    /// dispatched with `call_any`, in no particular order.
+    pub fn exercise_all(&self) -> usize {
+        self.slots.iter()
+            .filter_map(|(_, slot)| slot.make_default)
+            .filter(|make| self.call_any(make()).unwrap_or(false))
+            .count()
+    }
+
+    /// Registers a function pointer as the handler for its message type, returning whether the
+    /// same function was already registered for it.
+    ///
+    /// Apart from the return value, this is the same as `insert`. Only handlers registered with
+    /// this method are compared; a handler registered some other way is never considered the
+    /// same. Function pointers are compared by address, which isn't guaranteed to be unique: the
+    /// same function may have different addresses in different crates, and different functions
+    /// with the same body may be merged into one. This is meant to catch accidental
+    /// double-registration during development, not to identify functions.
+    pub fn insert_fn<T: Any>(&mut self, handler: fn(T)) -> bool {
+        let addr = handler as usize;
+        let id = TypeId::of::<T>();
+        let same = self.slots.get(&id).is_some_and(|slot| slot.fn_addr == Some(addr));
+
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let mut slot = Slot::new::<T>(SlotHandler::Value(ptr));
+        slot.fn_addr = Some(addr);
+        self.insert_slot(id, slot);
+        same
+    }
+
+    /// Registers a new handler into the map which receives its message by reference.
+    ///
+    /// This handler takes the place of any other handler registered for the same type, and
+    /// vice-versa. It can be called with `call_ref`, without giving up the message, as well as
+    /// with `call`, in which case the message is dropped after the handler returns.
+    pub fn insert_ref<T: Any, F: Fn(&T) + 'a>(&mut self, handler: F) {
+        let handler = move |msg: *const T| handler(unsafe { &*msg });
         let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
         let id = TypeId::of::<T>();
 
-        self.0.insert(id, ptr);
+        self.insert_slot(id, Slot::new::<T>(SlotHandler::Ref(ptr)));
     }
 
-    /// Un-registers the handler for the given type from this map.
-    pub fn remove<T: Any>(&mut self) {
+    /// Registers a new handler into the map which receives its message by mutable reference.
+    ///
+    /// This handler takes the place of any other handler registered for the same type, and
+    /// vice-versa. It can be called with `call_mut_ref`, so that the caller keeps the message
+    /// along with the handler's changes to it, as well as with `call`, in which case the message
+    /// is dropped after the handler returns.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    ///
+    /// struct Request {
+    ///     path: String,
+    /// }
+    ///
+    /// let mut map = HandlerMap::new();
+    /// map.insert_mut_ref(|req: &mut Request| req.path.make_ascii_lowercase());
+    ///
+    /// let mut req = Request { path: "/Index.HTML".to_string() };
+    /// assert!(map.call_mut_ref(&mut req));
+    /// assert_eq!(req.path, "/index.html");
+    /// ```
+    pub fn insert_mut_ref<T: Any, F: Fn(&mut T) + 'a>(&mut self, handler: F) {
+        let handler = move |msg: *mut T| handler(unsafe { &mut *msg });
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
         let id = TypeId::of::<T>();
-        self.0.remove(&id);
+
+        self.insert_slot(id, Slot::new::<T>(SlotHandler::MutRef(ptr)));
     }
 
-    /// Returns true if the given message type has a handler registered in the map.
-    pub fn is_registered<T: Any>(&self) -> bool {
+    /// Registers a new handler into the map which can decline the messages it receives, by
+    /// returning false.
+    ///
+    /// When the handler declines a message, `dispatch` and `call_filter` return
+    /// `CallOutcome::Declined`. Since the handler was still registered and run, `call` returns
+    /// true either way. A declined message has been given to the handler, so it's not saved in
+    /// the dead-letter queue.
+    pub fn insert_filter<T: Any, F: Fn(T) -> bool + 'a>(&mut self, handler: F) {
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
         let id = TypeId::of::<T>();
-        self.0.contains_key(&id)
+
+        self.insert_slot(id, Slot::new::<T>(SlotHandler::Filter(ptr)));
     }
 
-    /// Returns true if the given message has a handler registered in this map.
+    /// Registers a new handler into the map for the message type with the given `TypeId`, which
+    /// receives its messages boxed.
     ///
-    /// This is the same operation as `is_registered`, but allows you to call it with a value
-    /// rather than having to supply the type.
-    pub fn val_is_registered<T: Any>(&self, _msg: &T) -> bool {
-        self.is_registered::<T>()
+    /// This is a lower-level escape hatch for message types which can't be named where the
+    /// handler is registered. The handler is called with every message whose type has the given
+    /// `TypeId`, whether it's dispatched by `call` or `call_any`, and is responsible for
+    /// downcasting the message itself. The map never inspects the box, so registering a handler
+    /// under the wrong `TypeId` can't cause undefined behavior; however, the handler will then
+    /// receive messages of a type it doesn't expect, and its downcasts will fail.
+    ///
+    /// Since the name of the message type isn't known, it's recorded as `"<raw>"`. This handler
+    /// takes the place of any other handler registered for the same type, and vice-versa.
+    pub fn insert_raw<F: Fn(Box<dyn Any>) + 'a>(&mut self, id: TypeId, handler: F) {
+        let ptr = BoxFn::from(Box::new(handler)).erase();
+        self.insert_slot(id, Slot::raw(id, SlotHandler::Raw(ptr)));
     }
 
-    /// Calls the handler with the given message, returning whether the handler was registered.
-    pub fn call<T: Any>(&self, msg: T) -> bool {
+    /// Registers a new handler into the map which receives its messages boxed, and downcasts them
+    /// itself before passing them on to `handler`.
+    ///
+    /// Handlers registered with `insert` are called by reinterpreting them as taking the message
+    /// type they were registered for. This handler is instead always called through a function
+    /// taking a `Box<dyn Any>`, so calling it doesn't rely on that reinterpretation, at the cost
+    /// of allocating a box for each message. Apart from that, this is the same as `insert`:
+    /// the handler is run by `call` and its variants, and handlers registered either way can be
+    /// mixed in the same map. `call_via_box` can be used to box the message before it's
+    /// dispatched, which avoids boxing it separately for a handler registered with this method.
+    pub fn insert_boxed<T: Any, F: Handler<T> + 'a>(&mut self, handler: F) {
+        let handler = move |msg: Box<dyn Any>| {
+            if let Ok(msg) = msg.downcast::<T>() {
+                handler(*msg);
+            }
+        };
+        let ptr = BoxFn::from(Box::new(handler)).erase();
         let id = TypeId::of::<T>();
-        if let Some(act) = self.0.get(&id) {
-            unsafe { act.call_erased(msg); }
-            true
-        } else {
-            false
+
+        self.insert_slot(id, Slot::new::<T>(SlotHandler::Raw(ptr)));
+    }
+
+    /// Registers a handler which is already boxed as a trait object.
+    ///
+    /// This is the same as passing the box to `insert`, which a `Box<dyn Fn(T)>` can be passed to
+    /// as-is, since it implements `Fn(T)` itself; it's spelled out as its own method for callers
+    /// whose handlers are already type-erased, so the message type is named by the box rather than
+    /// inferred. The map keeps the box as it is, without wrapping it in another closure, but it's
+    /// still stored inside the map's own allocation for the handler, since the map only holds
+    /// handlers through a thin pointer.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    ///
+    /// struct Log(String);
+    ///
+    /// let handler: Box<dyn Fn(Log)> = Box::new(|msg| println!("{}", msg.0));
+    /// let mut map = HandlerMap::new();
+    /// map.insert_boxed_fn(handler);
+    ///
+    /// assert!(map.call(Log("hello".into())));
+    /// ```
+    pub fn insert_boxed_fn<T: Any>(&mut self, handler: Box<dyn Fn(T) + 'a>) {
+        self.insert(handler);
+    }
+
+    /// Registers a handler for the message type `Inner`, which wraps each message with `wrap` and
+    /// passes it to the shared `handler`.
+    ///
+    /// This lets one handler receive several related message types, by registering it once for
+    /// each variant of an enum that wraps them:
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::cell::Cell;
+    /// use std::rc::Rc;
+    ///
+    /// struct Click(u32);
+    /// struct Scroll(i32);
+    ///
+    /// enum Input {
+    ///     Click(Click),
+    ///     Scroll(Scroll),
+    /// }
+    ///
+    /// let clicks = Cell::new(0);
+    /// let sink: Rc<dyn Fn(Input)> = Rc::new(|input| match input {
+    ///     Input::Click(_) => clicks.set(clicks.get() + 1),
+    ///     Input::Scroll(_) => {}
+    /// });
+    ///
+    /// let mut map = HandlerMap::new();
+    /// map.insert_variant(Input::Click, sink.clone());
+    /// map.insert_variant(Input::Scroll, sink);
+    ///
+    /// assert!(map.call(Click(1)));
+    /// assert!(map.call(Scroll(-3)));
+    /// assert_eq!(clicks.get(), 1);
+    /// ```
+    pub fn insert_variant<Inner, Event>(
+        &mut self,
+        wrap: fn(Inner) -> Event,
+        handler: Rc<dyn Fn(Event) + 'a>,
+    )
+    where
+        Inner: Any,
+        Event: 'a,
+    {
+        self.insert(move |msg: Inner| handler(wrap(msg)));
+    }
+
+    /// Registers a new handler into the map which can mutate its own state.
+    ///
+    /// The handler can be called with either `call` or `call_mut`. Since `call` only borrows the
+    /// map immutably, a handler registered this way must not cause itself to be called again
+    /// while it's running, for example by calling `call` from inside itself through a shared
+    /// reference to the map; doing so panics. `call_mut` borrows the map mutably, so that can't
+    /// happen.
+    pub fn insert_mut<T: Any, F: FnMut(T) + 'a>(&mut self, handler: F) {
+        let handler = RefCell::new(handler);
+        self.insert(move |msg: T| {
+            let mut handler = handler.try_borrow_mut()
+                .expect("a handler registered with insert_mut was called while already running");
+            (*handler)(msg)
+        });
+    }
+
+    /// Registers a new handler into the map which is consumed the first time it's run.
+    ///
+    /// After the handler has run, the message type is treated as unregistered: `call` returns
+    /// false, and `dispatch` returns `CallOutcome::Unregistered`. Since `call` only borrows the
+    /// map immutably, the spent handler stays in the map until it's replaced or removed, so
+    /// `is_registered` continues to return true for it; call it with `call_once_aware` instead to
+    /// remove it from the map once it has run.
+    pub fn insert_once<T: Any, F: FnOnce(T) + 'a>(&mut self, handler: F) {
+        let handler = Cell::new(Some(handler));
+        let handler = move |msg: T| {
+            if let Some(handler) = handler.take() {
+                handler(msg);
+            }
+        };
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        self.insert_slot(id, Slot::new::<T>(SlotHandler::Once(ptr, Cell::new(false))));
+    }
+
+    /// Registers a constructor for a handler into the map, which is run to build the handler the
+    /// first time a message of its type is dispatched.
+    ///
+    /// The constructor runs at most once: the handler it returns is kept, and used for that
+    /// message and every one after it. If no message of this type is ever dispatched, or the
+    /// handler is replaced or removed before then, the constructor never runs. Messages which
+    /// are rejected by a validator or a consumed single-shot handler don't cause it to run.
+    pub fn insert_lazy<T, F, C>(&mut self, ctor: C)
+    where
+        T: Any,
+        F: Handler<T> + 'a,
+        C: FnOnce() -> F + 'a,
+    {
+        let ctor = Cell::new(Some(ctor));
+        let handler = OnceCell::new();
+        self.insert(move |msg: T| {
+            let handler = handler.get_or_init(|| {
+                (ctor.take().expect("lazy handler constructor ran twice"))()
+            });
+            handler(msg)
+        });
+    }
+
+    /// Registers a new handler into the map which, when called, also receives the names of all
+    /// the message types registered in the map.
+    ///
+    /// The names are those given by `std::any::type_name`, sorted alphabetically, and include the
+    /// handler's own message type. This allows a handler to report what messages the map accepts,
+    /// without needing to borrow the map itself.
+    pub fn insert_with_registry<T, F>(&mut self, handler: F)
+    where
+        T: Any,
+        F: Fn(&[&'static str], T) + 'a,
+    {
+        let handler = move |(names, msg): (*const [&'static str], T)| {
+            handler(unsafe { &*names }, msg)
+        };
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        self.insert_slot(id, Slot::new::<T>(SlotHandler::Registry(ptr)));
+    }
+
+    /// Registers a new handler into the map, unless doing so would exceed the limit set with
+    /// `set_max_handlers`.
+    ///
+    /// Replacing the handler for a message type that's already registered doesn't count against
+    /// the limit. Only this method enforces the limit; `insert` and the other registration methods
+    /// always add their handler, even if the map is already over the limit.
+    pub fn try_insert_bounded<T: Any, F: Handler<T> + 'a>(&mut self, handler: F)
+        -> Result<(), CapacityExceeded>
+    {
+        if let Some(max) = self.max_handlers {
+            if self.slots.len() >= max && !self.slots.contains(&TypeId::of::<T>()) {
+                return Err(CapacityExceeded { max });
+            }
         }
+
+        self.insert(handler);
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::HandlerMap;
+    /// Sets the maximum number of message types that `try_insert_bounded` will register handlers
+    /// for.
+    ///
+    /// Lowering the limit below the number of handlers already registered doesn't remove any of
+    /// them, but prevents new ones from being added with `try_insert_bounded`.
+    pub fn set_max_handlers(&mut self, max: usize) {
+        self.max_handlers = Some(max);
+    }
 
-    #[test]
-    fn it_works() {
-        struct MyMessage;
-        fn respond(_: MyMessage) {}
+    /// Returns the limit set with `set_max_handlers`, if any.
+    pub fn max_handlers(&self) -> Option<usize> {
+        self.max_handlers
+    }
 
-        let mut map = HandlerMap::new();
-        map.insert(respond);
+    /// Registers a new handler into the map, along with a validator that checks messages before
+    /// they're passed to it.
+    ///
+    /// When a message of type `T` is dispatched, `validate` is run first. If it returns an error,
+    /// the handler isn't called, `call` returns false, and `dispatch` returns
+    /// `CallOutcome::ValidationFailed` with the validator's error. This allows the handler to
+    /// assume that every message it receives is valid.
+    pub fn insert_validated<T, V, F>(&mut self, validate: V, handler: F)
+    where
+        T: Any,
+        V: Fn(&T) -> Result<(), ValidationError> + 'a,
+        F: Handler<T> + 'a,
+    {
+        let validate = move |msg: *const T| validate(unsafe { &*msg });
+        let validator = BoxFn::from(Box::new(validate)).erase().erase_arg();
 
-        assert!(map.call(MyMessage));
+        self.insert(handler);
+        if let Some(slot) = self.slots.get_mut(&TypeId::of::<T>()) {
+            slot.validator = Some(validator);
+        }
     }
 
-    #[test]
-    fn no_handler() {
-        struct MyMessage;
+    /// Wraps the handler registered for the given message type in another closure, returning
+    /// whether there was a handler to wrap.
+    ///
+    /// The wrapper receives each message along with a function which calls the original handler,
+    /// and can decide whether and when to pass the message on. This allows adding behavior like
+    /// logging or timing to a handler without needing the original closure. Only handlers
+    /// registered with `insert` or one of its variants which receive their message by value can be
+    /// wrapped; for other handlers, like the ones registered with `insert_ref` or `insert_async`,
+    /// this returns false without changing them. Settings like a validator or single-shot state
+    /// are kept, but a wrapped handler can't be cloned by `try_clone`.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::cell::RefCell;
+    ///
+    /// let log = RefCell::new(Vec::new());
+    /// let mut map = HandlerMap::new();
+    /// map.insert(|msg: u32| log.borrow_mut().push(format!("handled {}", msg)));
+    ///
+    /// assert!(map.wrap_handler(|msg: u32, inner: &dyn Fn(u32)| {
+    ///     log.borrow_mut().push("before".to_string());
+    ///     inner(msg);
+    ///     log.borrow_mut().push("after".to_string());
+    /// }));
+    ///
+    /// assert!(map.call(5u32));
+    /// assert_eq!(*log.borrow(), ["before", "handled 5", "after"]);
+    /// ```
+    pub fn wrap_handler<T: Any, W: Fn(T, &dyn Fn(T)) + 'a>(&mut self, wrapper: W) -> bool {
+        let id = TypeId::of::<T>();
+        let mut slot = match self.slots.remove(&id) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        slot.debug_check_type::<T>();
 
-        let map = HandlerMap::new();
+        let inner = match slot.handler {
+            SlotHandler::Value(inner) => inner,
+            handler => {
+                slot.handler = handler;
+                self.slots.insert(id, slot);
+                return false;
+            }
+        };
+        let wrapped = move |msg: T| wrapper(msg, &|msg: T| unsafe { inner.call_erased(msg) });
 
-        assert!(!map.call(MyMessage));
+        slot.handler = SlotHandler::Value(BoxFn::from(Box::new(wrapped)).erase().erase_arg());
+        slot.fn_addr = None;
+        slot.set_catch_drop_panics(self.catch_drop_panics);
+        self.slots.insert(id, slot);
+        true
     }
 
-    #[test]
-    fn handler_is_called() {
-        use std::sync::Arc;
-        use std::sync::atomic::AtomicUsize;
-        use std::sync::atomic::Ordering::SeqCst;
+    /// Registers a handler for `To` which converts each message with the given function and
+    /// passes it to the handler registered for `From`, returning whether there was a handler to
+    /// share.
+    ///
+    /// Both message types then share the same closure, rather than `To` receiving a copy of it.
+    /// Like `wrap_handler`, only handlers which receive their message by value can be shared; for
+    /// other handlers, this returns false without registering anything. Settings like a validator
+    /// or single-shot state stay with `From`'s handler, and aren't applied to messages of `To`.
+    /// Neither handler can be cloned by `try_clone` afterward.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::cell::Cell;
+    ///
+    /// struct Celsius(i32);
+    /// struct Kelvin(i32);
+    ///
+    /// let last = Cell::new(0);
+    /// let mut map = HandlerMap::new();
+    /// map.insert(|msg: Celsius| last.set(msg.0));
+    ///
+    /// assert!(map.alias_with(|msg: Kelvin| Celsius(msg.0 - 273)));
+    /// assert!(map.call(Kelvin(300)));
+    /// assert_eq!(last.get(), 27);
+    /// ```
+    pub fn alias_with<From: Any, To: Any>(&mut self, conv: fn(To) -> From) -> bool {
+        let id = TypeId::of::<From>();
+        let mut slot = match self.slots.remove(&id) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        slot.debug_check_type::<From>();
 
-        let mut map = HandlerMap::new();
+        let shared = match slot.handler {
+            SlotHandler::Value(inner) => Rc::new(inner),
+            handler => {
+                slot.handler = handler;
+                self.slots.insert(id, slot);
+                return false;
+            }
+        };
 
-        struct FancyCaller;
-        let acc = Arc::new(AtomicUsize::new(0));
-        {
-            let acc = acc.clone();
-            map.insert(move |_: FancyCaller| {
-                acc.fetch_add(1, SeqCst);
-            });
+        let inner = shared.clone();
+        let handler = move |msg: From| unsafe { inner.call_erased(msg) };
+        slot.handler = SlotHandler::Value(BoxFn::from(Box::new(handler)).erase().erase_arg());
+        slot.fn_addr = None;
+        slot.set_catch_drop_panics(self.catch_drop_panics);
+        self.slots.insert(id, slot);
+
+        self.insert(move |msg: To| unsafe { shared.call_erased(conv(msg)) });
+        true
+    }
+
+    /// Un-registers the handler for the given type from this map, returning whether a handler
+    /// was registered.
+    ///
+    /// If the handler was registered with `insert_with_teardown`, its teardown closure is run
+    /// before the handler is dropped. If dropping the handler panics, the panic is passed on to
+    /// the caller, after the handler has been removed, unless the map was set to catch it with
+    /// `set_catch_drop_panics`.
+    pub fn remove<T: Any>(&mut self) -> bool {
+        self.remove_by_id(TypeId::of::<T>())
+    }
+
+    /// Un-registers the handler for the message type with the given `TypeId`, running its
+    /// teardown closure if it has one, and returns whether a handler was registered.
+    pub(crate) fn remove_by_id(&mut self, id: TypeId) -> bool {
+        match self.slots.remove(&id) {
+            Some(mut slot) => {
+                if let Some(teardown) = slot.teardown.take() {
+                    teardown.run();
+                }
+                true
+            }
+            None => false,
         }
+    }
 
-        map.call(FancyCaller);
-        map.call(FancyCaller);
-        map.call(FancyCaller);
+    /// Un-registers the handler for the given message's type from this map, returning whether a
+    /// handler was registered.
+    ///
+    /// This is the same operation as `remove`, but allows you to call it with a value rather than
+    /// having to supply the type.
+    pub fn remove_val<T: Any>(&mut self, _msg: &T) -> bool {
+        self.remove::<T>()
+    }
+
+    /// Exchanges the handlers registered for the two given message types, so that messages of
+    /// type `a` are passed to the handler that was registered for `b`, and vice versa. Returns
+    /// false without changing anything if either type has no handler registered.
+    ///
+    /// Along with the handlers, this exchanges their validators from `insert_validated`, their
+    /// single-shot state from `mark_single_shot`, their groups from `insert_in_group`, the
+    /// values they were registered for with `insert_weak`, and their teardown closures from
+    /// `insert_with_teardown`. The name of each message type, and the
+    /// default message created for it by `insert_with_default`, stay with the type.
+    ///
+    /// # Safety
+    ///
+    /// Handlers are stored with their argument types erased, and are called by reinterpreting
+    /// them as taking the type they're registered for. Callers must ensure that the two message
+    /// types have the same representation, for example two `#[repr(transparent)]` wrappers around
+    /// the same type, so that each handler can receive the other's messages. Failure to uphold this
+    /// constraint can cause the handlers to be called with invalid data.
+    pub unsafe fn swap_handlers(&mut self, a: TypeId, b: TypeId) -> bool {
+        if a == b {
+            return self.slots.contains(&a);
+        }
+
+        let mut first = match self.slots.remove(&a) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        let swapped = match self.slots.get_mut(&b) {
+            Some(second) => {
+                std::mem::swap(&mut first.handler, &mut second.handler);
+                std::mem::swap(&mut first.validator, &mut second.validator);
+                std::mem::swap(&mut first.single_shot, &mut second.single_shot);
+                std::mem::swap(&mut first.fn_addr, &mut second.fn_addr);
+                std::mem::swap(&mut first.group, &mut second.group);
+                std::mem::swap(&mut first.dead, &mut second.dead);
+                std::mem::swap(&mut first.teardown, &mut second.teardown);
+                true
+            }
+            None => false,
+        };
+        self.slots.insert(a, first);
+        swapped
+    }
+
+    /// Un-registers every handler whose message type's `TypeId` doesn't satisfy the given
+    /// predicate.
+    ///
+    /// Like `remove`, this only affects the handlers counted by `len`. The removed handlers are
+    /// dropped before this returns.
+    pub fn retain<P: FnMut(&TypeId) -> bool>(&mut self, mut pred: P) {
+        self.slots.retain(&mut |id, _| pred(id));
+    }
+
+    /// Returns the identity of this map, which is different from that of every other map.
+    ///
+    /// A copy of the map made with `try_clone` is a different map, and gets a new id.
+    pub fn id(&self) -> HandlerMapId {
+        self.id
+    }
+
+    /// Returns the number of message types with a handler registered in the map.
+    ///
+    /// This counts the handlers registered with `insert` and its variants, like
+    /// `insert_validated`, `insert_async`, or `insert_cascade`. Handlers registered with
+    /// `insert_multi`, `insert_multi_with_result`, `insert_with_result`, `insert_named`,
+    /// `insert_signal`, or `insert_borrowed` are kept separately, and aren't counted.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns true if no message types have a handler registered in the map, as counted by
+    /// `len`.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Returns the number of message types the map can hold handlers for without reallocating.
+    ///
+    /// Like `len`, this only covers the handlers registered with `insert` and its variants.
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    /// Reserves room for at least `additional` more message types to be registered without
+    /// reallocating.
+    pub fn reserve(&mut self, additional: usize) {
+        self.slots.reserve(additional);
+    }
+
+    /// Shrinks the capacity of the map as much as possible, for example after removing many
+    /// handlers.
+    pub fn shrink_to_fit(&mut self) {
+        self.slots.shrink_to_fit();
+    }
+
+    /// Removes every handler from the map, including those registered with `insert_multi`,
+    /// `insert_multi_with_result`, `insert_multi_stoppable`, `insert_multi_ref`,
+    /// `insert_with_result`, `insert_named`, `insert_signal`, and `insert_borrowed`, the
+    /// conversions registered with `register_conversion`, and the fallback handler.
+    ///
+    /// Each handler is dropped as it's removed, along with anything it captured. Settings like the
+    /// handler limit or the error policy are kept.
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.listeners.clear();
+        self.result_listeners.clear();
+        self.stoppable_listeners.clear();
+        self.ref_listeners.clear();
+        self.responders.clear();
+        self.named.clear();
+        self.signals.clear();
+        self.borrowed.clear();
+        self.conversions.clear();
+        self.fallback = None;
+    }
+
+    /// Sets a catch-all handler, which receives the messages dispatched to this map whose type has
+    /// no handler registered.
+    ///
+    /// The fallback handler only runs when no handler is registered for the message type at all.
+    /// It doesn't run for messages rejected by a validator, or ones whose handler is of a kind
+    /// that `call` doesn't run, like one registered with `insert_async`. When the fallback
+    /// handles a message, `call` returns true, `dispatch` returns `CallOutcome::Fallback`, and the
+    /// message isn't saved in the dead-letter queue.
+    pub fn set_fallback<F: Fn(&dyn Any) + 'a>(&mut self, handler: F) {
+        self.fallback = Some(Box::new(handler));
+    }
+
+    /// Sets an observer which sees every message dispatched to this map, before it's passed to
+    /// its handler.
+    ///
+    /// Unlike the fallback handler, the tee receives messages whether or not a handler is
+    /// registered for them, which makes it a central place to log or trace them. It's called by
+    /// `call`, `call_ref`, `call_any`, and the methods built on them, like `dispatch`.
+    pub fn set_tee<F: Fn(&dyn Any) + 'a>(&mut self, tee: F) {
+        self.tee = Some(Box::new(tee));
+    }
+
+    /// Sets an observer which is told when registering a handler replaces the one already
+    /// registered for its message type.
+    ///
+    /// The observer receives the `TypeId` of the message type, and is called before the old
+    /// handler is dropped. It's called by `insert` and its variants, like `insert_ref` or
+    /// `insert_async`, and by `extend`; it isn't called when a type is registered for the first
+    /// time, when a handler is removed, or when maps are merged.
+    pub fn on_replace<F: Fn(TypeId) + 'a>(&mut self, observer: F) {
+        self.replace_observer = Some(Box::new(observer));
+    }
+
+    /// Sets whether a panic from dropping a handler is caught, so that the map's other handlers
+    /// are still dropped.
+    ///
+    /// A handler panics when it's dropped if something it captured panics in its destructor. By
+    /// default, that panic is passed on to whatever dropped the handler, like `remove`, `clear`,
+    /// replacing the handler with `insert`, or dropping the map. If the map is being dropped while
+    /// unwinding from another panic, this aborts the process.
+    ///
+    /// When enabled, the panic is reported by the panic hook and then caught instead, wherever the
+    /// handler is dropped. This applies to the handlers registered with `insert` and its variants,
+    /// including the ones registered before this was called, and to their validators; handlers of
+    /// other kinds, like those registered with `insert_multi`, aren't covered. The setting stays
+    /// with a handler taken out of the map with `take_handler` or `into_iter`, and moves to this
+    /// map's setting when a handler is moved into it from another map.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    ///
+    /// struct Bomb;
+    ///
+    /// impl Drop for Bomb {
+    ///     fn drop(&mut self) {
+    ///         panic!("bomb");
+    ///     }
+    /// }
+    ///
+    /// let mut map = HandlerMap::new();
+    /// let bomb = Bomb;
+    /// map.insert(move |_: u32| { let _ = &bomb; });
+    ///
+    /// map.set_catch_drop_panics(true);
+    /// assert!(map.remove::<u32>());
+    /// ```
+    pub fn set_catch_drop_panics(&mut self, enabled: bool) {
+        self.catch_drop_panics = enabled;
+        self.slots.retain(&mut |_, slot| {
+            slot.set_catch_drop_panics(enabled);
+            true
+        });
+    }
+
+    /// Puts the given slot in the map, telling the observer set with `on_replace` if it replaces
+    /// an existing slot.
+    fn insert_slot(&mut self, id: TypeId, mut slot: Slot<'a>) {
+        slot.set_catch_drop_panics(self.catch_drop_panics);
+        let old = self.slots.insert(id, slot);
+        if let (Some(_), Some(observer)) = (&old, &self.replace_observer) {
+            observer(id);
+        }
+    }
+
+    /// Returns true if the given slot's handler is in a group that was disabled with
+    /// `set_group_enabled`.
+    fn is_disabled(&self, slot: &Slot<'a>) -> bool {
+        slot.group.is_some_and(|group| self.disabled_groups.contains(&group))
+    }
+
+    /// Removes the handler set with `set_fallback`, if any.
+    pub fn clear_fallback(&mut self) {
+        self.fallback = None;
+    }
+
+    /// Returns true if the given message type has a handler registered in the map.
+    #[must_use = "checking whether a handler is registered has no other effect"]
+    pub fn is_registered<T: Any>(&self) -> bool {
+        let id = TypeId::of::<T>();
+        self.slots.contains(&id)
+    }
+
+    /// Returns true if the message type with the given `TypeId` has a handler registered in the
+    /// map.
+    ///
+    /// This is the same operation as `is_registered`, for when only the `TypeId` of the message
+    /// type is known.
+    pub fn contains_type_id(&self, id: TypeId) -> bool {
+        self.slots.contains(&id)
+    }
+
+    /// Returns true if the type of the given message has a handler registered in the map.
+    ///
+    /// This allows checking whether `call_any` would find a handler for a message before boxing
+    /// it. Note that a `Box<dyn Any>` coerced to `&dyn Any` is itself the message, so to check the
+    /// message inside a box, pass `&*msg` instead of `&msg`.
+    pub fn contains_any(&self, msg: &dyn Any) -> bool {
+        self.contains_type_id(msg.type_id())
+    }
+
+    /// Returns the `TypeId`s from the given list which don't have a handler registered in the map,
+    /// in the order they were given.
+    ///
+    /// This checks each `TypeId` the same way as `contains_type_id`, and is useful for checking
+    /// that a set of message types is covered before relying on it.
+    pub fn missing_handlers(&self, ids: &[TypeId]) -> Vec<TypeId> {
+        ids.iter().filter(|id| !self.slots.contains(id)).cloned().collect()
+    }
+
+    /// Returns true if every message type with a handler registered in `other` also has one
+    /// registered in this map.
+    ///
+    /// Only the handlers counted by `len` are compared, and the handlers themselves aren't
+    /// looked at, so this answers whether this map can receive every message `other` can. Every
+    /// map covers an empty map.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    ///
+    /// let mut required = HandlerMap::new();
+    /// required.insert(|_: u8| {});
+    ///
+    /// let mut provided = HandlerMap::new();
+    /// provided.insert(|_: u8| println!("got a u8"));
+    /// provided.insert(|_: u16| println!("got a u16"));
+    ///
+    /// assert!(provided.covers(&required));
+    /// assert!(!required.covers(&provided));
+    /// ```
+    pub fn covers<'b, S2, H2>(&self, other: &HandlerMap<'b, S2, H2>) -> bool
+    where
+        S2: BuildHasher,
+        H2: HandlerStore<'b>,
+    {
+        other.registered_types().all(|id| self.slots.contains(&id))
+    }
+
+    /// Compares the message types registered in this map against the ones registered in
+    /// `other`, returning the types only this map has a handler for, and the types only `other`
+    /// has a handler for.
+    ///
+    /// Like `covers`, this only compares the handlers counted by `len`. Each list is in the order
+    /// of its map's `registered_types`. This map covers `other` exactly when the second list is
+    /// empty.
+    pub fn coverage_diff<'b, S2, H2>(&self, other: &HandlerMap<'b, S2, H2>)
+        -> (Vec<TypeId>, Vec<TypeId>)
+    where
+        S2: BuildHasher,
+        H2: HandlerStore<'b>,
+    {
+        let ours = self.registered_types().filter(|id| !other.slots.contains(id)).collect();
+        let theirs = other.registered_types().filter(|id| !self.slots.contains(id)).collect();
+        (ours, theirs)
+    }
+
+    /// Returns an iterator over the `TypeId`s of the message types with a handler registered in
+    /// the map, as counted by `len`.
+    ///
+    /// The order of the types is unspecified, unless the map was created with `ordered`, or with
+    /// a `HandlerStore` that keeps them in order.
+    pub fn registered_types<'b>(&'b self) -> impl Iterator<Item = TypeId> + use<'a, 'b, S, H> {
+        self.slots.iter().map(|(id, _)| *id)
+    }
+
+    /// Returns true if the given message has a handler registered in this map.
+    ///
+    /// This is the same operation as `is_registered`, but allows you to call it with a value
+    /// rather than having to supply the type.
+    #[must_use = "checking whether a handler is registered has no other effect"]
+    pub fn val_is_registered<T: Any>(&self, _msg: &T) -> bool {
+        self.is_registered::<T>()
+    }
+
+    /// Returns true if calling `call` with the given message would run its handler, without
+    /// running it.
+    ///
+    /// This evaluates the validator registered with `insert_validated`, if any, against the
+    /// message, and checks whether a single-shot handler has already been consumed. Returns false
+    /// if no handler is registered, if the registered handler isn't one that `call` runs, or if
+    /// its group was disabled with `set_group_enabled`.
+    pub fn would_run<T: Any>(&self, msg: &T) -> bool {
+        let slot = match self.slots.get(&TypeId::of::<T>()) {
+            Some(slot) if !self.is_disabled(slot) => slot,
+            _ => return false,
+        };
+
+        match slot.handler {
+            SlotHandler::Async(_) | SlotHandler::Cascade(_) => return false,
+            SlotHandler::Once(_, ref spent) if spent.get() => return false,
+            SlotHandler::Value(_) | SlotHandler::Registry(_) | SlotHandler::Ref(_) |
+                SlotHandler::MutRef(_) | SlotHandler::Filter(_) | SlotHandler::Raw(_) |
+                SlotHandler::Once(..) => {}
+        }
+
+        slot.debug_check_type::<T>();
+        if let Some(ref validator) = slot.validator {
+            if unsafe { validator.call_erased(msg as *const T) }.is_err() {
+                return false;
+            }
+        }
+
+        !slot.single_shot.as_ref().is_some_and(Cell::get)
+    }
+
+    /// Calls the handler registered with `insert_ref` with a reference to the given message,
+    /// returning whether the handler was registered.
+    ///
+    /// Handlers which receive their message by value, like the ones registered with `insert`,
+    /// can't be called this way; for those, this returns false without running them. Validators
+    /// and single-shot handlers behave the same way as they do in `call`. Unlike `call`, a message
+    /// that isn't handled isn't saved in the dead-letter queue, since it's still owned by the
+    /// caller.
+    pub fn call_ref<T: Any>(&self, msg: &T) -> bool {
+        if let Some(ref tee) = self.tee {
+            tee(msg);
+        }
+
+        match self.slots.get(&TypeId::of::<T>()) {
+            Some(slot) if !self.is_disabled(slot) => slot.run_ref(msg),
+            _ => false,
+        }
+    }
+
+    /// Calls the handler registered with `insert_ref` for the given message's type, without
+    /// needing to know its type at compile time, returning whether the handler was registered.
+    ///
+    /// This is the same operation as `call_ref`, but isn't generic: the downcast to the message
+    /// type happens in a function created once when the handler is registered, so every call
+    /// site shares the same copy of the lookup code, whatever the type of the message. This can
+    /// reduce code size when a large message type is dispatched from many places, at the cost of
+    /// calling through one more function pointer.
+    ///
+    /// Note that a `Box<dyn Any>` coerced to `&dyn Any` is itself the message, so to call the
+    /// handler for the message inside a box, pass `&*msg` instead of `&msg`.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::any::Any;
+    /// use std::cell::Cell;
+    ///
+    /// let total = Cell::new(0);
+    /// let mut map = HandlerMap::new();
+    /// map.insert_ref(|n: &u32| total.set(total.get() + n));
+    ///
+    /// let msgs: [&dyn Any; 3] = [&1u32, &"unhandled", &2u32];
+    /// let handled = msgs.iter().filter(|msg| map.call_dyn(**msg)).count();
+    /// assert_eq!(handled, 2);
+    /// assert_eq!(total.get(), 3);
+    /// ```
+    pub fn call_dyn(&self, msg: &dyn Any) -> bool {
+        if let Some(ref tee) = self.tee {
+            tee(msg);
+        }
+
+        match self.slots.get(&msg.type_id()) {
+            Some(slot) if !self.is_disabled(slot) => (slot.run_dyn)(slot, msg),
+            _ => false,
+        }
+    }
+
+    /// Calls the handler registered with `insert_mut_ref` with a mutable reference to the given
+    /// message, returning whether the handler was registered.
+    ///
+    /// Handlers registered with `insert_ref` can also be called this way. Handlers which receive
+    /// their message by value can't; for those, this returns false without running them.
+    /// Otherwise, this behaves the same way as `call_ref`.
+    pub fn call_mut_ref<T: Any>(&self, msg: &mut T) -> bool {
+        if let Some(ref tee) = self.tee {
+            tee(&*msg);
+        }
+
+        let slot = match self.slots.get(&TypeId::of::<T>()) {
+            Some(slot) if !self.is_disabled(slot) => slot,
+            _ => return false,
+        };
+
+        match slot.handler {
+            SlotHandler::Ref(ref act) => match slot.admit(&*msg) {
+                Ok(()) => {
+                    unsafe { act.call_erased(msg as *const T) };
+                    true
+                }
+                Err(outcome) => outcome == CallOutcome::AlreadyConsumed,
+            },
+            SlotHandler::MutRef(ref act) => match slot.admit(&*msg) {
+                Ok(()) => {
+                    unsafe { act.call_erased(msg as *mut T) };
+                    true
+                }
+                Err(outcome) => outcome == CallOutcome::AlreadyConsumed,
+            },
+            _ => false,
+        }
+    }
+
+    /// Calls the handler with the given message, returning whether the handler was registered.
+    ///
+    /// Handlers registered with `insert_async` are not run by this method, since their futures
+    /// need to be driven by the caller; use `call_async` for those instead. Calling one of them
+    /// here returns false and drops the message, without saving it in the dead-letter queue, and
+    /// `dispatch` reports it as `CallOutcome::NotCallable`. If there's no handler
+    /// for the message type, but a conversion from it was registered with `register_conversion`,
+    /// the message may be converted and passed to the handler for another type instead.
+    ///
+    /// Ignoring the result causes an `unused_must_use` warning, since a message with no handler
+    /// is dropped without any other sign. When that's intended, assign the result to `_`:
+    ///
+    /// ```rust,compile_fail
+    /// #![deny(unused_must_use)]
+    /// use handler_map::HandlerMap;
+    ///
+    /// let map = HandlerMap::new();
+    /// map.call(5u32);
+    /// ```
+    ///
+    /// ```rust
+    /// #![deny(unused_must_use)]
+    /// use handler_map::HandlerMap;
+    ///
+    /// let map = HandlerMap::new();
+    /// let _ = map.call(5u32);
+    /// ```
+    #[must_use = "dispatch result ignored; the message may have been dropped without a handler"]
+    pub fn call<T: Any>(&self, msg: T) -> bool {
+        match self.dispatch(msg) {
+            CallOutcome::Handled | CallOutcome::Fallback | CallOutcome::AlreadyConsumed |
+                CallOutcome::Declined => true,
+            CallOutcome::Unregistered | CallOutcome::ValidationFailed(_) |
+                CallOutcome::Disabled | CallOutcome::NotCallable => false,
+        }
+    }
+
+    /// Calls the handler for the message created by the given closure, only creating the message
+    /// if a handler is registered for it, and returns whether the handler was called.
+    ///
+    /// This is meant for messages which are expensive to build. A handler registered in a parent
+    /// map set with `with_parent` counts as registered. If there's no handler for `T`, `make` is
+    /// never called, and this returns false without doing anything else: since there's no
+    /// message, it isn't passed to the fallback handler, saved in the dead-letter queue, or
+    /// counted by `with_metrics`. Otherwise, the message is passed to `call`.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    ///
+    /// struct Report(String);
+    ///
+    /// let mut map = HandlerMap::new();
+    /// assert!(!map.call_lazy(|| -> Report { panic!("no handler, so this isn't built") }));
+    ///
+    /// map.insert(|report: Report| println!("{}", report.0));
+    /// assert!(map.call_lazy(|| Report("expensive".repeat(1000))));
+    /// ```
+    #[must_use = "dispatch result ignored; the message may have been dropped without a handler"]
+    pub fn call_lazy<T: Any, G: FnOnce() -> T>(&self, make: G) -> bool {
+        self.is_registered_recursive::<T>() && self.call(make())
+    }
+
+    /// Calls the handler with the given message, handing the message back if there's no handler
+    /// to receive it.
+    ///
+    /// If no handler that `call` would run is registered for the message type, and there's no
+    /// fallback handler, this returns `Err` with the message, so it can be routed somewhere else.
+    /// Since the message is handed back, it isn't saved in the dead-letter queue. A message that's
+    /// rejected by a validator has already been given up, so it's not handed back. A message whose
+    /// handler is in a group disabled with `set_group_enabled` is handed back, without trying the
+    /// fallback handler.
+    pub fn call_or_return<T: Any>(&self, msg: T) -> Result<(), T> {
+        let id = TypeId::of::<T>();
+        self.record_dispatch(id, &msg);
+
+        match self.slots.get(&id) {
+            Some(slot) if self.is_disabled(slot) => Err(msg),
+            Some(slot) => slot.run(msg, &|| self.type_names(), None).map(|_| ()),
+            None => match (self.dispatch_converted(msg), &self.parent, &self.fallback) {
+                (Ok(_), _, _) => Ok(()),
+                (Err(msg), Some(parent), _) => parent.call_or_return(msg),
+                (Err(msg), None, Some(fallback)) => {
+                    fallback(&msg);
+                    Ok(())
+                }
+                (Err(msg), None, None) => Err(msg),
+            },
+        }
+    }
+
+    /// Calls the handler with the given message, handing the message back inside a
+    /// `DispatchError` if there's no handler to receive it.
+    ///
+    /// This is the same operation as `call_or_return`, but the error tells apart a message type
+    /// with no handler from one whose handler is in a disabled group. Like `call_or_return`, this
+    /// returns `Ok` once the message has been given to a handler or validator, even if the handler
+    /// declines it or the validator rejects it; use `dispatch` to tell those cases apart.
+    ///
+    /// ```rust
+    /// use handler_map::{DispatchError, HandlerMap};
+    ///
+    /// struct Save(String);
+    /// struct Quit;
+    ///
+    /// let mut map = HandlerMap::new();
+    /// map.insert(|_: Save| {});
+    ///
+    /// assert!(map.try_dispatch(Save("notes.txt".into())).is_ok());
+    /// match map.try_dispatch(Quit) {
+    ///     Err(DispatchError::Unregistered(Quit)) => {}
+    ///     _ => panic!("expected the message back"),
+    /// }
+    /// ```
+    pub fn try_dispatch<T: Any>(&self, msg: T) -> Result<(), DispatchError<T>> {
+        let disabled = self.slots.get(&TypeId::of::<T>()).is_some_and(|s| self.is_disabled(s));
+        self.call_or_return(msg).map_err(|msg| if disabled {
+            DispatchError::Disabled(msg)
+        } else {
+            DispatchError::Unregistered(msg)
+        })
+    }
+
+    /// Calls the handler with each unique message in the given batch, returning how many of them
+    /// were handled.
+    ///
+    /// Duplicate messages are dropped before any handler runs, so a burst of identical messages
+    /// only causes one dispatch. The unique messages are dispatched in the order they first
+    /// appeared in the batch, and are counted the same way `call` reports them.
+    pub fn call_deduped<T, I>(&self, msgs: I) -> usize
+    where
+        T: Any + Hash + Eq,
+        I: IntoIterator<Item = T>,
+    {
+        let msgs = msgs.into_iter().collect::<Vec<_>>();
+        let keep = {
+            let mut seen = HashSet::new();
+            msgs.iter().map(|msg| seen.insert(msg)).collect::<Vec<_>>()
+        };
+
+        let mut handled = 0;
+        for (msg, keep) in msgs.into_iter().zip(keep) {
+            if keep && self.call(msg) {
+                handled += 1;
+            }
+        }
+        handled
+    }
+
+    /// Looks up the handler for the given message type, returning a closure which calls it.
+    ///
+    /// Calling the returned closure is the same as calling `call`, except that the handler
+    /// doesn't need to be looked up again, which helps when calling the same handler many times
+    /// in a row. Returns `None` if no handler that `call` would run is registered for the type, or
+    /// if its group is disabled. Since the closure borrows the map, the handler can't be replaced
+    /// or removed while the closure exists.
+    pub fn get_caller<'b, T: Any>(&'b self) -> Option<impl Fn(T) + use<'a, 'b, S, H, T>> {
+        let slot = self.slots.get(&TypeId::of::<T>())?;
+        if self.is_disabled(slot) {
+            return None;
+        }
+        match slot.handler {
+            SlotHandler::Async(_) | SlotHandler::Cascade(_) => None,
+            _ => Some(move |msg: T| {
+                let _ = slot.run(msg, &|| self.type_names(), None);
+            }),
+        }
+    }
+
+    /// Boxes the given message, then calls its handler with it in the same way as `call_any`,
+    /// returning whether the handler was registered.
+    ///
+    /// A handler registered with `insert_boxed` or `insert_raw` receives the box the message was
+    /// put in here. Other handlers receive the message unboxed, as they would from `call`. Unlike
+    /// `call`, a message with no handler to receive it is dropped rather than being saved in the
+    /// dead-letter queue.
+    pub fn call_via_box<T: Any>(&self, msg: T) -> bool {
+        self.call_any(Box::new(msg)).unwrap_or(false)
+    }
+
+    /// Calls the handler for a boxed message, without needing to know its type, returning whether
+    /// the handler was registered.
+    ///
+    /// The handler is looked up by the type of the message inside the box, and receives the
+    /// unboxed message, exactly as if it was passed to `call`. If no handler that `call` would run
+    /// is registered for it, and there's no fallback handler, the box is handed back instead. Since
+    /// the message is handed back, it isn't saved in the dead-letter queue. The box is also handed
+    /// back if the handler is in a group disabled with `set_group_enabled`.
+    pub fn call_any(&self, msg: Box<dyn Any>) -> Result<bool, Box<dyn Any>> {
+        match self.dispatch_boxed(msg)? {
+            CallOutcome::Handled | CallOutcome::Fallback | CallOutcome::AlreadyConsumed |
+                CallOutcome::Declined => Ok(true),
+            CallOutcome::Unregistered | CallOutcome::ValidationFailed(_) |
+                CallOutcome::Disabled | CallOutcome::NotCallable => Ok(false),
+        }
+    }
+
+    /// Calls the handler for a boxed message in the same way as `call_any`, returning what
+    /// happened to the message, or handing it back if nothing handled it.
+    pub(crate) fn dispatch_boxed(&self, msg: Box<dyn Any>) -> Result<CallOutcome, Box<dyn Any>> {
+        let id = (*msg).type_id();
+        self.record_dispatch(id, &*msg);
+
+        match self.slots.get(&id) {
+            Some(slot) if self.is_disabled(slot) => Err(msg),
+            Some(slot) => (slot.run_boxed)(slot, msg, &|| self.type_names()),
+            None => match (self.dispatch_converted_boxed(msg), &self.parent, &self.fallback) {
+                (Ok(outcome), _, _) => Ok(outcome),
+                (Err(msg), Some(parent), _) => parent.dispatch_boxed(msg),
+                (Err(msg), None, Some(fallback)) => {
+                    fallback(&*msg);
+                    Ok(CallOutcome::Fallback)
+                }
+                (Err(msg), None, None) => Err(msg),
+            },
+        }
+    }
+
+    /// Calls the handler for a boxed message, without needing to know its type, handing the box
+    /// back if nothing handled it.
+    ///
+    /// This is the same as `call_any`, without reporting whether a handler that was found actually
+    /// ran. Since the box is handed back, several maps can be tried in turn:
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    ///
+    /// let mut map_a = HandlerMap::new();
+    /// map_a.insert(|_: u32| {});
+    /// let mut map_b = HandlerMap::new();
+    /// map_b.insert(|_: String| {});
+    ///
+    /// let msg = Box::new("hello".to_string());
+    /// assert!(map_a.call_boxed(msg).or_else(|msg| map_b.call_boxed(msg)).is_ok());
+    /// ```
+    pub fn call_boxed(&self, msg: Box<dyn Any>) -> Result<(), Box<dyn Any>> {
+        self.call_any(msg).map(|_| ())
+    }
+
+    /// Calls every handler registered with `insert` and its variants, with a message created by
+    /// the given factory, returning how many handlers ran.
+    ///
+    /// The factory is called with the `TypeId` of each registered message type, in no particular
+    /// order, and returns the message to dispatch for it, as with `call_any`. If it returns
+    /// `None`, or a message of some other type, that type is skipped.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::any::{Any, TypeId};
+    ///
+    /// struct Heartbeat(u32);
+    ///
+    /// let mut map = HandlerMap::new();
+    /// map.insert(|_: Heartbeat| {});
+    /// map.insert(|_: String| {});
+    ///
+    /// let ran = map.call_each(|id| {
+    ///     if id == TypeId::of::<Heartbeat>() {
+    ///         Some(Box::new(Heartbeat(1)) as Box<dyn Any>)
+    ///     } else {
+    ///         None
+    ///     }
+    /// });
+    /// assert_eq!(ran, 1);
+    /// ```
+    pub fn call_each<F: FnMut(TypeId) -> Option<Box<dyn Any>>>(&self, mut factory: F) -> usize {
+        let mut ran = 0;
+        for (&id, _) in self.slots.iter() {
+            let msg = match factory(id) {
+                Some(msg) if (*msg).type_id() == id => msg,
+                _ => continue,
+            };
+            if let Ok(true) = self.call_any(msg) {
+                ran += 1;
+            }
+        }
+        ran
+    }
+
+    /// Calls the handlers for a batch of boxed messages in order, in the same way as `call_any`,
+    /// returning whether each one was handled.
+    ///
+    /// The returned `Vec` has one entry per message, in the order the messages were given. A
+    /// message which `call_any` would hand back is dropped, and counts as not handled. Use
+    /// `dispatch_batch_collecting` to get those messages back instead.
+    pub fn dispatch_batch<I>(&self, msgs: I) -> Vec<bool>
+    where
+        I: IntoIterator<Item = Box<dyn Any>>,
+    {
+        msgs.into_iter().map(|msg| self.call_any(msg).unwrap_or(false)).collect()
+    }
+
+    /// Calls the handlers for a batch of boxed messages in order, in the same way as `call_any`,
+    /// returning how many were handled, along with the messages that were handed back.
+    ///
+    /// The handed-back messages are returned in the order they were given, so they can be queued
+    /// again later. A message which reached its handler without being handled, for example
+    /// because it failed validation, has already been consumed, so it isn't returned or counted.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::any::Any;
+    ///
+    /// let mut map = HandlerMap::new();
+    /// map.insert(|_: u32| {});
+    ///
+    /// let batch: Vec<Box<dyn Any>> = vec![Box::new(1u32), Box::new("later"), Box::new(2u32)];
+    /// let (handled, rest) = map.dispatch_batch_collecting(batch);
+    /// assert_eq!(handled, 2);
+    /// assert_eq!(*rest[0].downcast_ref::<&str>().unwrap(), "later");
+    /// ```
+    pub fn dispatch_batch_collecting<I>(&self, msgs: I) -> (usize, Vec<Box<dyn Any>>)
+    where
+        I: IntoIterator<Item = Box<dyn Any>>,
+    {
+        let mut handled = 0;
+        let mut rest = Vec::new();
+        for msg in msgs {
+            match self.call_any(msg) {
+                Ok(true) => handled += 1,
+                Ok(false) => {}
+                Err(msg) => rest.push(msg),
+            }
+        }
+        (handled, rest)
+    }
+
+    /// Calls the handler with the given message, catching any panic from the handler.
+    ///
+    /// This returns whether the handler was registered, in the same way as `call`, or the panic
+    /// payload if the handler panicked. The map remains usable afterward. The usual caveats of
+    /// `std::panic::catch_unwind` apply: panics are only caught if the panic strategy is
+    /// "unwind", and any state a handler was in the middle of updating when it panicked may be
+    /// left inconsistent. A single-shot handler which panicked is still considered consumed.
+    pub fn call_catch<T: Any>(&self, msg: T) -> Result<bool, Box<dyn Any + Send>> {
+        panic::catch_unwind(AssertUnwindSafe(|| self.call(msg)))
+    }
+
+    /// Calls the handler with the given message, returning whether the handler was registered.
+    ///
+    /// This is the same operation as `call`, except that it borrows the map mutably. This
+    /// guarantees that no handler registered with `insert_mut` is already running, so the call
+    /// can't panic because of one.
+    pub fn call_mut<T: Any>(&mut self, msg: T) -> bool {
+        self.call(msg)
+    }
+
+    /// Calls the handler with the given message, returning whether the handler was registered,
+    /// then un-registers the handler if it was registered with `insert_once` and has been run.
+    ///
+    /// This is otherwise the same operation as `call`.
+    pub fn call_once_aware<T: Any>(&mut self, msg: T) -> bool {
+        let handled = self.call(msg);
+
+        let id = TypeId::of::<T>();
+        if let Some(&Slot { handler: SlotHandler::Once(_, ref spent), .. }) = self.slots.get(&id) {
+            if spent.get() {
+                self.slots.remove(&id);
+            }
+        }
+
+        handled
+    }
+
+    /// Calls the handler with the given message, returning a `CallOutcome` describing what
+    /// happened to it.
+    ///
+    /// This is the same operation as `call`, but allows you to tell apart the different reasons a
+    /// handler may or may not have been run.
+    pub fn dispatch<T: Any>(&self, msg: T) -> CallOutcome {
+        self.dispatch_timed(msg, None)
+    }
+
+    /// Calls the handler with the given message, returning whether it was registered, and if so,
+    /// whether it accepted the message.
+    ///
+    /// This is the same operation as `dispatch`, named for use with handlers registered with
+    /// `insert_filter`: it returns `CallOutcome::Unregistered` if there's no handler,
+    /// `CallOutcome::Declined` if the handler returned false, and `CallOutcome::Handled` if it
+    /// returned true. This allows a message to be passed on to another map only when this one
+    /// didn't take it.
+    ///
+    /// ```rust
+    /// use handler_map::{CallOutcome, HandlerMap};
+    ///
+    /// let mut map = HandlerMap::new();
+    /// map.insert_filter(|n: u32| n < 10);
+    ///
+    /// assert_eq!(map.call_filter(5u32), CallOutcome::Handled);
+    /// assert_eq!(map.call_filter(50u32), CallOutcome::Declined);
+    /// assert_eq!(map.call_filter(5u8), CallOutcome::Unregistered);
+    /// ```
+    pub fn call_filter<T: Any>(&self, msg: T) -> CallOutcome {
+        self.dispatch(msg)
+    }
+
+    /// Calls the handler with the given message, returning how long the handler took to run if
+    /// it was called.
+    ///
+    /// The measurement only covers the execution of the handler itself, not the lookup of the
+    /// handler in the map or any validator attached to it. This returns `None` whenever the handler
+    /// wasn't run, including when it's a single-shot handler that was already consumed.
+    pub fn call_timed<T: Any>(&self, msg: T) -> Option<Duration> {
+        let mut elapsed = None;
+        self.dispatch_timed(msg, Some(&mut elapsed));
+        elapsed
+    }
+
+    /// Dispatches the given message, and if `elapsed` is given, stores the time the handler took
+    /// to run in it.
+    fn dispatch_timed<T: Any>(&self, msg: T, elapsed: Option<&mut Option<Duration>>)
+        -> CallOutcome
+    {
+        let id = TypeId::of::<T>();
+        self.record_dispatch(id, &msg);
+
+        let slot = match self.slots.get(&id) {
+            Some(slot) => slot,
+            None => {
+                let msg = match self.dispatch_converted(msg) {
+                    Ok(outcome) => return outcome,
+                    Err(msg) => msg,
+                };
+                if let Some(ref parent) = self.parent {
+                    return parent.dispatch_timed(msg, elapsed);
+                }
+                if let Some(ref fallback) = self.fallback {
+                    fallback(&msg);
+                    return CallOutcome::Fallback;
+                }
+                return self.unhandled(msg);
+            }
+        };
+        if self.is_disabled(slot) {
+            return CallOutcome::Disabled;
+        }
+        if let SlotHandler::Async(_) | SlotHandler::Cascade(_) = slot.handler {
+            return CallOutcome::NotCallable;
+        }
+
+        let mut took = None;
+        let timed = elapsed.is_some() || self.timing_observer.is_some();
+        let timer = if timed { Some(&mut took) } else { None };
+        let outcome = match slot.run(msg, &|| self.type_names(), timer) {
+            Ok(outcome) => outcome,
+            Err(msg) => self.unhandled(msg),
+        };
+
+        if let Some(took) = took {
+            if let Some(ref observer) = self.timing_observer {
+                observer(id, took);
+            }
+            if let Some(elapsed) = elapsed {
+                *elapsed = Some(took);
+            }
+        }
+        outcome
+    }
+
+    /// Counts a message of the given type for `with_metrics` and passes it to the tee set with
+    /// `set_tee`, before it's dispatched.
+    fn record_dispatch(&self, id: TypeId, msg: &dyn Any) {
+        if let Some(ref metrics) = self.metrics {
+            metrics.record(id);
+        }
+        if let Some(ref tee) = self.tee {
+            tee(msg);
+        }
+    }
+
+    /// Disposes of a message which had no handler to receive it, saving it in the dead-letter
+    /// queue if that's enabled.
+    fn unhandled<T: Any>(&self, msg: T) -> CallOutcome {
+        if let Some(ref dead) = self.dead_letters {
+            dead.borrow_mut().push(msg);
+        }
+        CallOutcome::Unregistered
+    }
+
+    /// Marks the handler for the given message type as "single-shot", so that it's only run the
+    /// first time it's called. Returns false if there is no handler registered for that type.
+    ///
+    /// After the first `call`, subsequent calls for that type skip the handler but still count as
+    /// handled: `call` returns true, and `dispatch` returns `CallOutcome::AlreadyConsumed`. Unlike
+    /// removing the handler after it runs, the handler stays registered, so `is_registered`
+    /// continues to return true, and the handler can be re-armed with `reset_single_shot`.
+    ///
+    /// This applies to handlers registered with `insert` and its variants, and to handlers
+    /// registered with `insert_async`, for which `call_async` returns `None` once the handler has
+    /// been run. Handlers registered with `insert_cascade` can't be marked, so for those this
+    /// returns false without marking them. Inserting a new handler for the type clears the mark.
+    pub fn mark_single_shot<T: Any>(&mut self) -> bool {
+        let id = TypeId::of::<T>();
+        match self.slots.get_mut(&id) {
+            Some(&mut Slot { handler: SlotHandler::Cascade(_), .. }) | None => false,
+            Some(slot) => {
+                slot.single_shot = Some(Cell::new(false));
+                true
+            }
+        }
+    }
+
+    /// Re-arms a single-shot handler for the given message type, so that it will run again the
+    /// next time it's called. Returns false if there is no single-shot handler registered for that
+    /// type.
+    pub fn reset_single_shot<T: Any>(&mut self) -> bool {
+        let id = TypeId::of::<T>();
+        match self.slots.get(&id).and_then(|slot| slot.single_shot.as_ref()) {
+            Some(consumed) => {
+                consumed.set(false);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the names of all the registered message types, in alphabetical order.
+    fn type_names(&self) -> Vec<&'static str> {
+        let mut names = self.slots.iter().map(|(_, slot)| slot.name).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Looks up the `TypeId`s of the given message type names, as they were registered in this
+    /// map.
+    ///
+    /// Each name is compared against the output of `std::any::type_name` for the registered
+    /// message types, and the resulting `Vec` has one entry for each given name, in the same
+    /// order. Names which don't correspond to a registered handler resolve to `None`.
+    ///
+    /// Note that the exact output of `type_name` is not guaranteed to be stable across compiler
+    /// versions, so this is best used for diagnostics and validation tooling rather than for
+    /// persistent identifiers.
+    pub fn resolve_names(&self, names: &[&str]) -> Vec<Option<TypeId>> {
+        let index = self.slots.iter()
+            .map(|(id, slot)| (slot.name, *id))
+            .collect::<HashMap<_, _>>();
+
+        names.iter().map(|name| index.get(name).cloned()).collect()
+    }
+
+    /// Returns the name of the message type with the given `TypeId`, as given by
+    /// `std::any::type_name`, if a handler is registered for it.
+    ///
+    /// This covers the handlers registered with `insert` and its variants. Handlers registered
+    /// with `insert_raw` are recorded as `"<raw>"`, since their message type isn't known. As with
+    /// `resolve_names`, the output of `type_name` is not guaranteed to be stable, so this is
+    /// intended for diagnostics only, like logging the messages that reach the fallback handler.
+    pub fn type_name_of(&self, id: TypeId) -> Option<&'static str> {
+        self.slots.get(&id).map(|slot| slot.name)
+    }
+
+    /// Returns the names of the message types with a handler registered in the map, as given by
+    /// `std::any::type_name`, for tools which generate code or documentation from a map.
+    ///
+    /// The names are in the same order as `registered_types`: in a map created with `ordered`,
+    /// that's the order of their `TypeId`s, which is the same every time the program runs, and in
+    /// a map created with `with_store`, it's the order of the store's `iter`. In other maps, the
+    /// order is unspecified, and may differ between runs. Like `type_name_of`, handlers
+    /// registered with `insert_raw` are listed as `"<raw>"`, and the names themselves aren't
+    /// guaranteed to be stable between compiler versions.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    ///
+    /// let mut map = HandlerMap::ordered();
+    /// map.insert(|_: u8| {});
+    /// map.insert(|_: String| {});
+    ///
+    /// let mut names = map.schema();
+    /// names.sort();
+    /// assert_eq!(names, ["alloc::string::String", "u8"]);
+    /// ```
+    pub fn schema(&self) -> Vec<&'static str> {
+        self.slots.iter().map(|(_, slot)| slot.name).collect()
+    }
+
+    /// Returns the `TypeId`s of all the registered message types whose type name starts with the
+    /// given prefix.
+    ///
+    /// Since type names include their module path, this can be used to select all the message
+    /// types from a given module, like `"my_crate::audio::"`. The order of the returned ids is
+    /// unspecified.
+    ///
+    /// As with `resolve_names`, the output of `std::any::type_name` is not guaranteed to be
+    /// stable, so this is best-effort and intended for development tooling.
+    pub fn type_ids_with_prefix(&self, prefix: &str) -> Vec<TypeId> {
+        self.slots.iter()
+            .filter(|&(_, slot)| slot.name.starts_with(prefix))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Un-registers handlers based on whether their message type's name starts with the given
+    /// prefix.
+    ///
+    /// If `keep` is true, only the handlers whose type name starts with `prefix` are kept, and all
+    /// others are removed. If `keep` is false, the handlers whose type name starts with `prefix`
+    /// are removed, and all others are kept.
+    ///
+    /// As with `resolve_names`, the output of `std::any::type_name` is not guaranteed to be
+    /// stable, so this is best-effort and intended for development tooling.
+    pub fn retain_by_name_prefix(&mut self, prefix: &str, keep: bool) {
+        self.slots.retain(&mut |_, slot| slot.name.starts_with(prefix) == keep);
+    }
+}
+
+/// The result of dispatching a message with `HandlerMap::dispatch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallOutcome {
+    /// No handler was registered for the message type.
+    Unregistered,
+    /// The message was passed to its handler.
+    Handled,
+    /// No handler was registered for the message type, so the message was passed to the
+    /// fallback handler set with `set_fallback`.
+    Fallback,
+    /// The message type's handler was marked as single-shot and has already been run, so the
+    /// message was dropped without calling it.
+    AlreadyConsumed,
+    /// The message was rejected by the validator given to `insert_validated`, so it wasn't
+    /// passed to the handler.
+    ValidationFailed(ValidationError),
+    /// The message type's handler is in a group that was disabled with
+    /// `HandlerMap::set_group_enabled`, so the message was dropped without calling it.
+    Disabled,
+    /// The message was passed to its handler, which was registered with
+    /// `HandlerMap::insert_filter`, and declined it by returning false.
+    Declined,
+    /// The message type's handler is one that `HandlerMap::call` doesn't run, like one
+    /// registered with `insert_async` or `insert_cascade`, so the message was dropped without
+    /// calling it.
+    NotCallable,
+}
+
+/// The identity of a `HandlerMap`, returned by `HandlerMap::id`.
+///
+/// Each map is given a new id when it's created, which stays the same for as long as the map
+/// exists, and is never given to another map in the same process, even after the map is
+/// dropped. This allows maps to be told apart, or used as keys in other collections, without
+/// comparing their handlers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HandlerMapId(u64);
+
+impl HandlerMapId {
+    /// Creates a new id, different from every id created before it.
+    fn next() -> HandlerMapId {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        HandlerMapId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Error returned by a message validator registered with `HandlerMap::insert_validated`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    message: String,
+}
+
+impl ValidationError {
+    /// Creates a new `ValidationError` with the given description of what was wrong with the
+    /// message.
+    pub fn new<S: Into<String>>(message: S) -> ValidationError {
+        ValidationError {
+            message: message.into(),
+        }
+    }
+
+    /// Returns the description of what was wrong with the message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "message failed validation: {}", self.message)
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Error returned by `HandlerMap::try_insert_bounded` when the map already has as many handlers as
+/// it's allowed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityExceeded {
+    max: usize,
+}
+
+impl CapacityExceeded {
+    /// Returns the maximum number of handlers the map was allowed to hold.
+    pub fn max(&self) -> usize {
+        self.max
+    }
+}
+
+impl fmt::Display for CapacityExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "handler map is limited to {} handlers", self.max)
+    }
+}
+
+impl Error for CapacityExceeded {}
+
+#[cfg(test)]
+mod tests {
+    use super::HandlerMap;
+
+    #[test]
+    fn it_works() {
+        struct MyMessage;
+        fn respond(_: MyMessage) {}
+
+        let mut map = HandlerMap::new();
+        map.insert(respond);
+
+        assert!(map.call(MyMessage));
+    }
+
+    #[test]
+    fn no_handler() {
+        struct MyMessage;
+
+        let map = HandlerMap::new();
+
+        assert!(!map.call(MyMessage));
+    }
+
+    #[test]
+    fn handler_is_called() {
+        use std::sync::Arc;
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering::SeqCst;
+
+        let mut map = HandlerMap::new();
+
+        struct FancyCaller;
+        let acc = Arc::new(AtomicUsize::new(0));
+        {
+            let acc = acc.clone();
+            map.insert(move |_: FancyCaller| {
+                acc.fetch_add(1, SeqCst);
+            });
+        }
+
+        assert!(map.call(FancyCaller));
+        assert!(map.call(FancyCaller));
+        assert!(map.call(FancyCaller));
 
         assert_eq!(acc.load(SeqCst), 3);
     }
+
+    #[test]
+    fn single_shot() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+        use CallOutcome;
+
+        struct Init;
+
+        let acc = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        {
+            let acc = acc.clone();
+            map.insert(move |_: Init| acc.set(acc.get() + 1));
+        }
+        assert!(map.mark_single_shot::<Init>());
+
+        assert_eq!(map.dispatch(Init), CallOutcome::Handled);
+        assert_eq!(map.dispatch(Init), CallOutcome::AlreadyConsumed);
+        assert!(map.call(Init));
+        assert!(map.is_registered::<Init>());
+        assert_eq!(acc.get(), 1);
+
+        assert!(map.reset_single_shot::<Init>());
+        assert_eq!(map.dispatch(Init), CallOutcome::Handled);
+        assert_eq!(map.dispatch(Init), CallOutcome::AlreadyConsumed);
+        assert_eq!(acc.get(), 2);
+
+        assert!(!map.mark_single_shot::<()>());
+        assert!(!map.reset_single_shot::<()>());
+    }
+
+    #[test]
+    fn validated() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+        use {CallOutcome, ValidationError};
+
+        struct Percent(u32);
+
+        let acc = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        {
+            let acc = acc.clone();
+            map.insert_validated(
+                |msg: &Percent| if msg.0 <= 100 {
+                    Ok(())
+                } else {
+                    Err(ValidationError::new("percentage out of range"))
+                },
+                move |msg: Percent| acc.set(acc.get() + msg.0),
+            );
+        }
+
+        assert_eq!(map.dispatch(Percent(150)),
+                   CallOutcome::ValidationFailed(ValidationError::new("percentage out of range")));
+        assert!(!map.call(Percent(101)));
+        assert_eq!(acc.get(), 0);
+
+        assert_eq!(map.dispatch(Percent(50)), CallOutcome::Handled);
+        assert_eq!(acc.get(), 50);
+    }
+
+    #[test]
+    fn would_run() {
+        use ValidationError;
+
+        struct Small(u32);
+
+        let mut map = HandlerMap::new();
+        assert!(!map.would_run(&Small(2)));
+
+        map.insert_validated(
+            |msg: &Small| if msg.0 < 10 { Ok(()) } else { Err(ValidationError::new("too big")) },
+            |msg: Small| assert!(msg.0 < 10),
+        );
+        assert!(map.would_run(&Small(2)));
+        assert!(!map.would_run(&Small(30)));
+
+        map.mark_single_shot::<Small>();
+        assert!(map.would_run(&Small(4)));
+        assert!(map.call(Small(4)));
+        assert!(!map.would_run(&Small(4)));
+    }
+
+    #[test]
+    fn get_caller() {
+        use std::cell::Cell;
+
+        let total = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert(|n: u32| total.set(total.get() + n));
+        map.insert_async(|_: u8| ::std::future::ready(()));
+
+        {
+            let caller = map.get_caller::<u32>().unwrap();
+            for n in 0..5 {
+                caller(n);
+            }
+        }
+        assert_eq!(total.get(), 10);
+        assert!(map.get_caller::<u8>().is_none());
+        assert!(map.get_caller::<u64>().is_none());
+    }
+
+    #[test]
+    fn call_any() {
+        use std::any::Any;
+        use std::cell::Cell;
+
+        struct Ping(u32);
+        struct Pong;
+
+        let total = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert(|msg: Ping| total.set(total.get() + msg.0));
+        map.insert_ref(|_: &Pong| total.set(total.get() + 100));
+
+        let msgs: Vec<Box<dyn Any>> = vec![Box::new(Ping(1)), Box::new(Pong), Box::new(Ping(2))];
+        for msg in msgs {
+            assert_eq!(map.call_any(msg).ok(), Some(true));
+        }
+        assert_eq!(total.get(), 103);
+
+        let missed = map.call_any(Box::new(5u8)).unwrap_err();
+        assert_eq!(missed.downcast_ref::<u8>(), Some(&5));
+    }
+
+    #[test]
+    fn handler_map_macro() {
+        use std::cell::Cell;
+
+        struct Ping;
+        fn handle_ping(_: Ping) {}
+
+        let total = Cell::new(0);
+        let map = handler_map![handle_ping, |n: u32| total.set(total.get() + n)];
+        assert!(map.is_registered::<Ping>());
+        assert!(map.call(5u32));
+        assert_eq!(total.get(), 5);
+
+        let empty: HandlerMap = handler_map![];
+        assert!(!empty.call(Ping));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "was called with a `u8`")]
+    fn slot_type_check() {
+        use std::any::TypeId;
+
+        let mut map = HandlerMap::new();
+        map.insert(|_: u32| {});
+        let slot = &map.slots[&TypeId::of::<u32>()];
+        let _ = slot.run(5u8, &Vec::new, None);
+    }
+
+    #[test]
+    fn message_layouts() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        struct Empty;
+        struct Large([u64; 16]);
+        struct Tracked(Rc<Cell<u32>>);
+
+        impl Drop for Tracked {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let sum = Cell::new(0);
+        let drops = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        map.insert(|_: Empty| sum.set(sum.get() + 1));
+        map.insert(|msg: Large| sum.set(sum.get() + msg.0.iter().sum::<u64>()));
+        map.insert(|msg: Tracked| assert_eq!(msg.0.get(), 0));
+
+        assert!(map.call(Empty));
+        let mut large = [0; 16];
+        for (i, n) in large.iter_mut().enumerate() {
+            *n = i as u64;
+        }
+        assert!(map.call(Large(large)));
+        assert_eq!(sum.get(), 121);
+
+        assert!(map.call(Tracked(drops.clone())));
+        assert_eq!(drops.get(), 1);
+        assert_eq!(Rc::strong_count(&drops), 1);
+    }
+
+    #[test]
+    fn call_each() {
+        use std::any::{Any, TypeId};
+        use std::cell::Cell;
+
+        let total = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert(|n: u32| total.set(total.get() + n));
+        map.insert(|n: u64| total.set(total.get() + n as u32 * 10));
+        map.insert(|_: u8| total.set(total.get() + 1000));
+
+        let ran = map.call_each(|id| -> Option<Box<dyn Any>> {
+            if id == TypeId::of::<u32>() {
+                Some(Box::new(1u32))
+            } else if id == TypeId::of::<u64>() {
+                Some(Box::new(2u64))
+            } else {
+                // the wrong type is skipped, rather than sent to the `u32` handler
+                Some(Box::new(5u32))
+            }
+        });
+        assert_eq!(ran, 2);
+        assert_eq!(total.get(), 21);
+    }
+
+    #[test]
+    fn dispatch_batch() {
+        use std::any::Any;
+        use std::cell::Cell;
+        use ValidationError;
+
+        let total = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert(|n: u32| total.set(total.get() + n));
+        map.insert_validated(
+            |&n: &u8| if n > 0 { Ok(()) } else { Err(ValidationError::new("zero")) },
+            |_: u8| total.set(total.get() + 1000),
+        );
+
+        let batch = || -> Vec<Box<dyn Any>> {
+            vec![Box::new(1u32), Box::new(0u8), Box::new('x'), Box::new(2u32), Box::new(7u64)]
+        };
+        assert_eq!(map.dispatch_batch(batch()), [true, false, false, true, false]);
+        assert_eq!(total.get(), 3);
+
+        let (handled, rest) = map.dispatch_batch_collecting(batch());
+        assert_eq!(handled, 2);
+        assert_eq!(total.get(), 6);
+        assert_eq!(rest.len(), 2);
+        assert_eq!(*rest[0].downcast_ref::<char>().unwrap(), 'x');
+        assert_eq!(*rest[1].downcast_ref::<u64>().unwrap(), 7);
+    }
+
+    #[test]
+    fn insert_variant() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        struct Opened;
+        struct Closed;
+
+        enum FileEvent {
+            Opened(Opened),
+            Closed(Closed),
+        }
+
+        let opened = Cell::new(0);
+        let closed = Cell::new(0);
+        let sink: Rc<dyn Fn(FileEvent)> = Rc::new(|event| match event {
+            FileEvent::Opened(_) => opened.set(opened.get() + 1),
+            FileEvent::Closed(_) => closed.set(closed.get() + 1),
+        });
+
+        let mut map = HandlerMap::new();
+        map.insert_variant(FileEvent::Opened, sink.clone());
+        map.insert_variant(FileEvent::Closed, sink.clone());
+
+        assert!(map.call(Opened));
+        assert!(map.call(Opened));
+        assert!(map.call(Closed));
+        assert_eq!((opened.get(), closed.get()), (2, 1));
+        assert_eq!(Rc::strong_count(&sink), 3);
+    }
+
+    #[test]
+    fn exercise_all() {
+        use std::cell::Cell;
+
+        #[derive(Default)]
+        struct Reset(u32);
+
+        let total = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert_with_default(|msg: Reset| total.set(total.get() + msg.0 + 1));
+        map.insert_with_default(|msg: u32| total.set(total.get() + msg + 10));
+        map.insert(|_: u8| total.set(total.get() + 100));
+
+        assert_eq!(map.exercise_all(), 2);
+        assert_eq!(total.get(), 11);
+
+        map.insert(|_: u32| {});
+        assert_eq!(map.exercise_all(), 1);
+    }
+
+    #[test]
+    fn insert_fn() {
+        fn first(_: u32) {}
+        fn second(_: u32) {
+            // give the two functions different bodies, so they can't be merged into one address
+            let _ = std::hint::black_box(1);
+        }
+
+        // each cast of a function to a pointer may give a different address, so cast once
+        let first = first as fn(u32);
+
+        let mut map = HandlerMap::new();
+        assert!(!map.insert_fn(first));
+        assert!(map.insert_fn(first));
+        assert!(!map.insert_fn(second));
+
+        map.insert(second);
+        assert!(!map.insert_fn(second));
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn tracing_span() {
+        use std::any::type_name;
+        use std::fmt;
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        /// Subscriber which records the name and `msg` field of each new span.
+        struct Recorder(Arc<Mutex<Vec<(&'static str, String)>>>);
+
+        struct MsgField(String);
+
+        impl Visit for MsgField {
+            fn record_str(&mut self, field: &Field, value: &str) {
+                if field.name() == "msg" {
+                    self.0 = value.to_string();
+                }
+            }
+
+            fn record_debug(&mut self, _: &Field, _: &dyn fmt::Debug) {}
+        }
+
+        impl Subscriber for Recorder {
+            fn enabled(&self, _: &Metadata) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes) -> Id {
+                let mut msg = MsgField(String::new());
+                span.record(&mut msg);
+                let mut spans = self.0.lock().unwrap();
+                spans.push((span.metadata().name(), msg.0));
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, _: &Id, _: &Record) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        struct Traced;
+
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let mut map = HandlerMap::new();
+        map.insert(|_: Traced| {});
+
+        tracing::subscriber::with_default(Recorder(spans.clone()), || {
+            assert!(map.call(Traced));
+            assert!(!map.call(1u8));
+        });
+
+        assert_eq!(*spans.lock().unwrap(), [("handler", type_name::<Traced>().to_string())]);
+    }
+
+    #[test]
+    fn tee() {
+        use std::any::Any;
+        use std::cell::RefCell;
+
+        let log = RefCell::new(Vec::new());
+        let mut map = HandlerMap::new();
+        map.set_tee(|msg: &dyn Any| {
+            let n = msg.downcast_ref::<u32>().cloned().unwrap_or(0);
+            log.borrow_mut().push(("tee", n));
+        });
+        map.insert(|n: u32| log.borrow_mut().push(("handler", n)));
+
+        assert!(map.call(1u32));
+        assert!(!map.call(2u8));
+        assert!(!map.call_ref(&3u32));
+        assert!(map.call_any(Box::new(4u32)).unwrap());
+
+        assert_eq!(*log.borrow(), [
+            ("tee", 1), ("handler", 1),
+            ("tee", 0),
+            ("tee", 3),
+            ("tee", 4), ("handler", 4),
+        ]);
+    }
+
+    #[test]
+    fn on_replace() {
+        use std::any::TypeId;
+        use std::cell::RefCell;
+
+        struct Guard<'a>(&'a RefCell<Vec<&'static str>>);
+
+        impl<'a> Drop for Guard<'a> {
+            fn drop(&mut self) {
+                self.0.borrow_mut().push("dropped");
+            }
+        }
+
+        let log = RefCell::new(Vec::new());
+        let mut map = HandlerMap::new();
+        map.on_replace(|id| {
+            assert_eq!(id, TypeId::of::<u32>());
+            log.borrow_mut().push("replaced");
+        });
+
+        let guard = Guard(&log);
+        map.insert(move |_: u32| { let _ = &guard; });
+        assert!(log.borrow().is_empty());
+
+        map.insert_ref(|_: &u32| {});
+        assert_eq!(*log.borrow(), ["replaced", "dropped"]);
+
+        map.remove::<u32>();
+        map.insert(|_: u32| {});
+        assert_eq!(log.borrow().len(), 2);
+    }
+
+    #[test]
+    fn call_or_return() {
+        use std::cell::Cell;
+
+        struct Routed(u32);
+
+        let total = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.enable_dead_letter(4);
+        map.insert(|msg: Routed| total.set(total.get() + msg.0));
+
+        assert!(map.call_or_return(Routed(3)).is_ok());
+        assert_eq!(total.get(), 3);
+
+        assert_eq!(map.call_or_return(7u8), Err(7));
+        assert_eq!(map.dead_letter_count(), 0);
+
+        map.set_fallback(|_| total.set(total.get() + 100));
+        assert_eq!(map.call_or_return(7u8), Ok(()));
+        assert_eq!(total.get(), 103);
+    }
+
+    #[test]
+    fn try_dispatch() {
+        use DispatchError;
+
+        #[derive(Debug, PartialEq)]
+        struct Job(u32);
+
+        let mut map = HandlerMap::new();
+        map.insert_in_group(1, |_: Job| {});
+
+        assert!(map.try_dispatch(Job(1)).is_ok());
+        match map.try_dispatch(5u8) {
+            Err(DispatchError::Unregistered(5)) => {}
+            other => panic!("expected an unregistered message, got {:?}", other),
+        }
+
+        map.set_group_enabled(1, false);
+        match map.try_dispatch(Job(2)) {
+            Err(err @ DispatchError::Disabled(_)) => assert_eq!(err.into_inner(), Some(Job(2))),
+            other => panic!("expected a disabled handler, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn call_boxed_chain() {
+        use std::cell::Cell;
+
+        let hits = Cell::new((0, 0));
+        let mut map_a = HandlerMap::new();
+        map_a.insert(|n: u32| hits.set((hits.get().0 + n, hits.get().1)));
+        let mut map_b = HandlerMap::new();
+        map_b.insert(|n: u8| hits.set((hits.get().0, hits.get().1 + n)));
+
+        assert!(map_a.call_boxed(Box::new(3u8)).or_else(|m| map_b.call_boxed(m)).is_ok());
+        assert!(map_a.call_boxed(Box::new(4u32)).or_else(|m| map_b.call_boxed(m)).is_ok());
+        assert_eq!(hits.get(), (4, 3));
+
+        let missed = map_a.call_boxed(Box::new(5i64)).or_else(|m| map_b.call_boxed(m));
+        assert_eq!(missed.unwrap_err().downcast_ref::<i64>(), Some(&5));
+    }
+
+    #[test]
+    fn insert_raw() {
+        use std::any::{Any, TypeId};
+        use std::cell::Cell;
+
+        struct Script(&'static str);
+
+        let total = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert_raw(TypeId::of::<Script>(), |msg: Box<dyn Any>| {
+            total.set(total.get() + msg.downcast::<Script>().unwrap().0.len());
+        });
+
+        assert!(map.call(Script("abc")));
+        assert_eq!(map.call_any(Box::new(Script("de"))).ok(), Some(true));
+        assert_eq!(total.get(), 5);
+
+        map.mark_single_shot::<Script>();
+        assert!(map.call_any(Box::new(Script("f"))).is_ok());
+        assert!(map.call(Script("gh")));
+        assert_eq!(total.get(), 6);
+    }
+
+    #[test]
+    fn call_catch() {
+        struct Bad;
+        struct Good;
+
+        let mut map = HandlerMap::new();
+        map.insert(|_: Bad| panic!("bad handler"));
+        map.insert(|_: Good| {});
+
+        let payload = map.call_catch(Bad).unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"bad handler"));
+        assert_eq!(map.call_catch(Good).ok(), Some(true));
+        assert!(map.call(Good));
+        assert_eq!(map.call_catch(()).ok(), Some(false));
+    }
+
+    #[test]
+    fn drop_panics() {
+        use std::cell::Cell;
+        use std::panic::{self, AssertUnwindSafe};
+        use std::rc::Rc;
+
+        struct Bomb;
+
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                panic!("bomb");
+            }
+        }
+
+        struct Guard(Rc<Cell<u32>>);
+
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let dropped = Rc::new(Cell::new(0));
+        let make_map = || {
+            let mut map = HandlerMap::new();
+            let bomb = Bomb;
+            map.insert(move |_: u8| { let _ = &bomb; });
+            map.set_catch_drop_panics(true);
+            let guard = Guard(dropped.clone());
+            map.insert(move |_: u16| { let _ = &guard; });
+            let guard = Guard(dropped.clone());
+            map.insert_multi(move |_: u16| { let _ = &guard; });
+            map
+        };
+
+        drop(make_map());
+        assert_eq!(dropped.get(), 2);
+
+        let mut map = make_map();
+        assert!(map.remove::<u8>());
+        map.clear();
+        assert_eq!(dropped.get(), 4);
+
+        let outer = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _map = make_map();
+            panic!("outer");
+        }));
+        assert!(outer.is_err());
+        assert_eq!(dropped.get(), 6);
+
+        // without `set_catch_drop_panics`, the panic reaches the caller
+        let mut map = make_map();
+        map.set_catch_drop_panics(false);
+        let removed = panic::catch_unwind(AssertUnwindSafe(|| map.remove::<u8>()));
+        assert!(removed.is_err());
+        assert!(!map.is_registered::<u8>());
+        drop(map);
+        assert_eq!(dropped.get(), 8);
+    }
+
+    #[test]
+    fn try_insert() {
+        struct Claim;
+
+        let mut map = HandlerMap::new();
+        let first = |_: Claim| {};
+        let second = |_: Claim| panic!("the second handler shouldn't be registered");
+
+        assert!(map.try_insert(first).is_ok());
+        let rejected = map.try_insert(second).err().unwrap();
+        assert!(map.call(Claim));
+
+        map.remove::<Claim>();
+        assert!(map.try_insert(rejected).is_ok());
+    }
+
+    #[test]
+    fn fallback() {
+        use std::any::Any;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use CallOutcome;
+
+        let missed = Rc::new(RefCell::new(Vec::new()));
+        let mut map = HandlerMap::new();
+        map.insert(|_: u8| {});
+        {
+            let missed = missed.clone();
+            map.set_fallback(move |msg: &dyn Any| {
+                missed.borrow_mut().push(msg.downcast_ref::<u32>().cloned());
+            });
+        }
+
+        assert!(map.call(5u8));
+        assert_eq!(map.dispatch(7u32), CallOutcome::Fallback);
+        assert!(map.call("unknown"));
+        assert_eq!(*missed.borrow(), vec![Some(7), None]);
+
+        map.clear_fallback();
+        assert!(!map.call(7u32));
+        assert_eq!(missed.borrow().len(), 2);
+    }
+
+    #[test]
+    fn call_lazy() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let built = Cell::new(false);
+        let make = || {
+            built.set(true);
+            5u32
+        };
+
+        let mut map = HandlerMap::new();
+        map.set_fallback(|_| panic!("the fallback shouldn't see an unbuilt message"));
+        assert!(!map.call_lazy(make));
+        assert!(!built.get());
+
+        let total = Cell::new(0);
+        let mut parent = HandlerMap::new();
+        parent.insert(|n: u32| total.set(total.get() + n));
+        let map = HandlerMap::new().with_parent(Rc::new(parent));
+        assert!(map.call_lazy(make));
+        assert!(built.get());
+        assert_eq!(total.get(), 5);
+    }
+
+    #[test]
+    fn ignore() {
+        use std::cell::Cell;
+        use CallOutcome;
+
+        let missed = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.set_fallback(|_| missed.set(missed.get() + 1));
+        map.insert(|_: u8| panic!("should have been replaced"));
+        map.ignore::<u8>();
+        map.ignore::<u16>();
+
+        assert!(map.call(5u8));
+        assert_eq!(map.dispatch(5u16), CallOutcome::Handled);
+        assert_eq!(missed.get(), 0);
+        assert_eq!(map.dispatch(5u32), CallOutcome::Fallback);
+        assert_eq!(missed.get(), 1);
+    }
+
+    #[test]
+    fn call_ref() {
+        use std::cell::Cell;
+
+        struct Big([u8; 64]);
+
+        let seen = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert_ref(|msg: &Big| seen.set(seen.get() + msg.0.len()));
+
+        let msg = Big([0; 64]);
+        assert!(map.call_ref(&msg));
+        assert!(map.call_ref(&msg));
+        assert!(map.call(msg));
+        assert_eq!(seen.get(), 192);
+
+        map.insert(|_: Big| {});
+        assert!(!map.call_ref(&Big([0; 64])));
+        assert!(!map.call_ref(&5u8));
+    }
+
+    #[test]
+    fn call_dyn() {
+        use std::any::{Any, TypeId};
+        use std::cell::Cell;
+
+        struct Big([u8; 64]);
+
+        let seen = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert_ref(|msg: &Big| seen.set(seen.get() + msg.0.len()));
+        map.insert(|_: u16| panic!("by-value handlers shouldn't be called"));
+        map.insert_raw(TypeId::of::<u32>(), |_| panic!("raw handlers shouldn't be called"));
+
+        let boxed: Box<dyn Any> = Box::new(Big([0; 64]));
+        assert!(map.call_dyn(&Big([0; 64])));
+        assert!(map.call_dyn(&*boxed));
+        assert!(!map.call_dyn(&boxed));
+        assert_eq!(seen.get(), 128);
+
+        assert!(!map.call_dyn(&5u8));
+        assert!(!map.call_dyn(&5u16));
+        assert!(!map.call_dyn(&5u32));
+
+        map.mark_single_shot::<Big>();
+        assert!(map.call_dyn(&*boxed));
+        assert!(map.call_dyn(&*boxed));
+        assert_eq!(seen.get(), 192);
+    }
+
+    #[test]
+    fn call_filter() {
+        use std::cell::Cell;
+
+        use CallOutcome;
+
+        let seen = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert_filter(|n: u32| {
+            seen.set(seen.get() + 1);
+            n % 2 == 1
+        });
+
+        assert_eq!(map.call_filter(1u32), CallOutcome::Handled);
+        assert_eq!(map.call_filter(2u32), CallOutcome::Declined);
+        assert_eq!(map.call_filter(2u8), CallOutcome::Unregistered);
+        assert!(map.call(2u32));
+        assert_eq!(map.call_any(Box::new(2u32)).ok(), Some(true));
+        assert_eq!(seen.get(), 4);
+    }
+
+    #[test]
+    fn insert_boxed() {
+        use std::any::{TypeId, type_name};
+        use std::cell::Cell;
+
+        struct Large([u64; 32]);
+
+        let total = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert_boxed(|msg: Large| total.set(total.get() + msg.0.iter().sum::<u64>()));
+        map.insert(|n: u64| total.set(total.get() + n));
+
+        assert!(map.call(Large([1; 32])));
+        assert!(map.call_via_box(Large([2; 32])));
+        assert!(map.call_via_box(100u64));
+        assert!(!map.call_via_box(5u8));
+        assert_eq!(total.get(), 32 + 64 + 100);
+        assert_eq!(map.type_name_of(TypeId::of::<Large>()), Some(type_name::<Large>()));
+
+        map.mark_single_shot::<Large>();
+        assert!(map.call_via_box(Large([1; 32])));
+        assert!(map.call_via_box(Large([1; 32])));
+        assert_eq!(total.get(), 32 + 64 + 100 + 32);
+    }
+
+    #[test]
+    fn insert_boxed_fn() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        struct Tick;
+
+        let ticks = Rc::new(Cell::new(0));
+        let handler: Box<dyn Fn(Tick)> = {
+            let ticks = ticks.clone();
+            Box::new(move |_| ticks.set(ticks.get() + 1))
+        };
+        let mut map = HandlerMap::new();
+        map.insert_boxed_fn(handler);
+
+        assert!(map.call(Tick));
+        assert!(map.call(Tick));
+        assert_eq!(ticks.get(), 2);
+        assert_eq!(Rc::strong_count(&ticks), 2);
+
+        assert!(map.remove::<Tick>());
+        assert_eq!(Rc::strong_count(&ticks), 1);
+        assert!(!map.call(Tick));
+    }
+
+    #[test]
+    fn call_mut_ref() {
+        struct Counter(u32);
+
+        let mut map = HandlerMap::new();
+        map.insert_mut_ref(|msg: &mut Counter| msg.0 += 1);
+
+        let mut msg = Counter(0);
+        assert!(map.call_mut_ref(&mut msg));
+        assert!(map.call_mut_ref(&mut msg));
+        assert_eq!(msg.0, 2);
+        assert!(!map.call_ref(&msg));
+        assert!(map.call(msg));
+
+        map.insert_ref(|msg: &Counter| assert_eq!(msg.0, 7));
+        assert!(map.call_mut_ref(&mut Counter(7)));
+
+        map.insert(|_: Counter| {});
+        assert!(!map.call_mut_ref(&mut Counter(0)));
+        assert!(!map.call_mut_ref(&mut 5u8));
+    }
+
+    #[test]
+    fn insert_mut() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        struct Push(u32);
+        struct DropFlag(Rc<Cell<bool>>);
+
+        impl Drop for DropFlag {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+
+        let dropped = Rc::new(Cell::new(false));
+        let total = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        {
+            let flag = DropFlag(dropped.clone());
+            let total = total.clone();
+            let mut buffer = Vec::new();
+            map.insert_mut(move |msg: Push| {
+                let _ = &flag;
+                buffer.push(msg.0);
+                total.set(buffer.iter().sum());
+            });
+        }
+
+        assert!(map.call_mut(Push(1)));
+        assert!(map.call(Push(2)));
+        assert_eq!(total.get(), 3);
+
+        assert!(!dropped.get());
+        map.remove::<Push>();
+        assert!(dropped.get());
+    }
+
+    #[test]
+    fn capacity() {
+        let mut map = HandlerMap::with_capacity(50);
+        assert!(map.capacity() >= 50);
+        assert!(map.is_empty());
+
+        map.insert(|_: u8| {});
+        map.reserve(100);
+        assert!(map.capacity() >= 101);
+
+        map.shrink_to_fit();
+        assert!(map.capacity() < 101);
+        assert!(map.call(0u8));
+    }
+
+    #[test]
+    fn wrap_handler() {
+        use std::cell::Cell;
+
+        struct Job(u32);
+
+        let total = Cell::new(0);
+        let mut map = HandlerMap::new();
+        assert!(!map.wrap_handler(|msg: Job, inner: &dyn Fn(Job)| inner(msg)));
+
+        map.insert(|msg: Job| total.set(total.get() + msg.0));
+        assert!(map.wrap_handler(|msg: Job, inner: &dyn Fn(Job)| {
+            if msg.0 > 1 {
+                inner(msg);
+            }
+        }));
+        assert!(map.wrap_handler(|msg: Job, inner: &dyn Fn(Job)| inner(Job(msg.0 * 10))));
+
+        assert!(map.call(Job(1)));
+        assert!(map.call(Job(2)));
+        assert_eq!(total.get(), 30);
+
+        map.insert_ref(|_: &Job| {});
+        assert!(!map.wrap_handler(|msg: Job, inner: &dyn Fn(Job)| inner(msg)));
+        assert!(map.call(Job(4)));
+        assert_eq!(total.get(), 30);
+    }
+
+    #[test]
+    fn alias_with() {
+        use std::cell::Cell;
+
+        struct Job(u32);
+        struct Task(u32);
+
+        let total = Cell::new(0);
+        let mut map = HandlerMap::new();
+        assert!(!map.alias_with(|msg: Task| Job(msg.0)));
+        assert!(!map.is_registered::<Task>());
+
+        map.insert(|msg: Job| total.set(total.get() + msg.0));
+        assert!(map.alias_with(|msg: Task| Job(msg.0 * 10)));
+        assert!(map.call(Job(1)));
+        assert!(map.call(Task(2)));
+        assert_eq!(total.get(), 21);
+        assert!(map.try_clone().is_none());
+
+        map.insert_ref(|_: &u8| {});
+        assert!(!map.alias_with(|msg: u16| msg as u8));
+        assert!(!map.is_registered::<u16>());
+    }
+
+    #[test]
+    fn id() {
+        use std::collections::HashSet;
+
+        let first = HandlerMap::new();
+        let second = HandlerMap::new();
+        let mut third: HandlerMap = HandlerMap::default();
+        third.insert_cloneable(|_: u8| {});
+        let copy = third.try_clone().unwrap();
+
+        let ids = [first.id(), second.id(), third.id(), copy.id()];
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), 4);
+        third.insert(|_: u8| {});
+        assert_eq!(third.id(), ids[2]);
+    }
+
+    #[test]
+    fn bucket_collisions() {
+        use std::hash::{BuildHasherDefault, Hasher};
+
+        /// Hasher which sends every key to the same bucket.
+        #[derive(Default)]
+        struct Constant;
+
+        impl Hasher for Constant {
+            fn finish(&self) -> u64 {
+                0
+            }
+
+            fn write(&mut self, _: &[u8]) {}
+        }
+
+        let mut map = HandlerMap::with_hasher(BuildHasherDefault::<Constant>::default());
+        assert_eq!(map.bucket_collisions(), 0);
+        map.insert(|_: u8| {});
+        assert_eq!(map.bucket_collisions(), 0);
+        map.insert(|_: u16| {});
+        map.insert(|_: u32| {});
+        assert_eq!(map.bucket_collisions(), 1);
+        assert!(map.call(5u32));
+
+        // the estimate is taken modulo the capacity, so more room doesn't separate equal hashes
+        map.reserve(100);
+        assert_eq!(map.bucket_collisions(), 1);
+    }
+
+    #[test]
+    fn remove() {
+        struct Msg;
+
+        let mut map = HandlerMap::new();
+        map.insert(|_: Msg| {});
+
+        assert!(map.remove::<Msg>());
+        assert!(!map.remove::<Msg>());
+
+        map.insert(|_: Msg| {});
+        assert!(map.remove_val(&Msg));
+        assert!(!map.remove_val(&Msg));
+        assert!(!map.call(Msg));
+    }
+
+    #[test]
+    fn swap_handlers() {
+        use std::any::TypeId;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        #[repr(transparent)]
+        struct Celsius(f64);
+        #[repr(transparent)]
+        struct Fahrenheit(f64);
+
+        let seen = Rc::new(Cell::new(""));
+        let mut map = HandlerMap::new();
+        {
+            let seen = seen.clone();
+            map.insert(move |c: Celsius| {
+                assert_eq!(c.0, 1.5);
+                seen.set("celsius");
+            });
+        }
+        {
+            let seen = seen.clone();
+            map.insert(move |f: Fahrenheit| {
+                assert_eq!(f.0, 2.5);
+                seen.set("fahrenheit");
+            });
+        }
+
+        let (c, f) = (TypeId::of::<Celsius>(), TypeId::of::<Fahrenheit>());
+        assert!(unsafe { map.swap_handlers(c, f) });
+        assert!(map.call(Celsius(2.5)));
+        assert_eq!(seen.get(), "fahrenheit");
+        assert!(map.call(Fahrenheit(1.5)));
+        assert_eq!(seen.get(), "celsius");
+        assert_eq!(map.type_name_of(c), Some(std::any::type_name::<Celsius>()));
+
+        assert!(!unsafe { map.swap_handlers(c, TypeId::of::<u8>()) });
+        assert!(map.is_registered::<Celsius>());
+    }
+
+    #[test]
+    fn with_hasher() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::BuildHasherDefault;
+
+        let mut map = HandlerMap::with_hasher(BuildHasherDefault::<DefaultHasher>::default());
+        map.insert(|x: u32| assert_eq!(x, 5));
+
+        assert!(map.call(5u32));
+        assert!(!map.call(5u64));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn ordered() {
+        use std::any::TypeId;
+        use std::cell::Cell;
+
+        let hits = Cell::new(0);
+        let mut map = HandlerMap::ordered();
+        map.insert(|_: String| {});
+        map.insert(|n: u8| hits.set(hits.get() + n));
+        map.insert(|_: bool| {});
+        map.insert(|_: ()| {});
+        map.entry::<u16>().or_default();
+
+        assert!(map.call(3u8));
+        assert!(!map.call(3u32));
+        assert_eq!(hits.get(), 3);
+        assert_eq!(map.capacity(), map.len());
+
+        let mut expected = vec![
+            TypeId::of::<String>(), TypeId::of::<u8>(), TypeId::of::<bool>(), TypeId::of::<()>(),
+            TypeId::of::<u16>(),
+        ];
+        expected.sort();
+        assert_eq!(map.registered_types().collect::<Vec<_>>(), expected);
+
+        map.retain(|id| *id != TypeId::of::<bool>());
+        expected.retain(|id| *id != TypeId::of::<bool>());
+        assert_eq!(map.into_iter().map(|(id, _)| id).collect::<Vec<_>>(), expected);
+
+        let mut cloneable = HandlerMap::ordered();
+        cloneable.insert_cloneable(|_: u8| {});
+        cloneable.insert_cloneable(|_: bool| {});
+        let copy = cloneable.try_clone().unwrap();
+        assert_eq!(copy.registered_types().collect::<Vec<_>>(),
+                   cloneable.registered_types().collect::<Vec<_>>());
+        assert_eq!(copy.capacity(), 2);
+    }
+
+    #[test]
+    fn registered_types() {
+        use std::any::{Any, TypeId};
+
+        let mut map = HandlerMap::new();
+        map.insert(|_: u8| {});
+        map.insert(|_: u16| {});
+        map.insert_multi(|_: u32| {});
+
+        let mut types = map.registered_types().collect::<Vec<_>>();
+        types.sort();
+        let mut expected = vec![TypeId::of::<u8>(), TypeId::of::<u16>()];
+        expected.sort();
+        assert_eq!(types, expected);
+
+        assert!(map.contains_type_id(TypeId::of::<u8>()));
+        assert!(!map.contains_type_id(TypeId::of::<u32>()));
+
+        let boxed: Box<dyn Any> = Box::new(5u16);
+        assert!(map.contains_any(&5u8));
+        assert!(map.contains_any(&*boxed));
+        assert!(!map.contains_any(&boxed));
+        assert!(!map.contains_any(&5u32));
+
+        let wanted = [TypeId::of::<u32>(), TypeId::of::<u8>(), TypeId::of::<u64>()];
+        assert_eq!(map.missing_handlers(&wanted), [TypeId::of::<u32>(), TypeId::of::<u64>()]);
+        assert!(map.missing_handlers(&wanted[1..2]).is_empty());
+    }
+
+    #[test]
+    fn covers() {
+        use std::any::TypeId;
+
+        let mut map = HandlerMap::new();
+        map.insert(|_: u8| {});
+        map.insert(|_: u16| {});
+        map.insert_multi(|_: u64| {});
+
+        let mut other = HandlerMap::ordered();
+        assert!(map.covers(&other));
+        assert_eq!(map.coverage_diff(&other).1, []);
+
+        other.insert(|_: u8| {});
+        assert!(map.covers(&other));
+        assert!(!other.covers(&map));
+        assert_eq!(map.coverage_diff(&other), (vec![TypeId::of::<u16>()], vec![]));
+
+        other.insert(|_: u32| {});
+        other.insert(|_: u64| {});
+        assert!(!map.covers(&other));
+        let (ours, mut theirs) = map.coverage_diff(&other);
+        theirs.sort();
+        let mut expected = vec![TypeId::of::<u32>(), TypeId::of::<u64>()];
+        expected.sort();
+        assert_eq!(ours, [TypeId::of::<u16>()]);
+        assert_eq!(theirs, expected);
+    }
+
+    #[test]
+    fn clear() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        struct DropFlag(Rc<Cell<u32>>);
+
+        impl Drop for DropFlag {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let dropped = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        assert!(map.is_empty());
+        {
+            let single = DropFlag(dropped.clone());
+            let multi = DropFlag(dropped.clone());
+            map.insert(move |_: u8| { let _ = &single; });
+            map.insert(|_: u16| {});
+            map.insert_multi(move |_: u8| { let _ = &multi; });
+        }
+        assert_eq!(map.len(), 2);
+        assert!(!map.is_empty());
+
+        map.clear();
+        assert_eq!(dropped.get(), 2);
+        assert!(map.is_empty());
+        assert_eq!(map.call_all(0u8), 0);
+    }
+
+    #[test]
+    fn insert_once() {
+        use std::cell::RefCell;
+
+        struct Setup(String);
+
+        let log = RefCell::new(Vec::new());
+        let mut map = HandlerMap::new();
+        map.insert_once(|Setup(name): Setup| log.borrow_mut().push(name));
+
+        assert!(map.call(Setup("first".into())));
+        assert!(!map.call(Setup("second".into())));
+        assert!(map.is_registered::<Setup>());
+
+        map.insert_once(|Setup(name): Setup| log.borrow_mut().push(name));
+        assert!(map.call_once_aware(Setup("third".into())));
+        assert!(!map.is_registered::<Setup>());
+        assert!(!map.call_once_aware(Setup("fourth".into())));
+
+        assert_eq!(*log.borrow(), ["first", "third"]);
+    }
+
+    #[test]
+    fn insert_lazy() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        struct Tick;
+
+        let built = Rc::new(Cell::new(0));
+        let ticks = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        {
+            let built = built.clone();
+            let ticks = ticks.clone();
+            map.insert_lazy(move || {
+                built.set(built.get() + 1);
+                move |_: Tick| ticks.set(ticks.get() + 1)
+            });
+        }
+
+        assert!(map.is_registered::<Tick>());
+        assert_eq!(built.get(), 0);
+
+        assert!(map.call(Tick));
+        assert!(map.call(Tick));
+        assert_eq!(built.get(), 1);
+        assert_eq!(ticks.get(), 2);
+    }
+
+    #[test]
+    fn with_registry() {
+        use std::any::type_name;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        struct Announce;
+        struct Ping;
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut map = HandlerMap::new();
+        {
+            let seen = seen.clone();
+            map.insert_with_registry(move |names: &[&'static str], _: Announce| {
+                *seen.borrow_mut() = names.to_vec();
+            });
+        }
+        map.insert(|_: Ping| {});
+
+        assert!(map.call(Announce));
+
+        let mut expected = vec![type_name::<Announce>(), type_name::<Ping>()];
+        expected.sort();
+        assert_eq!(*seen.borrow(), expected);
+    }
+
+    #[test]
+    fn bounded_insert() {
+        struct A;
+        struct B;
+        struct C;
+
+        let mut map = HandlerMap::new();
+        map.set_max_handlers(2);
+        assert_eq!(map.max_handlers(), Some(2));
+
+        assert!(map.try_insert_bounded(|_: A| {}).is_ok());
+        assert!(map.try_insert_bounded(|_: B| {}).is_ok());
+        assert!(map.try_insert_bounded(|_: B| {}).is_ok());
+        assert_eq!(map.try_insert_bounded(|_: C| {}).unwrap_err().max(), 2);
+        assert!(!map.is_registered::<C>());
+    }
+
+    #[test]
+    fn name_prefix() {
+        use std::any::TypeId;
+
+        mod audio {
+            pub struct Play;
+            pub struct Stop;
+        }
+        struct Render;
+
+        let mut map = HandlerMap::new();
+        map.insert(|_: audio::Play| {});
+        map.insert(|_: audio::Stop| {});
+        map.insert(|_: Render| {});
+
+        let prefix = concat!(module_path!(), "::name_prefix::audio::");
+        let mut audio = map.type_ids_with_prefix(prefix);
+        audio.sort();
+        let mut expected = vec![TypeId::of::<audio::Play>(), TypeId::of::<audio::Stop>()];
+        expected.sort();
+        assert_eq!(audio, expected);
+
+        map.retain_by_name_prefix(prefix, false);
+        assert!(!map.is_registered::<audio::Play>());
+        assert!(!map.is_registered::<audio::Stop>());
+        assert!(map.is_registered::<Render>());
+    }
+
+    #[test]
+    fn retain() {
+        use std::any::TypeId;
+        use std::rc::Rc;
+
+        struct Kept;
+        struct Pruned;
+
+        let token = Rc::new(());
+        let mut map = HandlerMap::new();
+        map.insert(|_: Kept| {});
+        {
+            let token = token.clone();
+            map.insert(move |_: Pruned| { let _ = &token; });
+        }
+        assert_eq!(Rc::strong_count(&token), 2);
+
+        map.retain(|id| *id != TypeId::of::<Pruned>());
+        assert_eq!(Rc::strong_count(&token), 1);
+        assert!(map.call(Kept));
+        assert!(!map.call(Pruned));
+    }
+
+    #[test]
+    fn call_timed() {
+        use std::thread;
+        use std::time::Duration;
+
+        struct Slow;
+
+        let mut map = HandlerMap::new();
+        map.insert(|_: Slow| thread::sleep(Duration::from_millis(10)));
+
+        assert!(map.call_timed(Slow).unwrap() >= Duration::from_millis(10));
+        assert_eq!(map.call_timed(()), None);
+    }
+
+    #[test]
+    fn call_deduped() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        #[derive(PartialEq, Eq, Hash)]
+        struct Refresh(&'static str);
+
+        let count = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        {
+            let count = count.clone();
+            map.insert(move |_: Refresh| count.set(count.get() + 1));
+        }
+
+        let burst = vec![Refresh("a"), Refresh("a"), Refresh("b"), Refresh("a")];
+        assert_eq!(map.call_deduped(burst), 2);
+        assert_eq!(count.get(), 2);
+
+        assert_eq!(map.call_deduped(vec![5u8, 5, 5]), 0);
+    }
+
+    #[test]
+    fn resolve_names() {
+        use std::any::{TypeId, type_name};
+
+        struct Registered;
+        struct Unregistered;
+
+        let mut map = HandlerMap::new();
+        map.insert(|_: Registered| {});
+
+        let names = [type_name::<Registered>(), type_name::<Unregistered>(), "not a type"];
+        assert_eq!(map.resolve_names(&names), vec![Some(TypeId::of::<Registered>()), None, None]);
+    }
+
+    #[test]
+    fn type_name_of() {
+        use std::any::{TypeId, type_name};
+
+        struct Registered;
+
+        let mut map = HandlerMap::new();
+        map.insert(|_: Registered| {});
+        map.insert_raw(TypeId::of::<u8>(), |_| {});
+
+        assert_eq!(map.type_name_of(TypeId::of::<Registered>()), Some(type_name::<Registered>()));
+        assert_eq!(map.type_name_of(TypeId::of::<u8>()), Some("<raw>"));
+        assert_eq!(map.type_name_of(TypeId::of::<u32>()), None);
+    }
+
+    #[test]
+    fn schema() {
+        use std::any::TypeId;
+
+        let mut first = HandlerMap::ordered();
+        first.insert(|_: u8| {});
+        first.insert(|_: String| {});
+        first.insert_raw(TypeId::of::<u32>(), |_| {});
+        first.insert(|_: bool| {});
+
+        let mut second = HandlerMap::ordered();
+        second.insert(|_: bool| {});
+        second.insert_raw(TypeId::of::<u32>(), |_| {});
+        second.insert(|_: String| {});
+        second.insert(|_: u8| {});
+
+        let schema = first.schema();
+        let expected = first.registered_types()
+            .map(|id| first.type_name_of(id).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(schema, expected);
+        assert_eq!(second.schema(), schema);
+        assert!(schema.contains(&"<raw>"));
+
+        assert!(HandlerMap::new().schema().is_empty());
+    }
 }
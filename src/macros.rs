@@ -0,0 +1,116 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Macros to build a `HandlerMap`, and message types to dispatch to one.
+
+/// Creates a `HandlerMap` containing the given handlers.
+///
+/// Each handler is registered with `insert`, in order, so a later handler for the same message
+/// type replaces an earlier one. The message type of each handler is inferred from its signature,
+/// so function items can be given as-is; closures need an explicit type annotation on their
+/// parameter, since there's nothing else for the message type to be inferred from.
+///
+/// ```rust
+/// #[macro_use] extern crate handler_map;
+/// # fn main() {
+/// use std::cell::Cell;
+///
+/// struct Ping;
+/// struct Count(u32);
+///
+/// fn handle_ping(_: Ping) {
+///     println!("pong");
+/// }
+///
+/// let total = Cell::new(0);
+/// let map = handler_map![
+///     handle_ping,
+///     |msg: Count| total.set(total.get() + msg.0),
+/// ];
+///
+/// assert!(map.call(Ping));
+/// assert!(map.call(Count(3)));
+/// assert_eq!(total.get(), 3);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! handler_map {
+    ($($handler:expr),* $(,)?) => {{
+        #[allow(unused_mut)]
+        let mut map = $crate::HandlerMap::new();
+        $(map.insert($handler);)*
+        map
+    }};
+}
+
+/// Defines an enum of messages, along with a message type wrapping each variant, so that each
+/// variant can have its own handler.
+///
+/// Each variant of the enum holds one value, and is given the name of a wrapper type to generate
+/// for it. The wrappers are tuple structs holding the variant's value in a public field, and are
+/// declared alongside the enum, with the same visibility. The enum implements `HandlerEnum`, so
+/// that `dispatch_enum` can unwrap an enum value and call the handler for its variant's wrapper.
+/// Since the wrappers are distinct types, several variants can hold the same type of value and
+/// still be handled separately.
+///
+/// ```rust
+/// #[macro_use] extern crate handler_map;
+/// # fn main() {
+/// use handler_map::{dispatch_enum, HandlerMap};
+/// use std::cell::Cell;
+///
+/// handler_enum! {
+///     pub enum Event {
+///         Click((i32, i32)) => OnClick,
+///         Scroll(i32) => OnScroll,
+///         Zoom(i32) => OnZoom,
+///     }
+/// }
+///
+/// let scrolled = Cell::new(0);
+/// let mut map = HandlerMap::new();
+/// map.insert(|OnClick((x, y))| println!("clicked at {}, {}", x, y));
+/// map.insert(|OnScroll(delta)| scrolled.set(scrolled.get() + delta));
+///
+/// assert!(dispatch_enum(&map, Event::Scroll(5)));
+/// assert!(dispatch_enum(&map, Event::Click((1, 2))));
+/// assert!(!dispatch_enum(&map, Event::Zoom(5)));
+/// assert_eq!(scrolled.get(), 5);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! handler_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $($variant:ident($inner:ty) => $wrapper:ident),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $($variant($inner),)*
+        }
+
+        $(
+            #[doc = concat!(
+                "The `", stringify!($variant), "` variant of `", stringify!($name),
+                "`, as a message type of its own."
+            )]
+            $vis struct $wrapper(pub $inner);
+        )*
+
+        impl $crate::HandlerEnum for $name {
+            fn dispatch<'a, S, H>(self, map: &$crate::HandlerMap<'a, S, H>) -> bool
+            where
+                S: ::std::hash::BuildHasher,
+                H: $crate::HandlerStore<'a>,
+            {
+                match self {
+                    $($name::$variant(inner) => map.call($wrapper(inner)),)*
+                }
+            }
+        }
+    };
+}
@@ -0,0 +1,259 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Opt-in counters of how many messages of each type were dispatched, and observers of how long
+//! their handlers took.
+
+use std::any::{Any, TypeId};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use {HandlerMap, HandlerStore};
+
+/// Dispatch counters for a map created with `HandlerMap::with_metrics`.
+#[derive(Default)]
+pub(crate) struct CallMetrics {
+    counts: RefCell<HashMap<TypeId, u64>>,
+    total: Cell<u64>,
+    /// The type of the message counted most recently.
+    last: Cell<Option<TypeId>>,
+}
+
+/// Snapshot of the handlers registered in a map and the messages dispatched to it, as returned by
+/// `HandlerMap::stats`.
+///
+/// The fields only hold plain data, so the snapshot can be kept or sent elsewhere after the map is
+/// gone, for example to be reported by a diagnostics endpoint. With the `serde` feature, it
+/// implements `Serialize`, so it can be emitted as JSON or any other format `serde` supports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct HandlerStats {
+    /// The number of handlers registered with `insert` and its variants, as counted by `len`.
+    pub handlers: usize,
+    /// The names of the message types of those handlers, in alphabetical order.
+    pub type_names: Vec<&'static str>,
+    /// If the map was created with `with_metrics`, how many messages of each registered type have
+    /// been dispatched to it, keyed by type name.
+    pub call_counts: Option<BTreeMap<&'static str, u64>>,
+    /// If the map was created with `with_metrics`, how many messages of any type have been
+    /// dispatched to it, including types with no handler.
+    pub total_calls: Option<u64>,
+}
+
+/// Observer set with `HandlerMap::set_timing_observer`, which receives the message type and how
+/// long its handler took to run.
+pub(crate) type TimingObserver<'a> = Box<dyn Fn(TypeId, Duration) + 'a>;
+
+impl CallMetrics {
+    /// Counts one dispatch of a message with the given type.
+    pub(crate) fn record(&self, id: TypeId) {
+        *self.counts.borrow_mut().entry(id).or_insert(0) += 1;
+        self.total.set(self.total.get() + 1);
+        self.last.set(Some(id));
+    }
+}
+
+impl<'a> HandlerMap<'a> {
+    /// Creates a new map with no handlers, which counts how many messages of each type are
+    /// dispatched to it.
+    ///
+    /// Every message passed to `call`, `dispatch`, `call_timed`, or `call_any` is counted, whether
+    /// or not a handler was registered for it. The counts can be read with `call_count` and
+    /// `total_calls`, and the type of the latest one with `last_dispatched`. Maps created any other
+    /// way don't keep these counts, and don't pay for them.
+    pub fn with_metrics() -> HandlerMap<'a> {
+        let mut map = Self::new();
+        map.metrics = Some(CallMetrics::default());
+        map
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Returns how many messages of the given type have been dispatched to this map, as counted by
+    /// `with_metrics`.
+    ///
+    /// If the map wasn't created with `with_metrics`, this always returns zero.
+    pub fn call_count<T: Any>(&self) -> u64 {
+        self.metrics.as_ref()
+            .and_then(|m| m.counts.borrow().get(&TypeId::of::<T>()).cloned())
+            .unwrap_or(0)
+    }
+
+    /// Returns how many messages of any type have been dispatched to this map, as counted by
+    /// `with_metrics`.
+    ///
+    /// If the map wasn't created with `with_metrics`, this always returns zero.
+    pub fn total_calls(&self) -> u64 {
+        self.metrics.as_ref().map_or(0, |m| m.total.get())
+    }
+
+    /// Returns the `TypeId` of the message most recently dispatched to this map, as recorded by
+    /// `with_metrics`.
+    ///
+    /// The type is recorded before the message is passed to its handler, so if a handler panics
+    /// or hangs, this tells which message it was handling. Its name can be found with
+    /// `type_name_of`, if a handler is registered for it. If the map wasn't created with
+    /// `with_metrics`, or nothing has been dispatched to it yet, this returns `None`.
+    pub fn last_dispatched(&self) -> Option<TypeId> {
+        self.metrics.as_ref().and_then(|m| m.last.get())
+    }
+
+    /// Sets an observer which is told how long each handler run by `call` took.
+    ///
+    /// After each handler run by `call`, `dispatch`, or `call_timed` returns, the observer
+    /// receives the message type and the time the handler took, measured the same way as
+    /// `call_timed`: only the handler itself is timed, not looking it up or running its
+    /// validator. When no observer is set, handlers aren't timed at all.
+    pub fn set_timing_observer<F: Fn(TypeId, Duration) + 'a>(&mut self, observer: F) {
+        self.timing_observer = Some(Box::new(observer));
+    }
+
+    /// Returns a snapshot of the handlers registered in this map, along with the counts recorded
+    /// by `with_metrics`.
+    ///
+    /// Messages of a type with no registered handler have no name to be listed under, so they're
+    /// only included in `total_calls`. Handlers registered with `insert_raw` share the name
+    /// `"<raw>"`, so their counts are added together.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    ///
+    /// let mut map = HandlerMap::with_metrics();
+    /// map.insert(|_: u32| {});
+    /// assert!(map.call(1u32));
+    /// assert!(!map.call(2u8));
+    ///
+    /// let stats = map.stats();
+    /// assert_eq!(stats.handlers, 1);
+    /// assert_eq!(stats.type_names, ["u32"]);
+    /// assert_eq!(stats.call_counts.unwrap()["u32"], 1);
+    /// assert_eq!(stats.total_calls, Some(2));
+    /// ```
+    pub fn stats(&self) -> HandlerStats {
+        let call_counts = self.metrics.as_ref().map(|m| {
+            let mut named = BTreeMap::new();
+            for (id, &count) in m.counts.borrow().iter() {
+                if let Some(name) = self.type_name_of(*id) {
+                    *named.entry(name).or_insert(0) += count;
+                }
+            }
+            named
+        });
+
+        HandlerStats {
+            handlers: self.len(),
+            type_names: self.type_names(),
+            call_counts,
+            total_calls: self.metrics.as_ref().map(|m| m.total.get()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+    use std::cell::RefCell;
+    use std::thread;
+    use std::time::Duration;
+
+    use HandlerMap;
+
+    #[test]
+    fn metrics() {
+        let mut map = HandlerMap::with_metrics();
+        map.insert(|_: u32| {});
+
+        assert!(map.call(1u32));
+        assert!(map.call(2u32));
+        map.dispatch(3u8);
+        assert!(map.call_any(Box::new(4u32)).is_ok());
+
+        assert_eq!(map.call_count::<u32>(), 3);
+        assert_eq!(map.call_count::<u8>(), 1);
+        assert_eq!(map.call_count::<u64>(), 0);
+        assert_eq!(map.total_calls(), 4);
+        assert_eq!(map.last_dispatched(), Some(TypeId::of::<u32>()));
+        assert!(!map.call("unregistered"));
+        assert_eq!(map.last_dispatched(), Some(TypeId::of::<&str>()));
+
+        let mut plain = HandlerMap::new();
+        plain.insert(|_: u32| {});
+        assert!(plain.call(1u32));
+        assert_eq!(plain.call_count::<u32>(), 0);
+        assert_eq!(plain.total_calls(), 0);
+        assert_eq!(plain.last_dispatched(), None);
+    }
+
+    #[test]
+    fn timing_observer() {
+        let seen = RefCell::new(Vec::new());
+        let mut map = HandlerMap::new();
+        map.set_timing_observer(|id, took| seen.borrow_mut().push((id, took)));
+        map.insert(|_: u32| thread::sleep(Duration::from_millis(5)));
+
+        assert!(map.call(1u32));
+        assert!(!map.call(2u8));
+        let took = map.call_timed(3u32).unwrap();
+
+        let seen = seen.borrow();
+        assert_eq!(seen.len(), 2);
+        assert!(seen.iter().all(|&(id, took)| {
+            id == TypeId::of::<u32>() && took >= Duration::from_millis(5)
+        }));
+        assert_eq!(seen[1].1, took);
+    }
+
+    #[test]
+    fn stats() {
+        let mut map = HandlerMap::with_metrics();
+        map.insert(|_: u32| {});
+        map.insert(|_: bool| {});
+        map.insert_multi(|_: u64| {});
+
+        assert!(map.call(1u32));
+        assert!(map.call(2u32));
+        assert!(!map.call(5u8));
+        assert!(!map.call(3u64));
+
+        let stats = map.stats();
+        assert_eq!(stats.handlers, 2);
+        assert_eq!(stats.type_names, ["bool", "u32"]);
+        let counts = stats.call_counts.unwrap();
+        assert_eq!(counts.into_iter().collect::<Vec<_>>(), [("u32", 2)]);
+        assert_eq!(stats.total_calls, Some(4));
+
+        let mut plain = HandlerMap::new();
+        plain.insert(|_: u32| {});
+        assert!(plain.call(1u32));
+        let stats = plain.stats();
+        assert_eq!(stats.type_names, ["u32"]);
+        assert_eq!(stats.call_counts, None);
+        assert_eq!(stats.total_calls, None);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn stats_json() {
+        let mut map = HandlerMap::with_metrics();
+        map.insert(|_: u32| {});
+        map.insert(|_: bool| {});
+        assert!(map.call(1u32));
+        assert!(!map.call(5u8));
+
+        let json = serde_json::to_string(&map.stats()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"handlers":2,"type_names":["bool","u32"],"call_counts":{"u32":1},"total_calls":2}"#,
+        );
+
+        let json = serde_json::to_string(&HandlerMap::new().stats()).unwrap();
+        assert_eq!(json, r#"{"handlers":0,"type_names":[],"call_counts":null,"total_calls":null}"#);
+    }
+}
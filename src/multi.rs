@@ -0,0 +1,672 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for registering several handlers for the same message type.
+//!
+//! These handlers, called "listeners" internally, are stored separately from the single handler
+//! registered with `insert`, so the two don't interfere with each other.
+
+use std::any::{Any, TypeId};
+use std::error::Error;
+use std::fmt;
+use std::hash::BuildHasher;
+
+use box_fn::{BoxFn, Opaque};
+use {Handler, HandlerMap, HandlerStore};
+
+/// One of several handlers registered for a message type with `insert_multi`.
+pub(crate) struct Listener<'a> {
+    label: Option<&'static str>,
+    priority: i32,
+    handler: BoxFn<'a, Opaque>,
+}
+
+impl<'a> Listener<'a> {
+    /// Adds this listener to the given list of listeners for its message type, after every
+    /// listener with a greater or equal priority, and before every listener with a lower one.
+    pub(crate) fn insert_into(self, listeners: &mut Vec<Listener<'a>>) {
+        let pos = listeners.partition_point(|other| other.priority >= self.priority);
+        listeners.insert(pos, self);
+    }
+}
+
+/// Handler registered with `insert_multi_stoppable`, which receives a `*const T` and returns
+/// whether to stop propagation.
+pub(crate) type StoppableListener<'a> = BoxFn<'a, Opaque, bool>;
+
+/// Information about one of the handlers registered for a message type with `insert_multi`, as
+/// given to the selector in `HandlerMap::call_selected`, or returned by `HandlerMap::handler_info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlerInfo {
+    /// The position of the handler in the list of handlers for its message type. Handlers are
+    /// listed in the order `call_all` runs them.
+    pub index: usize,
+    /// The label given to the handler, if it was registered with `insert_multi_labeled`.
+    pub label: Option<&'static str>,
+    /// The priority given to the handler with `insert_multi_with_priority`, or zero if it was
+    /// registered with `insert_multi` or `insert_multi_labeled`.
+    pub priority: i32,
+}
+
+/// Error returned by `HandlerMap::call_selected` when the selector picked a handler that doesn't
+/// exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectionOutOfRange {
+    index: usize,
+    candidates: usize,
+}
+
+impl SelectionOutOfRange {
+    /// Returns the index the selector returned.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the number of handlers the selector could have chosen from.
+    pub fn candidates(&self) -> usize {
+        self.candidates
+    }
+}
+
+impl fmt::Display for SelectionOutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "selected handler {} out of {} handlers", self.index, self.candidates)
+    }
+}
+
+impl Error for SelectionOutOfRange {}
+
+/// What `HandlerMap::call_all_fallible` should do when one of the handlers returns an error.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Stop calling handlers, and return the error. This is the default.
+    #[default]
+    Stop,
+    /// Pass the error to the observer set with `set_error_observer`, if any, and continue calling
+    /// the remaining handlers.
+    Continue,
+}
+
+/// Observer for the errors skipped by `HandlerMap::call_all_fallible`, which receives the
+/// message type and the error.
+pub(crate) type ErrorObserver<'a> = Box<dyn Fn(TypeId, &dyn Any) + 'a>;
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Registers an additional handler for a message type, alongside any others that were
+    /// registered with this method.
+    ///
+    /// Handlers registered this way are kept separately from the one registered with `insert`:
+    /// they aren't replaced by it, and aren't run by `call`. Use `call_all` to run all of them.
+    ///
+    /// The handler has priority zero; see `insert_multi_with_priority` for details.
+    pub fn insert_multi<T: Any, F: Handler<T> + 'a>(&mut self, handler: F) {
+        self.push_listener::<T, F>(None, 0, handler);
+    }
+
+    /// Registers an additional handler for a message type, like `insert_multi`, with a label that
+    /// identifies it to the selector in `call_selected`.
+    pub fn insert_multi_labeled<T, F>(&mut self, label: &'static str, handler: F)
+    where
+        T: Any,
+        F: Handler<T> + 'a,
+    {
+        self.push_listener::<T, F>(Some(label), 0, handler);
+    }
+
+    /// Registers an additional handler for a message type, like `insert_multi`, with the given
+    /// priority.
+    ///
+    /// `call_all` runs the handlers for a message type in descending order of priority. Handlers
+    /// with the same priority run in the order they were registered, so handlers registered with
+    /// `insert_multi` or `insert_multi_labeled`, which have priority zero, run after every
+    /// zero-priority handler registered before them, and before every one registered after them.
+    pub fn insert_multi_with_priority<T, F>(&mut self, priority: i32, handler: F)
+    where
+        T: Any,
+        F: Handler<T> + 'a,
+    {
+        self.push_listener::<T, F>(None, priority, handler);
+    }
+
+    /// Registers an additional handler for a message type which returns a value, alongside any
+    /// others for the same message and return types that were registered with this method.
+    ///
+    /// These handlers are kept separately from the ones without a return value, and are called
+    /// for a given message and return type by `call_fold`.
+    pub fn insert_multi_with_result<T, R, F>(&mut self, handler: F)
+    where
+        T: Any,
+        R: Any,
+        F: Fn(T) -> R + 'a,
+    {
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg().erase_ret();
+        let id = (TypeId::of::<T>(), TypeId::of::<R>());
+
+        self.result_listeners.entry(id).or_default().push(ptr);
+    }
+
+    /// Registers an additional handler for a message type which can stop the message from being
+    /// passed to the handlers registered after it.
+    ///
+    /// The handler receives the message by reference, and returns true to stop propagation. These
+    /// handlers are kept separately from the ones registered with `insert_multi`, and are run by
+    /// `call_all_stoppable`.
+    pub fn insert_multi_stoppable<T, F>(&mut self, handler: F)
+    where
+        T: Any,
+        F: Fn(&T) -> bool + 'a,
+    {
+        let handler = move |msg: *const T| handler(unsafe { &*msg });
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        self.stoppable_listeners.entry(id).or_default().push(ptr);
+    }
+
+    /// Registers an additional handler for a message type which receives the message by
+    /// reference, alongside any others that were registered with this method.
+    ///
+    /// These handlers are kept separately from the ones registered with `insert_multi`, and are
+    /// run by `call_ref_all`.
+    pub fn insert_multi_ref<T: Any, F: Fn(&T) + 'a>(&mut self, handler: F) {
+        let handler = move |msg: *const T| handler(unsafe { &*msg });
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        self.ref_listeners.entry(id).or_default().push(ptr);
+    }
+
+    fn push_listener<T, F>(&mut self, label: Option<&'static str>, priority: i32, handler: F)
+    where
+        T: Any,
+        F: Fn(T) + 'a,
+    {
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        Listener {
+            label,
+            priority,
+            handler: ptr,
+        }.insert_into(self.listeners.entry(id).or_default());
+    }
+
+    /// Calls every handler registered for the message's type with `insert_multi`, returning how
+    /// many of them ran.
+    ///
+    /// The handlers are called in descending order of priority, and in the order they were
+    /// registered among handlers with the same priority, as described in
+    /// `insert_multi_with_priority`. Each handler except the last receives a clone of the message;
+    /// the last receives the original. The handler registered with `insert`, if any, isn't run.
+    pub fn call_all<T: Any + Clone>(&self, msg: T) -> usize {
+        let listeners = match self.listeners.get(&TypeId::of::<T>()) {
+            Some(listeners) => listeners,
+            None => return 0,
+        };
+
+        if let Some((last, rest)) = listeners.split_last() {
+            for listener in rest {
+                unsafe { listener.handler.call_erased(msg.clone()); }
+            }
+            unsafe { last.handler.call_erased(msg); }
+        }
+        listeners.len()
+    }
+
+    /// Calls one of the handlers registered for the message's type with `insert_multi`, as chosen
+    /// by the given selector.
+    ///
+    /// The selector is given information about each of the candidate handlers, in the order
+    /// `call_all` would run them, and returns the index of the one to call. If there are no
+    /// handlers registered for the message type, the selector isn't called, and this returns
+    /// `Ok(false)`.
+    /// If the selector returns an index that's out of range, no handler is called, and this
+    /// returns an error rather than panicking.
+    pub fn call_selected<T, P>(&self, msg: T, select: P) -> Result<bool, SelectionOutOfRange>
+    where
+        T: Any,
+        P: Fn(&[HandlerInfo]) -> usize,
+    {
+        let listeners = match self.listeners.get(&TypeId::of::<T>()) {
+            Some(listeners) if !listeners.is_empty() => listeners,
+            _ => return Ok(false),
+        };
+
+        let index = select(&Self::listener_info(listeners));
+        match listeners.get(index) {
+            Some(listener) => {
+                unsafe { listener.handler.call_erased(msg); }
+                Ok(true)
+            }
+            None => Err(SelectionOutOfRange { index, candidates: listeners.len() }),
+        }
+    }
+
+    /// Calls every handler for the given message and return types registered with
+    /// `insert_multi_with_result`, and combines their results with the given fold function.
+    ///
+    /// The handlers are called in the order they were registered. Each handler except the last
+    /// receives a clone of the message; the last receives the original. Each result is combined
+    /// with the accumulated value as it's returned, starting from `init`; if no handlers are
+    /// registered, `init` is returned as-is.
+    pub fn call_fold<T, R, Acc, F>(&self, msg: T, init: Acc, fold: F) -> Acc
+    where
+        T: Any + Clone,
+        R: Any,
+        F: Fn(Acc, R) -> Acc,
+    {
+        let id = (TypeId::of::<T>(), TypeId::of::<R>());
+        let handlers = match self.result_listeners.get(&id) {
+            Some(handlers) => handlers,
+            None => return init,
+        };
+
+        let mut acc = init;
+        if let Some((last, rest)) = handlers.split_last() {
+            for handler in rest {
+                acc = fold(acc, unsafe { handler.call_erased_ret(msg.clone()) });
+            }
+            acc = fold(acc, unsafe { last.call_erased_ret(msg) });
+        }
+        acc
+    }
+
+    /// Calls the handlers registered for the message's type with `insert_multi_stoppable`, in the
+    /// order they were registered, until one of them stops propagation. Returns whether
+    /// propagation was stopped.
+    ///
+    /// If no handler returns true, every handler is run, and this returns false.
+    pub fn call_all_stoppable<T: Any>(&self, msg: &T) -> bool {
+        match self.stoppable_listeners.get(&TypeId::of::<T>()) {
+            Some(handlers) => handlers.iter().any(|h| unsafe { h.call_erased(msg as *const T) }),
+            None => false,
+        }
+    }
+
+    /// Calls every handler registered for the message's type with `insert_multi_ref`, in the order
+    /// they were registered, returning how many of them ran.
+    ///
+    /// Every handler receives a reference to the same message, so unlike `call_all`, the message
+    /// doesn't need to be cloned.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::cell::Cell;
+    ///
+    /// struct Frame {
+    ///     bytes: Vec<u8>,
+    /// }
+    ///
+    /// let frames = Cell::new(0);
+    /// let bytes = Cell::new(0);
+    /// let mut map = HandlerMap::new();
+    /// map.insert_multi_ref(|_: &Frame| frames.set(frames.get() + 1));
+    /// map.insert_multi_ref(|f: &Frame| bytes.set(bytes.get() + f.bytes.len()));
+    ///
+    /// assert_eq!(map.call_ref_all(&Frame { bytes: vec![0; 16] }), 2);
+    /// assert_eq!((frames.get(), bytes.get()), (1, 16));
+    /// ```
+    pub fn call_ref_all<T: Any>(&self, msg: &T) -> usize {
+        match self.ref_listeners.get(&TypeId::of::<T>()) {
+            Some(handlers) => {
+                for handler in handlers {
+                    unsafe { handler.call_erased(msg as *const T); }
+                }
+                handlers.len()
+            }
+            None => 0,
+        }
+    }
+
+    /// Returns how many handlers are registered for the given message type, counting the handler
+    /// registered with `insert` or one of its variants, if any, along with every handler
+    /// registered with `insert_multi`, `insert_multi_stoppable`, or `insert_multi_ref`.
+    ///
+    /// This allows skipping the construction of a message nothing would receive, whichever way
+    /// its handlers were registered.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    ///
+    /// #[derive(Clone)]
+    /// struct Report(String);
+    ///
+    /// let mut map = HandlerMap::new();
+    /// assert_eq!(map.handler_count::<Report>(), 0);
+    ///
+    /// map.insert_multi(|r: Report| println!("{}", r.0));
+    /// map.insert_multi(|r: Report| eprintln!("{}", r.0));
+    /// assert_eq!(map.handler_count::<Report>(), 2);
+    /// ```
+    pub fn handler_count<T: Any>(&self) -> usize {
+        let id = TypeId::of::<T>();
+        self.slots.contains(&id) as usize
+            + self.listeners.get(&id).map_or(0, Vec::len)
+            + self.stoppable_listeners.get(&id).map_or(0, Vec::len)
+            + self.ref_listeners.get(&id).map_or(0, Vec::len)
+    }
+
+    /// Returns information about each of the handlers registered for the given message type with
+    /// `insert_multi` and its labeled and prioritized variants, in the order `call_all` runs them.
+    ///
+    /// This is the same information `call_selected` gives its selector.
+    pub fn handler_info<T: Any>(&self) -> Vec<HandlerInfo> {
+        self.listeners.get(&TypeId::of::<T>())
+            .map_or_else(Vec::new, |listeners| Self::listener_info(listeners))
+    }
+
+    /// Collects the `HandlerInfo` for each of the given listeners.
+    fn listener_info(listeners: &[Listener<'a>]) -> Vec<HandlerInfo> {
+        listeners.iter().enumerate().map(|(index, listener)| HandlerInfo {
+            index,
+            label: listener.label,
+            priority: listener.priority,
+        }).collect()
+    }
+
+    /// Sets what `call_all_fallible` does when one of the handlers returns an error.
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.error_policy = policy;
+    }
+
+    /// Sets the observer that receives errors from `call_all_fallible` when the error policy is
+    /// `ErrorPolicy::Continue`.
+    ///
+    /// The observer receives the `TypeId` of the message type, and the error returned by the
+    /// handler.
+    pub fn set_error_observer<F: Fn(TypeId, &dyn Any) + 'a>(&mut self, observer: F) {
+        self.error_observer = Some(Box::new(observer));
+    }
+
+    /// Calls every handler for the given message type registered with `insert_multi_with_result`
+    /// which returns `Result<(), E>`, returning how many of them succeeded.
+    ///
+    /// The handlers are called in the order they were registered, with each handler except the
+    /// last receiving a clone of the message. When a handler returns an error, the behavior
+    /// depends on the policy set with `set_error_policy`: with `ErrorPolicy::Stop`, no further
+    /// handlers are called and the error is returned; with `ErrorPolicy::Continue`, the error is
+    /// passed to the error observer and the remaining handlers are still called.
+    pub fn call_all_fallible<T, E>(&self, msg: T) -> Result<usize, E>
+    where
+        T: Any + Clone,
+        E: Any,
+    {
+        let id = (TypeId::of::<T>(), TypeId::of::<Result<(), E>>());
+        let handlers = match self.result_listeners.get(&id) {
+            Some(handlers) => handlers,
+            None => return Ok(0),
+        };
+
+        let mut succeeded = 0;
+        let mut msg = Some(msg);
+        for (idx, handler) in handlers.iter().enumerate() {
+            let arg = if idx + 1 == handlers.len() {
+                msg.take().unwrap()
+            } else {
+                msg.clone().unwrap()
+            };
+
+            match unsafe { handler.call_erased_ret::<T, Result<(), E>>(arg) } {
+                Ok(()) => succeeded += 1,
+                Err(e) => match self.error_policy {
+                    ErrorPolicy::Stop => return Err(e),
+                    ErrorPolicy::Continue => if let Some(ref observer) = self.error_observer {
+                        observer(TypeId::of::<T>(), &e);
+                    },
+                },
+            }
+        }
+
+        Ok(succeeded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use HandlerMap;
+
+    #[test]
+    fn call_all() {
+        #[derive(Clone)]
+        struct Event(u32);
+
+        let acc = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        for scale in &[1, 10, 100] {
+            let acc = acc.clone();
+            map.insert_multi(move |e: Event| acc.set(acc.get() + e.0 * scale));
+        }
+        map.insert(|_: Event| panic!("call_all shouldn't run the single handler"));
+
+        assert_eq!(map.call_all(Event(2)), 3);
+        assert_eq!(acc.get(), 222);
+        assert_eq!(map.call_all(()), 0);
+    }
+
+    #[test]
+    fn priority() {
+        use std::cell::RefCell;
+
+        #[derive(Clone)]
+        struct Request;
+
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let mut map = HandlerMap::new();
+        let push = |name: &'static str| {
+            let order = order.clone();
+            move |_: Request| order.borrow_mut().push(name)
+        };
+        map.insert_multi(push("log 1"));
+        map.insert_multi_with_priority(10, push("validate"));
+        map.insert_multi_with_priority(-5, push("cleanup"));
+        map.insert_multi_with_priority(0, push("log 2"));
+        map.insert_multi(push("log 3"));
+        map.insert_multi_with_priority(10, push("authorize"));
+
+        assert_eq!(map.call_all(Request), 6);
+        assert_eq!(
+            *order.borrow(),
+            ["validate", "authorize", "log 1", "log 2", "log 3", "cleanup"]
+        );
+    }
+
+    #[test]
+    fn call_all_stoppable() {
+        use std::cell::RefCell;
+
+        struct Click(u32);
+
+        let ran = Rc::new(RefCell::new(Vec::new()));
+        let mut map = HandlerMap::new();
+        for i in 0..3 {
+            let ran = ran.clone();
+            map.insert_multi_stoppable(move |c: &Click| {
+                ran.borrow_mut().push(i);
+                c.0 == i
+            });
+        }
+
+        assert!(map.call_all_stoppable(&Click(1)));
+        assert_eq!(*ran.borrow(), [0, 1]);
+
+        ran.borrow_mut().clear();
+        assert!(!map.call_all_stoppable(&Click(5)));
+        assert_eq!(*ran.borrow(), [0, 1, 2]);
+
+        assert!(!map.call_all_stoppable(&()));
+    }
+
+    #[test]
+    fn call_ref_all() {
+        use std::cell::RefCell;
+
+        struct Tick(u32);
+
+        let counters = [Cell::new(0), Cell::new(0), Cell::new(0)];
+        let order = RefCell::new(Vec::new());
+        let mut map = HandlerMap::new();
+        for (i, counter) in counters.iter().enumerate() {
+            let order = &order;
+            map.insert_multi_ref(move |t: &Tick| {
+                counter.set(counter.get() + t.0);
+                order.borrow_mut().push(i);
+            });
+        }
+        map.insert(|_: Tick| panic!("call_ref_all shouldn't run the single handler"));
+
+        let tick = Tick(2);
+        assert_eq!(map.call_ref_all(&tick), 3);
+        assert_eq!(map.call_ref_all(&tick), 3);
+        assert_eq!(counters.iter().map(Cell::get).collect::<Vec<_>>(), [4, 4, 4]);
+        assert_eq!(*order.borrow(), [0, 1, 2, 0, 1, 2]);
+        assert_eq!(map.call_ref_all(&()), 0);
+    }
+
+    #[test]
+    fn handler_count() {
+        #[derive(Clone)]
+        struct Event;
+
+        let mut map = HandlerMap::new();
+        assert_eq!(map.handler_count::<Event>(), 0);
+
+        map.insert(|_: Event| {});
+        assert_eq!(map.handler_count::<Event>(), 1);
+
+        map.insert_multi(|_: Event| {});
+        map.insert_multi(|_: Event| {});
+        map.insert_multi_stoppable(|_: &Event| false);
+        map.insert_multi_ref(|_: &Event| {});
+        assert_eq!(map.handler_count::<Event>(), 5);
+        assert_eq!(map.handler_count::<u32>(), 0);
+
+        map.remove::<Event>();
+        assert_eq!(map.handler_count::<Event>(), 4);
+    }
+
+    #[test]
+    fn call_selected() {
+        struct Job(u32);
+
+        let fast = Rc::new(Cell::new(0));
+        let slow = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        {
+            let fast = fast.clone();
+            let slow = slow.clone();
+            map.insert_multi_labeled("fast", move |job: Job| fast.set(fast.get() + job.0));
+            map.insert_multi_labeled("slow", move |job: Job| slow.set(slow.get() + job.0));
+        }
+
+        let pick = |label: &'static str| move |info: &[::HandlerInfo]| {
+            info.iter().position(|h| h.label == Some(label)).unwrap_or(info.len())
+        };
+
+        assert_eq!(map.call_selected(Job(1), pick("slow")), Ok(true));
+        assert_eq!(map.call_selected(Job(2), pick("fast")), Ok(true));
+        assert_eq!(fast.get(), 2);
+        assert_eq!(slow.get(), 1);
+
+        let err = map.call_selected(Job(4), pick("missing")).unwrap_err();
+        assert_eq!((err.index(), err.candidates()), (2, 2));
+        assert_eq!(fast.get() + slow.get(), 3);
+
+        assert_eq!(map.call_selected((), |_| 0), Ok(false));
+        assert!(!map.call(Job(8)));
+    }
+
+    #[test]
+    fn select_by_priority() {
+        use HandlerInfo;
+
+        struct Job;
+
+        let ran = Rc::new(Cell::new(""));
+        let mut map = HandlerMap::new();
+        for &(priority, name) in &[(0, "default"), (5, "urgent"), (-3, "background")] {
+            let ran = ran.clone();
+            map.insert_multi_with_priority(priority, move |_: Job| ran.set(name));
+        }
+        {
+            let ran = ran.clone();
+            map.insert_multi_labeled("audit", move |_: Job| ran.set("audit"));
+        }
+
+        let info = map.handler_info::<Job>();
+        let priorities = info.iter().map(|h| (h.priority, h.label)).collect::<Vec<_>>();
+        assert_eq!(priorities, [(5, None), (0, None), (0, Some("audit")), (-3, None)]);
+        assert!(map.handler_info::<u8>().is_empty());
+
+        // pick the lowest-priority handler
+        let lowest = |info: &[HandlerInfo]| {
+            info.iter().min_by_key(|h| h.priority).map_or(info.len(), |h| h.index)
+        };
+        assert_eq!(map.call_selected(Job, lowest), Ok(true));
+        assert_eq!(ran.get(), "background");
+
+        // pick the first handler with a non-negative priority below the highest
+        let middle = |info: &[HandlerInfo]| {
+            info.iter().position(|h| h.priority >= 0 && h.priority < 5).unwrap_or(info.len())
+        };
+        assert_eq!(map.call_selected(Job, middle), Ok(true));
+        assert_eq!(ran.get(), "default");
+    }
+
+    #[test]
+    fn call_fold() {
+        #[derive(Clone)]
+        struct Score(u32);
+
+        let mut map = HandlerMap::new();
+        map.insert_multi_with_result(|s: Score| s.0);
+        map.insert_multi_with_result(|s: Score| s.0 * 2);
+        map.insert_multi_with_result(|s: Score| s.0 * 3);
+        map.insert_multi_with_result(|s: Score| format!("{}", s.0));
+
+        assert_eq!(map.call_fold(Score(5), 0, |acc, r: u32| acc + r), 30);
+        assert_eq!(map.call_fold(Score(5), String::new(), |acc, r: String| acc + &r), "5");
+        assert_eq!(map.call_fold(Score(5), 1, |acc, r: u64| acc + r), 1);
+    }
+
+    #[test]
+    fn error_policy() {
+        use std::cell::RefCell;
+        use ErrorPolicy;
+
+        #[derive(Clone)]
+        struct Step;
+
+        let ran = Rc::new(RefCell::new(Vec::new()));
+        let errors = Rc::new(Cell::new(0));
+        let mut map = HandlerMap::new();
+        for i in 0..3 {
+            let ran = ran.clone();
+            map.insert_multi_with_result(move |_: Step| -> Result<(), String> {
+                ran.borrow_mut().push(i);
+                if i == 1 { Err(format!("step {} failed", i)) } else { Ok(()) }
+            });
+        }
+        {
+            let errors = errors.clone();
+            map.set_error_observer(move |_, e| {
+                assert_eq!(e.downcast_ref::<String>().unwrap(), "step 1 failed");
+                errors.set(errors.get() + 1);
+            });
+        }
+
+        assert_eq!(map.call_all_fallible(Step), Err("step 1 failed".to_string()));
+        assert_eq!(*ran.borrow(), vec![0, 1]);
+        assert_eq!(errors.get(), 0);
+
+        ran.borrow_mut().clear();
+        map.set_error_policy(ErrorPolicy::Continue);
+        assert_eq!(map.call_all_fallible::<_, String>(Step), Ok(2));
+        assert_eq!(*ran.borrow(), vec![0, 1, 2]);
+        assert_eq!(errors.get(), 1);
+    }
+}
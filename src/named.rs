@@ -0,0 +1,90 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for handlers registered under a name, so that several subsystems can each handle the
+//! same message type.
+//!
+//! Named handlers are stored separately from the ones registered with `insert`, so a named and an
+//! unnamed handler for the same type don't replace each other.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use box_fn::{BoxFn, Opaque};
+use {Handler, HandlerMap, HandlerStore};
+
+/// Handlers registered with `insert_named`, keyed by their message type and name.
+pub(crate) type NamedHandlers<'a> = HashMap<(TypeId, &'static str), BoxFn<'a, Opaque>>;
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Registers a new handler into the map under the given name.
+    ///
+    /// Each message type can have one handler for each name, and calling `call_named` with that
+    /// name routes the message to it. Named handlers are kept separately from the handler
+    /// registered with `insert`, so `call` never runs them, and registering a named handler never
+    /// replaces the unnamed one, or vice-versa.
+    pub fn insert_named<T: Any, F: Handler<T> + 'a>(&mut self, name: &'static str, handler: F) {
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        self.named.insert((TypeId::of::<T>(), name), ptr);
+    }
+
+    /// Un-registers the handler with the given name for the given message type, returning whether
+    /// one was registered.
+    pub fn remove_named<T: Any>(&mut self, name: &'static str) -> bool {
+        self.named.remove(&(TypeId::of::<T>(), name)).is_some()
+    }
+
+    /// Returns true if the given message type has a handler with the given name registered in the
+    /// map.
+    pub fn is_registered_named<T: Any>(&self, name: &'static str) -> bool {
+        self.named.contains_key(&(TypeId::of::<T>(), name))
+    }
+
+    /// Calls the handler with the given name with the given message, returning whether the
+    /// handler was registered.
+    ///
+    /// If there's no handler with that name for the message type, the message is dropped without
+    /// falling back to the unnamed handler, or to the fallback handler set with `set_fallback`.
+    pub fn call_named<T: Any>(&self, name: &'static str, msg: T) -> bool {
+        if let Some(act) = self.named.get(&(TypeId::of::<T>(), name)) {
+            unsafe { act.call_erased(msg); }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use HandlerMap;
+
+    #[test]
+    fn named() {
+        struct StringMessage(&'static str);
+
+        let log = RefCell::new(Vec::new());
+        let mut map = HandlerMap::new();
+        map.insert(|m: StringMessage| log.borrow_mut().push(("unnamed", m.0)));
+        map.insert_named("audio", |m: StringMessage| log.borrow_mut().push(("audio", m.0)));
+        map.insert_named("video", |m: StringMessage| log.borrow_mut().push(("video", m.0)));
+
+        assert!(map.call_named("audio", StringMessage("a")));
+        assert!(map.call(StringMessage("b")));
+        assert!(map.call_named("video", StringMessage("c")));
+        assert!(!map.call_named("input", StringMessage("d")));
+
+        assert!(map.remove_named::<StringMessage>("audio"));
+        assert!(!map.is_registered_named::<StringMessage>("audio"));
+        assert!(map.is_registered_named::<StringMessage>("video"));
+        assert!(map.is_registered::<StringMessage>());
+        assert!(!map.call_named("audio", StringMessage("e")));
+
+        assert_eq!(*log.borrow(), [("audio", "a"), ("unnamed", "b"), ("video", "c")]);
+    }
+}
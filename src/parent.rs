@@ -0,0 +1,111 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for layering a map over a parent map, which handles the messages the child map has no
+//! handler for.
+
+use std::any::{Any, TypeId};
+use std::hash::BuildHasher;
+use std::rc::Rc;
+
+use {HandlerMap, HandlerStore};
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Sets the given map as the parent of this one, so that messages this map has no handler
+    /// for are passed on to it.
+    ///
+    /// When this map has no handler registered with `insert` or one of its variants for a
+    /// message, and none of its conversions registered with `register_conversion` apply, `call`,
+    /// `dispatch`, `call_or_return`, and `call_any` pass the message on to the parent in the same
+    /// way. The message then goes through the parent's handlers, its own
+    /// parent, and its fallback handler and dead-letter queue, exactly as if it had been sent to
+    /// the parent directly, so this map's fallback handler and dead-letter queue aren't used. A
+    /// handler in this map for a message type overrides the parent's handler for that type, even
+    /// while it's disabled.
+    ///
+    /// Since the parent is shared through an `Rc`, it can no longer be changed once it has a
+    /// child, which also means that maps can't be linked into a cycle.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::rc::Rc;
+    ///
+    /// struct Save;
+    /// struct Quit;
+    ///
+    /// let mut defaults = HandlerMap::new();
+    /// defaults.insert(|_: Save| println!("saving"));
+    /// defaults.insert(|_: Quit| println!("quitting"));
+    /// let defaults = Rc::new(defaults);
+    ///
+    /// let mut editor = HandlerMap::new().with_parent(defaults.clone());
+    /// editor.insert(|_: Save| println!("saving the open file"));
+    ///
+    /// assert!(editor.call(Save));
+    /// assert!(editor.call(Quit));
+    /// assert!(!editor.is_registered::<Quit>());
+    /// assert!(editor.is_registered_recursive::<Quit>());
+    /// ```
+    pub fn with_parent(mut self, parent: Rc<HandlerMap<'a, S, H>>) -> HandlerMap<'a, S, H> {
+        self.parent = Some(parent);
+        self
+    }
+
+    /// Returns true if the given message type has a handler registered in this map, or in one of
+    /// its parents set with `with_parent`.
+    pub fn is_registered_recursive<T: Any>(&self) -> bool {
+        self.contains_type_id_recursive(TypeId::of::<T>())
+    }
+
+    fn contains_type_id_recursive(&self, id: TypeId) -> bool {
+        self.contains_type_id(id) ||
+            self.parent.as_ref().is_some_and(|parent| parent.contains_type_id_recursive(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use {CallOutcome, HandlerMap};
+
+    #[test]
+    fn with_parent() {
+        struct Shared;
+        struct Inherited;
+        struct Missing;
+
+        let seen = Cell::new("");
+        let mut root = HandlerMap::new();
+        root.insert(|_: Inherited| seen.set("root"));
+        root.enable_dead_letter(4);
+        let root = Rc::new(root);
+
+        let mut middle = HandlerMap::new();
+        middle.insert(|_: Shared| seen.set("middle"));
+        let middle = Rc::new(middle.with_parent(root.clone()));
+
+        let mut leaf = HandlerMap::new().with_parent(middle.clone());
+        leaf.insert(|_: Shared| seen.set("leaf"));
+        leaf.set_fallback(|_| panic!("the parents should get unhandled messages"));
+
+        assert!(leaf.call(Shared));
+        assert_eq!(seen.get(), "leaf");
+        assert!(middle.call(Shared));
+        assert_eq!(seen.get(), "middle");
+        assert_eq!(leaf.dispatch(Inherited), CallOutcome::Handled);
+        assert_eq!(seen.get(), "root");
+
+        assert!(leaf.is_registered_recursive::<Inherited>());
+        assert!(!leaf.is_registered::<Inherited>());
+        assert!(!leaf.is_registered_recursive::<Missing>());
+
+        assert!(!leaf.call(Missing));
+        assert!(leaf.call_or_return(Missing).is_err());
+        assert!(leaf.call_any(Box::new(Inherited)).unwrap());
+        assert_eq!(root.dead_letter_count(), 1);
+    }
+}
@@ -0,0 +1,138 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! The `Registrar` trait, for units of handlers which can be registered together.
+
+use std::any::{Any, TypeId};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use {Handler, HandlerMap, HandlerStore, Slot};
+
+/// Something which registers handlers into a `HandlerMap`, as passed to `HandlerMap::register`.
+///
+/// This is implemented for:
+///
+/// - every handler that `insert` accepts, which is registered with `insert`;
+/// - `RegisterFn`, which wraps a closure that receives the map;
+/// - tuples of up to eight registrars, which register each of their items in order.
+///
+/// The `M` parameter only keeps these implementations apart, and is always inferred.
+///
+/// ```rust
+/// use handler_map::{HandlerMap, RegisterFn};
+///
+/// struct Connect;
+/// struct Disconnect;
+///
+/// fn on_connect(_: Connect) {}
+///
+/// let mut map = HandlerMap::new();
+/// map.register((
+///     on_connect,
+///     |_: Disconnect| {},
+///     RegisterFn(|map: &mut HandlerMap| map.insert(|_: u32| {})),
+/// ));
+///
+/// assert!(map.is_registered::<Connect>());
+/// assert!(map.is_registered::<Disconnect>());
+/// assert!(map.is_registered::<u32>());
+/// ```
+pub trait Registrar<'a, M, S = RandomState, H = HashMap<TypeId, Slot<'a>, S>> {
+    /// Registers this item's handlers into the given map.
+    fn register(self, map: &mut HandlerMap<'a, S, H>);
+}
+
+/// A `Registrar` which calls the wrapped closure with the map.
+///
+/// This is for registration units which need to do more than insert a fixed set of handlers,
+/// like plugins which register themselves. Closures which take the map can't be registrars by
+/// themselves, since they couldn't be told apart from handlers which receive a
+/// `&mut HandlerMap` as their message.
+pub struct RegisterFn<F>(pub F);
+
+impl<'a, S, H, T, F> Registrar<'a, fn(T), S, H> for F
+where
+    S: BuildHasher,
+    H: HandlerStore<'a>,
+    T: Any,
+    F: Handler<T> + 'a,
+{
+    fn register(self, map: &mut HandlerMap<'a, S, H>) {
+        map.insert(self);
+    }
+}
+
+impl<'a, S, H, F: FnOnce(&mut HandlerMap<'a, S, H>)> Registrar<'a, (), S, H> for RegisterFn<F> {
+    fn register(self, map: &mut HandlerMap<'a, S, H>) {
+        (self.0)(map);
+    }
+}
+
+macro_rules! tuple_registrar {
+    ($($item:ident $marker:ident),+) => {
+        impl<'a, S, H, $($item, $marker),+> Registrar<'a, ($($marker,)+), S, H> for ($($item,)+)
+        where
+            $($item: Registrar<'a, $marker, S, H>),+
+        {
+            #[allow(non_snake_case)]
+            fn register(self, map: &mut HandlerMap<'a, S, H>) {
+                let ($($item,)+) = self;
+                $($item.register(map);)+
+            }
+        }
+    };
+}
+
+tuple_registrar!(T1 M1);
+tuple_registrar!(T1 M1, T2 M2);
+tuple_registrar!(T1 M1, T2 M2, T3 M3);
+tuple_registrar!(T1 M1, T2 M2, T3 M3, T4 M4);
+tuple_registrar!(T1 M1, T2 M2, T3 M3, T4 M4, T5 M5);
+tuple_registrar!(T1 M1, T2 M2, T3 M3, T4 M4, T5 M5, T6 M6);
+tuple_registrar!(T1 M1, T2 M2, T3 M3, T4 M4, T5 M5, T6 M6, T7 M7);
+tuple_registrar!(T1 M1, T2 M2, T3 M3, T4 M4, T5 M5, T6 M6, T7 M7, T8 M8);
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Registers the handlers from the given `Registrar`, like a single handler, a `RegisterFn`,
+    /// or a tuple of them.
+    pub fn register<M, R: Registrar<'a, M, S, H>>(&mut self, registrar: R) {
+        registrar.register(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use {HandlerMap, RegisterFn};
+
+    #[test]
+    fn register() {
+        struct Connect;
+        fn on_connect(_: Connect) {}
+
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let mut map = HandlerMap::new();
+        map.register(on_connect);
+        map.register((
+            |_: u32| panic!("should have been replaced"),
+            RegisterFn({
+                let order = order.clone();
+                move |map: &mut HandlerMap| {
+                    map.insert(|_: u8| {});
+                    map.insert(move |_: u32| order.borrow_mut().push("second"));
+                }
+            }),
+        ));
+
+        assert_eq!(map.len(), 3);
+        assert!(map.is_registered::<Connect>());
+        assert!(map.call(0u32));
+        assert_eq!(*order.borrow(), ["second"]);
+    }
+}
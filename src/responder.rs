@@ -0,0 +1,186 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for handlers which return a response to the caller.
+
+use std::any::{Any, TypeId};
+use std::error::Error;
+use std::fmt;
+use std::hash::BuildHasher;
+
+use box_fn::BoxFn;
+use {CallOutcome, HandlerMap, HandlerStore};
+
+/// Handler registered with `insert_with_result`, which receives a boxed message and returns a
+/// boxed response.
+pub(crate) struct Responder<'a> {
+    /// The `TypeId` of the handler's return type.
+    ret: TypeId,
+    handler: BoxFn<'a, Box<dyn Any>, Box<dyn Any>>,
+}
+
+/// Error returned when a message couldn't be dispatched to a handler.
+///
+/// Where possible, the original message is handed back inside the error, so that it isn't lost.
+pub enum DispatchError<T = Box<dyn Any>> {
+    /// No handler was registered for the message's type.
+    Unregistered(T),
+    /// The handler for the message's type is in a group that was disabled with
+    /// `HandlerMap::set_group_enabled`.
+    Disabled(T),
+    /// The message reached the handler for its type, which turned it away, so it wasn't handled.
+    /// The `CallOutcome` says how: `Declined`, `ValidationFailed`, or `AlreadyConsumed`.
+    ///
+    /// The message has already been given up, so it can't be handed back.
+    Rejected(CallOutcome),
+}
+
+impl<T> DispatchError<T> {
+    /// Returns the message that couldn't be dispatched, or `None` if it was rejected by its
+    /// handler and so can't be handed back.
+    pub fn into_inner(self) -> Option<T> {
+        match self {
+            DispatchError::Unregistered(msg) | DispatchError::Disabled(msg) => Some(msg),
+            DispatchError::Rejected(_) => None,
+        }
+    }
+}
+
+impl<T> fmt::Debug for DispatchError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DispatchError::Unregistered(_) => f.write_str("Unregistered(..)"),
+            DispatchError::Disabled(_) => f.write_str("Disabled(..)"),
+            DispatchError::Rejected(ref outcome) => write!(f, "Rejected({:?})", outcome),
+        }
+    }
+}
+
+impl<T> fmt::Display for DispatchError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DispatchError::Unregistered(_) => f.write_str("no handler registered for message"),
+            DispatchError::Disabled(_) => f.write_str("handler for message is disabled"),
+            DispatchError::Rejected(_) => f.write_str("handler for message rejected it"),
+        }
+    }
+}
+
+impl<T> Error for DispatchError<T> {}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Registers a new handler into the map which returns a response, to be called by
+    /// `call_with_result` or `call_any_result`.
+    ///
+    /// These handlers are kept separately from the one registered with `insert`: they aren't
+    /// replaced by it, and aren't run by `call`.
+    ///
+    /// Since the handler takes its message by value, it can consume part of the message and
+    /// return whatever is left over. For example, a parser can take some input and hand back the
+    /// bytes it didn't use:
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    ///
+    /// struct Input(Vec<u8>);
+    ///
+    /// let mut map = HandlerMap::new();
+    /// map.insert_with_result(|msg: Input| {
+    ///     let mut bytes = msg.0;
+    ///     let rest = bytes.split_off(2);
+    ///     println!("header: {:?}", bytes);
+    ///     rest
+    /// });
+    ///
+    /// let rest: Option<Vec<u8>> = map.call_with_result(Input(vec![1, 2, 3, 4]));
+    /// assert_eq!(rest, Some(vec![3, 4]));
+    /// ```
+    pub fn insert_with_result<T, R, F>(&mut self, handler: F)
+    where
+        T: Any,
+        R: Any,
+        F: Fn(T) -> R + 'a,
+    {
+        let handler = move |msg: Box<dyn Any>| -> Box<dyn Any> {
+            // responders are only looked up by the `TypeId` of the message, so this can't fail
+            Box::new(handler(*msg.downcast::<T>().unwrap()))
+        };
+        let handler = BoxFn::from(Box::new(handler)).erase();
+
+        self.responders.insert(TypeId::of::<T>(), Responder { ret: TypeId::of::<R>(), handler });
+    }
+
+    /// Calls the handler registered with `insert_with_result` for the given message, returning its
+    /// response.
+    ///
+    /// Returns `None` if no such handler is registered for the message type. The return type is
+    /// checked as well: if the registered handler's return type isn't `R`, the handler isn't run,
+    /// and `None` is returned.
+    pub fn call_with_result<T: Any, R: Any>(&self, msg: T) -> Option<R> {
+        match self.responders.get(&TypeId::of::<T>()) {
+            Some(responder) if responder.ret == TypeId::of::<R>() => {
+                let resp = responder.handler.call(Box::new(msg));
+                // the return type was checked above, so this can't fail
+                Some(*resp.downcast::<R>().unwrap())
+            }
+            _ => None,
+        }
+    }
+
+    /// Calls the handler registered with `insert_with_result` for the boxed message's type,
+    /// returning its boxed response.
+    ///
+    /// This allows calling a handler without knowing the message's type at compile time. If no
+    /// such handler is registered, the message is returned in
+    /// `DispatchError::Unregistered`.
+    pub fn call_any_result(&self, msg: Box<dyn Any>) -> Result<Box<dyn Any>, DispatchError> {
+        match self.responders.get(&(*msg).type_id()) {
+            Some(responder) => Ok(responder.handler.call(msg)),
+            None => Err(DispatchError::Unregistered(msg)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use HandlerMap;
+
+    #[test]
+    fn call_with_result() {
+        use std::cell::Cell;
+
+        struct Parse(&'static str);
+
+        let calls = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert_with_result(|msg: Parse| {
+            calls.set(calls.get() + 1);
+            msg.0.parse::<i32>().ok()
+        });
+
+        assert_eq!(map.call_with_result(Parse("12")), Some(Some(12)));
+        assert_eq!(map.call_with_result(Parse("twelve")), Some(None::<i32>));
+        assert_eq!(calls.get(), 2);
+
+        assert_eq!(map.call_with_result::<_, i32>(Parse("12")), None);
+        assert_eq!(map.call_with_result::<_, ()>(12u8), None);
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn call_any_result() {
+        struct Double(u32);
+
+        let mut map = HandlerMap::new();
+        map.insert_with_result(|msg: Double| msg.0 * 2);
+        map.insert(|_: u32| panic!("responders are separate from plain handlers"));
+
+        let resp = map.call_any_result(Box::new(Double(21))).unwrap();
+        assert_eq!(*resp.downcast::<u32>().unwrap(), 42);
+
+        let err = map.call_any_result(Box::new(7u32)).unwrap_err();
+        assert_eq!(*err.into_inner().unwrap().downcast::<u32>().unwrap(), 7);
+    }
+}
@@ -0,0 +1,96 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! A map of request handlers which each return a response.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+use box_fn::{BoxFn, Opaque};
+
+/// Struct that maps request types with functions or closures that receive them and return a
+/// response.
+///
+/// Each request type has one responder, which determines its response type. `dispatch` has to
+/// name the response type it expects, and returns `None` if it doesn't match the one the
+/// responder returns.
+///
+/// ```rust
+/// use handler_map::ResponderMap;
+///
+/// struct GetName(u32);
+///
+/// let mut map = ResponderMap::new();
+/// map.insert(|req: GetName| format!("user {}", req.0));
+///
+/// assert_eq!(map.dispatch::<_, String>(GetName(7)), Some("user 7".to_string()));
+/// assert_eq!(map.dispatch::<_, u32>(GetName(7)), None);
+/// ```
+#[derive(Default)]
+pub struct ResponderMap<'a> {
+    /// The responders, along with the `TypeId` of their response type.
+    responders: HashMap<TypeId, (TypeId, BoxFn<'a, Opaque, Opaque>)>,
+}
+
+impl<'a> ResponderMap<'a> {
+    /// Creates a new map with no responders.
+    pub fn new() -> ResponderMap<'a> {
+        Self::default()
+    }
+
+    /// Registers a new responder into the map, replacing any responder for the same request type.
+    pub fn insert<Req, Resp, F>(&mut self, responder: F)
+    where
+        Req: Any,
+        Resp: Any,
+        F: Fn(Req) -> Resp + 'a,
+    {
+        let ptr = BoxFn::from(Box::new(responder)).erase().erase_arg().erase_ret();
+        self.responders.insert(TypeId::of::<Req>(), (TypeId::of::<Resp>(), ptr));
+    }
+
+    /// Un-registers the responder for the given request type from this map, returning whether a
+    /// responder was registered.
+    pub fn remove<Req: Any>(&mut self) -> bool {
+        self.responders.remove(&TypeId::of::<Req>()).is_some()
+    }
+
+    /// Returns true if the given request type has a responder registered in the map.
+    pub fn is_registered<Req: Any>(&self) -> bool {
+        self.responders.contains_key(&TypeId::of::<Req>())
+    }
+
+    /// Calls the responder for the given request, returning its response.
+    ///
+    /// Returns `None` without calling the responder if there's no responder for the request type,
+    /// or if its response type isn't `Resp`.
+    pub fn dispatch<Req: Any, Resp: Any>(&self, req: Req) -> Option<Resp> {
+        match self.responders.get(&TypeId::of::<Req>()) {
+            Some(&(ret, ref act)) if ret == TypeId::of::<Resp>() => {
+                Some(unsafe { act.call_erased_ret(req) })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ResponderMap;
+
+    #[test]
+    fn round_trip() {
+        let mut map = ResponderMap::new();
+        map.insert(|n: u32| n.to_string());
+
+        assert_eq!(map.dispatch::<u32, String>(42), Some("42".to_string()));
+        assert_eq!(map.dispatch::<u32, u32>(42), None);
+        assert_eq!(map.dispatch::<u8, String>(42), None);
+
+        assert!(map.remove::<u32>());
+        assert!(!map.is_registered::<u32>());
+        assert_eq!(map.dispatch::<u32, String>(42), None);
+    }
+}
@@ -0,0 +1,198 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for handlers which are un-registered when a guard returned alongside them is dropped.
+
+use std::any::{Any, TypeId};
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::rc::{Rc, Weak};
+
+use box_fn::BoxFn;
+use {Handler, HandlerMap, HandlerStore, Slot, SlotHandler};
+
+/// Guard returned by `HandlerMap::insert_scoped`, which un-registers its handler when dropped.
+///
+/// The guard only holds a `Weak` reference to the map, so it doesn't keep the map alive, and
+/// dropping it after the map is gone does nothing. Dropping it after its handler was replaced by
+/// another one for the same message type doesn't remove the new handler.
+///
+/// If the map is borrowed when the guard is dropped, for example because the guard is dropped by
+/// a handler while the map is calling it, the handler can't be removed right away. It's disabled
+/// instead: messages sent to it are dropped without running it, and the next call to
+/// `prune_dead` removes it.
+#[must_use = "dropping a Subscription un-registers its handler right away"]
+pub struct Subscription<'a, S = RandomState, H = HashMap<TypeId, Slot<'a>, S>>
+where
+    S: BuildHasher,
+    H: HandlerStore<'a>,
+{
+    map: Weak<RefCell<HandlerMap<'a, S, H>>>,
+    id: TypeId,
+    dead: Rc<Cell<bool>>,
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> Subscription<'a, S, H> {
+    /// Returns the `TypeId` of the message type the handler was registered for.
+    pub fn type_id(&self) -> TypeId {
+        self.id
+    }
+
+    /// Drops the guard without un-registering its handler, so that it stays in the map like a
+    /// handler registered with `insert`.
+    pub fn forget(mut self) {
+        self.map = Weak::new();
+        self.dead = Rc::new(Cell::new(false));
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> Drop for Subscription<'a, S, H> {
+    fn drop(&mut self) {
+        self.dead.set(true);
+
+        let map = match self.map.upgrade() {
+            Some(map) => map,
+            None => return,
+        };
+        let mut map = match map.try_borrow_mut() {
+            Ok(map) => map,
+            Err(_) => return,
+        };
+
+        let ours = map.slots.get(&self.id)
+            .and_then(|slot| slot.dead.as_ref())
+            .is_some_and(|dead| Rc::ptr_eq(dead, &self.dead));
+        if ours {
+            map.slots.remove(&self.id);
+        }
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Registers a new handler into the given shared map, returning a guard which un-registers it
+    /// when dropped.
+    ///
+    /// Since the guard needs to change the map after this call returns, the map has to be shared
+    /// in an `Rc<RefCell<_>>`, rather than borrowed. Otherwise, the handler is registered the same
+    /// way as with `insert`, replacing any handler already registered for the message type. See
+    /// `Subscription` for what happens when the guard is dropped.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    ///
+    /// struct Redraw;
+    ///
+    /// let map = Rc::new(RefCell::new(HandlerMap::new()));
+    /// {
+    ///     let _sub = HandlerMap::insert_scoped(&map, |_: Redraw| println!("redrawing"));
+    ///     assert!(map.borrow().call(Redraw));
+    /// }
+    /// assert!(!map.borrow().call(Redraw));
+    /// ```
+    pub fn insert_scoped<T, F>(map: &Rc<RefCell<HandlerMap<'a, S, H>>>, handler: F)
+        -> Subscription<'a, S, H>
+    where
+        T: Any,
+        F: Handler<T> + 'a,
+    {
+        let dead = Rc::new(Cell::new(false));
+        let check = dead.clone();
+        let handler = move |msg: T| if !check.get() {
+            handler(msg);
+        };
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        let mut slot = Slot::new::<T>(SlotHandler::Value(ptr));
+        slot.dead = Some(dead.clone());
+        map.borrow_mut().insert_slot(id, slot);
+
+        Subscription {
+            map: Rc::downgrade(map),
+            id,
+            dead,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    use HandlerMap;
+
+    #[test]
+    fn insert_scoped() {
+        struct Tick;
+
+        let ticks = Cell::new(0);
+        let map = Rc::new(RefCell::new(HandlerMap::new()));
+
+        let sub = HandlerMap::insert_scoped(&map, |_: Tick| ticks.set(ticks.get() + 1));
+        assert!(map.borrow().call(Tick));
+        drop(sub);
+        assert!(!map.borrow().is_registered::<Tick>());
+        assert_eq!(ticks.get(), 1);
+
+        let sub = HandlerMap::insert_scoped(&map, |_: Tick| ticks.set(ticks.get() + 10));
+        map.borrow_mut().insert(|_: Tick| ticks.set(ticks.get() + 100));
+        drop(sub);
+        assert!(map.borrow().call(Tick));
+        assert_eq!(ticks.get(), 101);
+
+        HandlerMap::insert_scoped(&map, |_: u8| {}).forget();
+        assert!(map.borrow().is_registered::<u8>());
+
+        let sub = HandlerMap::insert_scoped(&map, |_: u16| ticks.set(ticks.get() + 1000));
+        {
+            let held = map.borrow();
+            drop(sub);
+            assert!(held.call(5u16));
+        }
+        assert_eq!(ticks.get(), 101);
+        assert_eq!(map.borrow_mut().prune_dead(), 1);
+        assert!(!map.borrow().is_registered::<u16>());
+
+        let sub = HandlerMap::insert_scoped(&map, |_: u32| {});
+        drop(map);
+        drop(sub);
+    }
+
+    #[test]
+    fn self_removal() {
+        use Subscription;
+
+        struct Tick;
+
+        let map = Rc::new(RefCell::new(HandlerMap::new()));
+        let ticks = Rc::new(Cell::new(0));
+        for _ in 0..50 {
+            let holder: Rc<RefCell<Option<Subscription>>> = Rc::new(RefCell::new(None));
+            let sub = {
+                let (holder, ticks, weak) = (holder.clone(), ticks.clone(), Rc::downgrade(&map));
+                HandlerMap::insert_scoped(&map, move |_: Tick| {
+                    // drop this handler's own guard, and ask for its removal, while it's running
+                    let sub = holder.borrow_mut().take();
+                    drop(sub);
+                    weak.upgrade().unwrap().borrow().defer_remove::<Tick>();
+                    ticks.set(ticks.get() + 1);
+                })
+            };
+            *holder.borrow_mut() = Some(sub);
+
+            assert!(map.borrow().call(Tick));
+            assert!(map.borrow().call(Tick));
+            assert_eq!(map.borrow_mut().apply_deferred(), 1);
+            assert_eq!(map.borrow_mut().prune_dead(), 0);
+            assert!(!map.borrow().is_registered::<Tick>());
+        }
+        assert_eq!(ticks.get(), 50);
+    }
+}
@@ -0,0 +1,231 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Adapter to use a `HandlerMap` as a `tower::Service`. Requires the `tower` feature.
+
+use std::any::Any;
+use std::future::{self, Ready};
+use std::hash::BuildHasher;
+use std::task::{Context, Poll};
+
+use tower_service::Service;
+
+use {CallOutcome, DispatchError, HandlerMap, HandlerStore};
+
+/// Wrapper around a `HandlerMap` which implements `tower::Service`.
+///
+/// Requests are boxed messages, which are routed to the handlers registered with
+/// `insert_with_result` through `HandlerMap::call_any_result`. Since handlers are always run
+/// synchronously, the service is always ready, and its handler is run when `call` is called
+/// rather than when the returned future is polled.
+pub struct HandlerMapService<'a> {
+    map: HandlerMap<'a>,
+}
+
+impl<'a> HandlerMapService<'a> {
+    /// Wraps the given map.
+    pub fn new(map: HandlerMap<'a>) -> HandlerMapService<'a> {
+        HandlerMapService { map }
+    }
+
+    /// Returns a reference to the wrapped map.
+    pub fn get_ref(&self) -> &HandlerMap<'a> {
+        &self.map
+    }
+
+    /// Returns a mutable reference to the wrapped map, to register or remove handlers.
+    pub fn get_mut(&mut self) -> &mut HandlerMap<'a> {
+        &mut self.map
+    }
+
+    /// Unwraps the map.
+    pub fn into_inner(self) -> HandlerMap<'a> {
+        self.map
+    }
+}
+
+impl<'a> From<HandlerMap<'a>> for HandlerMapService<'a> {
+    fn from(map: HandlerMap<'a>) -> HandlerMapService<'a> {
+        HandlerMapService::new(map)
+    }
+}
+
+impl<'a> Service<Box<dyn Any>> for HandlerMapService<'a> {
+    type Response = Box<dyn Any>;
+    type Error = DispatchError;
+    type Future = Ready<Result<Box<dyn Any>, DispatchError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context) -> Poll<Result<(), DispatchError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Box<dyn Any>) -> Self::Future {
+        future::ready(self.map.call_any_result(req))
+    }
+}
+
+/// A shared reference to a `HandlerMap` can also be used as a `tower::Service`, which routes
+/// requests to the handlers registered with `insert` and its variants, in the same way as
+/// `HandlerMap::call_boxed`.
+///
+/// A request is only successful if a handler, or the fallback handler, handled it. A request which
+/// no handler receives is handed back in the error, as `DispatchError::Disabled` if its handler's
+/// group is disabled, or `DispatchError::Unregistered` otherwise. A request which its handler
+/// turned away, because a filter declined it, a validator rejected it, or a single-shot handler
+/// was already consumed, fails with `DispatchError::Rejected`. Since the service is only a
+/// reference, the map can still be used directly while it's part of a middleware stack.
+impl<'a, S, H> Service<Box<dyn Any>> for &HandlerMap<'a, S, H>
+where
+    S: BuildHasher,
+    H: HandlerStore<'a>,
+{
+    type Response = ();
+    type Error = DispatchError;
+    type Future = Ready<Result<(), DispatchError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context) -> Poll<Result<(), DispatchError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Box<dyn Any>) -> Self::Future {
+        future::ready(match self.dispatch_boxed(req) {
+            Ok(CallOutcome::Handled) | Ok(CallOutcome::Fallback) => Ok(()),
+            Ok(outcome) => Err(DispatchError::Rejected(outcome)),
+            Err(msg) => {
+                let disabled = self.slots.get(&(*msg).type_id())
+                    .is_some_and(|slot| self.is_disabled(slot));
+                if disabled {
+                    Err(DispatchError::Disabled(msg))
+                } else {
+                    Err(DispatchError::Unregistered(msg))
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    use tower_service::Service;
+
+    use {CallOutcome, DispatchError, HandlerMap, HandlerMapService, ValidationError};
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    #[test]
+    fn map_service() {
+        use std::any::Any;
+        use std::cell::{Cell, RefCell};
+
+        // a trivial middleware which logs the outcome of each request
+        struct Logged<'l, S> {
+            inner: S,
+            log: &'l RefCell<Vec<bool>>,
+        }
+
+        impl<'l, S: Service<Box<dyn Any>, Response = ()>> Service<Box<dyn Any>> for Logged<'l, S> {
+            type Response = ();
+            type Error = S::Error;
+            type Future = std::future::Ready<Result<(), S::Error>>;
+
+            fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+                self.inner.poll_ready(cx)
+            }
+
+            fn call(&mut self, req: Box<dyn Any>) -> Self::Future {
+                let waker = Waker::from(Arc::new(NoopWaker));
+                let res = match pin!(self.inner.call(req)).poll(&mut Context::from_waker(&waker)) {
+                    Poll::Ready(res) => res,
+                    Poll::Pending => panic!("HandlerMap services are always ready"),
+                };
+                self.log.borrow_mut().push(res.is_ok());
+                std::future::ready(res)
+            }
+        }
+
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+
+        let total = Cell::new(0);
+        let mut map = HandlerMap::new();
+        map.insert(|n: u32| total.set(total.get() + n));
+        map.insert_in_group(1, |_: u8| panic!("group is disabled"));
+        map.set_group_enabled(1, false);
+
+        let log = RefCell::new(Vec::new());
+        let mut svc = Logged { inner: &map, log: &log };
+        assert!(matches!(svc.poll_ready(&mut cx), Poll::Ready(Ok(()))));
+        assert!(matches!(pin!(svc.call(Box::new(5u32))).poll(&mut cx), Poll::Ready(Ok(()))));
+        match pin!(svc.call(Box::new(5u8))).poll(&mut cx) {
+            Poll::Ready(Err(DispatchError::Disabled(_))) => {}
+            _ => panic!("expected a disabled message"),
+        }
+        match pin!(svc.call(Box::new('x'))).poll(&mut cx) {
+            Poll::Ready(Err(e @ DispatchError::Unregistered(_))) => {
+                assert_eq!(*e.into_inner().unwrap().downcast::<char>().unwrap(), 'x');
+            }
+            _ => panic!("expected an unregistered message"),
+        }
+
+        // handlers which turn their message away are reported as such, not as missing
+        map.insert_filter(|n: u16| n > 10);
+        map.insert_validated(
+            |&n: &i32| if n > 0 { Ok(()) } else { Err(ValidationError::new("not positive")) },
+            |n: i32| total.set(total.get() + n as u32),
+        );
+        map.insert(|_: i64| {});
+        map.mark_single_shot::<i64>();
+        let mut svc = Logged { inner: &map, log: &log };
+        assert!(matches!(pin!(svc.call(Box::new(50u16))).poll(&mut cx), Poll::Ready(Ok(()))));
+        match pin!(svc.call(Box::new(5u16))).poll(&mut cx) {
+            Poll::Ready(Err(DispatchError::Rejected(CallOutcome::Declined))) => {}
+            _ => panic!("expected a declined message"),
+        }
+        match pin!(svc.call(Box::new(-1i32))).poll(&mut cx) {
+            Poll::Ready(Err(e @ DispatchError::Rejected(CallOutcome::ValidationFailed(_)))) => {
+                assert!(e.into_inner().is_none());
+            }
+            _ => panic!("expected a message rejected by its validator"),
+        }
+        assert!(matches!(pin!(svc.call(Box::new(1i64))).poll(&mut cx), Poll::Ready(Ok(()))));
+        match pin!(svc.call(Box::new(1i64))).poll(&mut cx) {
+            Poll::Ready(Err(DispatchError::Rejected(CallOutcome::AlreadyConsumed))) => {}
+            _ => panic!("expected a consumed single-shot handler"),
+        }
+
+        assert_eq!(*log.borrow(), [true, false, false, true, false, false, true, false]);
+        assert!(map.call(1u32));
+        assert_eq!(total.get(), 6);
+    }
+
+    #[test]
+    fn service() {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+
+        let mut map = HandlerMap::new();
+        map.insert_with_result(|msg: String| msg.len());
+        let mut svc = HandlerMapService::new(map);
+
+        assert!(matches!(svc.poll_ready(&mut cx), Poll::Ready(Ok(()))));
+        match pin!(svc.call(Box::new("hello".to_string()))).poll(&mut cx) {
+            Poll::Ready(Ok(resp)) => assert_eq!(*resp.downcast::<usize>().unwrap(), 5),
+            _ => panic!("expected a response"),
+        }
+        match pin!(svc.call(Box::new(5u8))).poll(&mut cx) {
+            Poll::Ready(Err(DispatchError::Unregistered(_))) => {}
+            _ => panic!("expected an unregistered message"),
+        }
+    }
+}
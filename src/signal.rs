@@ -0,0 +1,99 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for signal handlers, which take no message and are keyed by a marker type instead.
+//!
+//! Signal handlers are stored separately from the ones registered with `insert`, so a signal and
+//! a regular handler keyed by the same type don't replace each other.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use box_fn::BoxFn;
+use {HandlerMap, HandlerStore};
+
+/// Handlers registered with `insert_signal`, keyed by their marker type.
+pub(crate) type SignalHandlers<'a> = HashMap<TypeId, BoxFn<'a, ()>>;
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Registers a new handler into the map which takes no message, keyed by the marker type `T`.
+    ///
+    /// The handler is run by `signal::<T>`. Signal handlers are kept separately from the handler
+    /// registered with `insert`, so `call` never runs them, and registering a signal handler never
+    /// replaces the regular handler for the same type, or vice-versa.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::cell::Cell;
+    ///
+    /// struct Tick;
+    ///
+    /// let ticks = Cell::new(0);
+    /// let mut map = HandlerMap::new();
+    /// map.insert_signal::<Tick, _>(|| ticks.set(ticks.get() + 1));
+    ///
+    /// assert!(map.signal::<Tick>());
+    /// assert!(!map.call(Tick));
+    /// assert_eq!(ticks.get(), 1);
+    /// ```
+    pub fn insert_signal<T: Any, F: Fn() + 'a>(&mut self, handler: F) {
+        let ptr = BoxFn::from(Box::new(move |()| handler())).erase();
+        self.signals.insert(TypeId::of::<T>(), ptr);
+    }
+
+    /// Un-registers the signal handler for the given marker type, returning whether one was
+    /// registered.
+    pub fn remove_signal<T: Any>(&mut self) -> bool {
+        self.signals.remove(&TypeId::of::<T>()).is_some()
+    }
+
+    /// Returns true if the given marker type has a signal handler registered in the map.
+    pub fn is_signal_registered<T: Any>(&self) -> bool {
+        self.signals.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Runs the signal handler for the given marker type, returning whether one was registered.
+    ///
+    /// This never falls back to the handler registered with `insert` for the same type, or to the
+    /// fallback handler set with `set_fallback`.
+    pub fn signal<T: Any>(&self) -> bool {
+        if let Some(act) = self.signals.get(&TypeId::of::<T>()) {
+            act.call(());
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use HandlerMap;
+
+    #[test]
+    fn signal() {
+        struct Tick;
+
+        let log = RefCell::new(Vec::new());
+        let mut map = HandlerMap::new();
+        map.insert_signal::<Tick, _>(|| log.borrow_mut().push("signal"));
+        assert!(!map.is_registered::<Tick>());
+
+        map.insert(|_: Tick| log.borrow_mut().push("call"));
+        assert!(map.signal::<Tick>());
+        assert!(map.call(Tick));
+        assert!(!map.signal::<u32>());
+
+        assert!(map.remove_signal::<Tick>());
+        assert!(!map.is_signal_registered::<Tick>());
+        assert!(map.is_registered::<Tick>());
+        assert!(!map.signal::<Tick>());
+
+        assert_eq!(*log.borrow(), ["signal", "call"]);
+    }
+}
@@ -0,0 +1,345 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! The `HandlerStore` trait, which lets a `HandlerMap` keep the handlers registered with `insert`
+//! and its variants in a table of the user's choosing.
+
+use std::any::TypeId;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+
+use Slot;
+
+/// A table of the handlers registered with `insert` and its variants, keyed by the `TypeId` of
+/// their message type, which a `HandlerMap` can be created with using `HandlerMap::with_store`.
+///
+/// By default, a `HandlerMap` keeps its handlers in a `HashMap` using the map's hasher, and
+/// `HandlerMap::ordered` uses a `BTreeMap`. Implementing this trait allows any other table to be
+/// used instead, such as a list for maps with only a handful of handlers, where searching it can
+/// be faster than hashing the `TypeId`. The handlers themselves are opaque `Slot`s, which the
+/// store only needs to keep and hand back.
+///
+/// Everything which goes through the handlers in turn, like `registered_types`, `retain`, or
+/// iterating over the map with `into_iter`, visits them in the order given by `iter`. Only the
+/// required methods need to be implemented; the rest are built on them, and can be overridden if
+/// the table can do better.
+///
+/// ```rust
+/// use handler_map::{HandlerMap, HandlerStore, Slot};
+/// use std::any::TypeId;
+///
+/// /// Store which keeps its handlers in a list, in the order they were registered.
+/// #[derive(Default)]
+/// struct LinearStore<'a>(Vec<(TypeId, Slot<'a>)>);
+///
+/// impl<'a> HandlerStore<'a> for LinearStore<'a> {
+///     fn get(&self, id: &TypeId) -> Option<&Slot<'a>> {
+///         self.0.iter().find(|pair| pair.0 == *id).map(|pair| &pair.1)
+///     }
+///
+///     fn get_mut(&mut self, id: &TypeId) -> Option<&mut Slot<'a>> {
+///         self.0.iter_mut().find(|pair| pair.0 == *id).map(|pair| &mut pair.1)
+///     }
+///
+///     fn insert(&mut self, id: TypeId, slot: Slot<'a>) -> Option<Slot<'a>> {
+///         match self.get_mut(&id) {
+///             Some(existing) => Some(std::mem::replace(existing, slot)),
+///             None => {
+///                 self.0.push((id, slot));
+///                 None
+///             }
+///         }
+///     }
+///
+///     fn remove(&mut self, id: &TypeId) -> Option<Slot<'a>> {
+///         let idx = self.0.iter().position(|pair| pair.0 == *id)?;
+///         Some(self.0.remove(idx).1)
+///     }
+///
+///     fn len(&self) -> usize {
+///         self.0.len()
+///     }
+///
+///     fn iter(&self) -> Box<dyn Iterator<Item = (&TypeId, &Slot<'a>)> + '_> {
+///         Box::new(self.0.iter().map(|pair| (&pair.0, &pair.1)))
+///     }
+/// }
+///
+/// let mut map = HandlerMap::with_store(LinearStore::default());
+/// map.insert(|_: u8| {});
+/// map.insert(|_: String| {});
+/// map.insert(|_: bool| {});
+///
+/// assert!(map.call(true));
+/// assert_eq!(
+///     map.registered_types().collect::<Vec<_>>(),
+///     [TypeId::of::<u8>(), TypeId::of::<String>(), TypeId::of::<bool>()],
+/// );
+/// ```
+pub trait HandlerStore<'a> {
+    /// Returns the handler for the message type with the given `TypeId`, if there is one.
+    fn get(&self, id: &TypeId) -> Option<&Slot<'a>>;
+
+    /// Returns the handler for the message type with the given `TypeId` mutably, if there is one.
+    fn get_mut(&mut self, id: &TypeId) -> Option<&mut Slot<'a>>;
+
+    /// Stores the handler for the message type with the given `TypeId`, returning the one it
+    /// replaces, if there was one.
+    fn insert(&mut self, id: TypeId, slot: Slot<'a>) -> Option<Slot<'a>>;
+
+    /// Removes the handler for the message type with the given `TypeId`, returning it if there
+    /// was one.
+    fn remove(&mut self, id: &TypeId) -> Option<Slot<'a>>;
+
+    /// Returns the number of handlers in the store.
+    fn len(&self) -> usize;
+
+    /// Returns an iterator over the handlers in the store, along with the `TypeId`s they're
+    /// stored under.
+    fn iter(&self) -> Box<dyn Iterator<Item = (&TypeId, &Slot<'a>)> + '_>;
+
+    /// Returns whether the store holds a handler for the message type with the given `TypeId`.
+    fn contains(&self, id: &TypeId) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Returns whether the store holds no handlers.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every handler for which the given predicate returns false.
+    fn retain(&mut self, pred: &mut dyn FnMut(&TypeId, &mut Slot<'a>) -> bool) {
+        let ids = self.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        for id in ids {
+            let keep = match self.get_mut(&id) {
+                Some(slot) => pred(&id, slot),
+                None => true,
+            };
+            if !keep {
+                self.remove(&id);
+            }
+        }
+    }
+
+    /// Removes every handler from the store, returning them in the order given by `iter`.
+    fn drain(&mut self) -> Vec<(TypeId, Slot<'a>)> {
+        let ids = self.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        ids.into_iter().filter_map(|id| self.remove(&id).map(|slot| (id, slot))).collect()
+    }
+
+    /// Removes every handler from the store.
+    fn clear(&mut self) {
+        self.drain();
+    }
+
+    /// Returns the number of handlers the store can hold without reallocating. By default, this
+    /// is the same as `len`.
+    fn capacity(&self) -> usize {
+        self.len()
+    }
+
+    /// Reserves room for at least `additional` more handlers. By default, this does nothing.
+    fn reserve(&mut self, additional: usize) {
+        let _ = additional;
+    }
+
+    /// Shrinks the store as much as possible. By default, this does nothing.
+    fn shrink_to_fit(&mut self) {}
+}
+
+impl<'a, S: BuildHasher> HandlerStore<'a> for HashMap<TypeId, Slot<'a>, S> {
+    fn get(&self, id: &TypeId) -> Option<&Slot<'a>> {
+        HashMap::get(self, id)
+    }
+
+    fn get_mut(&mut self, id: &TypeId) -> Option<&mut Slot<'a>> {
+        HashMap::get_mut(self, id)
+    }
+
+    fn insert(&mut self, id: TypeId, slot: Slot<'a>) -> Option<Slot<'a>> {
+        HashMap::insert(self, id, slot)
+    }
+
+    fn remove(&mut self, id: &TypeId) -> Option<Slot<'a>> {
+        HashMap::remove(self, id)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&TypeId, &Slot<'a>)> + '_> {
+        Box::new(HashMap::iter(self))
+    }
+
+    fn contains(&self, id: &TypeId) -> bool {
+        self.contains_key(id)
+    }
+
+    fn retain(&mut self, pred: &mut dyn FnMut(&TypeId, &mut Slot<'a>) -> bool) {
+        HashMap::retain(self, pred)
+    }
+
+    fn drain(&mut self) -> Vec<(TypeId, Slot<'a>)> {
+        HashMap::drain(self).collect()
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self)
+    }
+
+    fn capacity(&self) -> usize {
+        HashMap::capacity(self)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        HashMap::reserve(self, additional)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        HashMap::shrink_to_fit(self)
+    }
+}
+
+impl<'a> HandlerStore<'a> for BTreeMap<TypeId, Slot<'a>> {
+    fn get(&self, id: &TypeId) -> Option<&Slot<'a>> {
+        BTreeMap::get(self, id)
+    }
+
+    fn get_mut(&mut self, id: &TypeId) -> Option<&mut Slot<'a>> {
+        BTreeMap::get_mut(self, id)
+    }
+
+    fn insert(&mut self, id: TypeId, slot: Slot<'a>) -> Option<Slot<'a>> {
+        BTreeMap::insert(self, id, slot)
+    }
+
+    fn remove(&mut self, id: &TypeId) -> Option<Slot<'a>> {
+        BTreeMap::remove(self, id)
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&TypeId, &Slot<'a>)> + '_> {
+        Box::new(BTreeMap::iter(self))
+    }
+
+    fn contains(&self, id: &TypeId) -> bool {
+        self.contains_key(id)
+    }
+
+    fn retain(&mut self, pred: &mut dyn FnMut(&TypeId, &mut Slot<'a>) -> bool) {
+        BTreeMap::retain(self, pred)
+    }
+
+    fn drain(&mut self) -> Vec<(TypeId, Slot<'a>)> {
+        std::mem::take(self).into_iter().collect()
+    }
+
+    fn clear(&mut self) {
+        BTreeMap::clear(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+    use std::cell::Cell;
+    use std::mem;
+    use std::rc::Rc;
+
+    use {HandlerMap, HandlerStore, Slot};
+
+    /// Store which keeps its handlers in a list, in the order they were registered, and only
+    /// implements the required methods.
+    #[derive(Default)]
+    struct LinearStore<'a>(Vec<(TypeId, Slot<'a>)>);
+
+    impl<'a> HandlerStore<'a> for LinearStore<'a> {
+        fn get(&self, id: &TypeId) -> Option<&Slot<'a>> {
+            self.0.iter().find(|pair| pair.0 == *id).map(|pair| &pair.1)
+        }
+
+        fn get_mut(&mut self, id: &TypeId) -> Option<&mut Slot<'a>> {
+            self.0.iter_mut().find(|pair| pair.0 == *id).map(|pair| &mut pair.1)
+        }
+
+        fn insert(&mut self, id: TypeId, slot: Slot<'a>) -> Option<Slot<'a>> {
+            match self.get_mut(&id) {
+                Some(existing) => Some(mem::replace(existing, slot)),
+                None => {
+                    self.0.push((id, slot));
+                    None
+                }
+            }
+        }
+
+        fn remove(&mut self, id: &TypeId) -> Option<Slot<'a>> {
+            let idx = self.0.iter().position(|pair| pair.0 == *id)?;
+            Some(self.0.remove(idx).1)
+        }
+
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = (&TypeId, &Slot<'a>)> + '_> {
+            Box::new(self.0.iter().map(|pair| (&pair.0, &pair.1)))
+        }
+    }
+
+    #[test]
+    fn custom_store() {
+        let hits = Cell::new(0);
+        let mut map = HandlerMap::with_store(LinearStore::default());
+        map.insert(|_: String| {});
+        map.insert(|_: u8| panic!("should have been replaced"));
+        map.insert(|_: bool| {});
+        map.insert(|_: ()| {});
+        map.insert(|n: u8| hits.set(hits.get() + n));
+        map.entry::<u16>().or_default();
+
+        assert!(map.call(3u8));
+        assert!(!map.call(3u32));
+        assert_eq!(hits.get(), 3);
+        assert_eq!(map.len(), 5);
+        assert!(map.is_registered::<u16>());
+
+        let mut expected = vec![
+            TypeId::of::<String>(), TypeId::of::<u8>(), TypeId::of::<bool>(), TypeId::of::<()>(),
+            TypeId::of::<u16>(),
+        ];
+        assert_eq!(map.registered_types().collect::<Vec<_>>(), expected);
+
+        // the provided methods are built on the required ones
+        assert!(map.remove::<bool>());
+        map.retain(|id| *id != TypeId::of::<()>());
+        expected.retain(|id| *id != TypeId::of::<bool>() && *id != TypeId::of::<()>());
+        assert_eq!(map.registered_types().collect::<Vec<_>>(), expected);
+        assert_eq!(map.capacity(), map.len());
+
+        let mut other = HandlerMap::with_store(LinearStore::default());
+        other.insert(|_: char| {});
+        map.merge(other);
+        expected.push(TypeId::of::<char>());
+        assert_eq!(map.into_iter().map(|(id, _)| id).collect::<Vec<_>>(), expected);
+
+        let mut cloneable = HandlerMap::with_store(LinearStore::default());
+        cloneable.insert_cloneable(|_: u8| {});
+        cloneable.insert_cloneable(|_: bool| {});
+        let copy = cloneable.try_clone().unwrap();
+        assert_eq!(copy.registered_types().collect::<Vec<_>>(),
+                   cloneable.registered_types().collect::<Vec<_>>());
+
+        let child = HandlerMap::with_store(LinearStore::default()).with_parent(Rc::new(copy));
+        assert!(child.call(5u8));
+        assert!(child.is_registered_recursive::<bool>());
+        cloneable.clear();
+        assert!(cloneable.is_empty());
+    }
+}
@@ -0,0 +1,99 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! A handler map which can be shared between threads.
+
+use std::any::{Any, TypeId};
+
+use {Handler, HandlerMap};
+
+/// A handler map which is `Send` and `Sync`, so it can be shared between threads.
+///
+/// Handlers registered in this map must be `Send + Sync + 'static`, and so must their message
+/// types. Since every handler can be called from several threads at once, any state they share
+/// has to be synchronized, for example through atomics or a `Mutex`:
+///
+/// ```rust
+/// use handler_map::SyncHandlerMap;
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::thread;
+///
+/// struct Job;
+///
+/// let count = Arc::new(AtomicUsize::new(0));
+/// let mut map = SyncHandlerMap::new();
+/// {
+///     let count = count.clone();
+///     map.insert(move |_: Job| { count.fetch_add(1, Ordering::SeqCst); });
+/// }
+///
+/// let map = Arc::new(map);
+/// let workers = (0..4).map(|_| {
+///     let map = map.clone();
+///     thread::spawn(move || assert!(map.call(Job)))
+/// }).collect::<Vec<_>>();
+///
+/// for worker in workers {
+///     worker.join().unwrap();
+/// }
+///
+/// assert_eq!(count.load(Ordering::SeqCst), 4);
+/// ```
+///
+/// Only the parts of the `HandlerMap` API which don't rely on single-threaded state are
+/// available on this map.
+#[derive(Debug, Default)]
+pub struct SyncHandlerMap {
+    map: HandlerMap<'static>,
+}
+
+// The inner map is only ever given handlers that are `Send + Sync`, through `insert`. The methods
+// of `HandlerMap` that can be reached from `SyncHandlerMap` don't touch any of its `Cell`s or
+// `RefCell`s (single-shot state, validators, the dead-letter queue, or the cascade graph), since
+// those features can't be enabled through this wrapper. The rest of the map is only read by
+// `call` and `is_registered`, and only written through `&mut self`.
+unsafe impl Send for SyncHandlerMap {}
+unsafe impl Sync for SyncHandlerMap {}
+
+impl SyncHandlerMap {
+    /// Creates a new map with no handlers.
+    pub fn new() -> SyncHandlerMap {
+        Self::default()
+    }
+
+    /// Registers a new handler into the map.
+    pub fn insert<T, F>(&mut self, handler: F)
+    where
+        T: Any + Send,
+        F: Handler<T> + Send + Sync + 'static,
+    {
+        self.map.insert(handler);
+    }
+
+    /// Un-registers the handler for the given type from this map, returning whether a handler
+    /// was registered.
+    pub fn remove<T: Any + Send>(&mut self) -> bool {
+        self.map.remove::<T>()
+    }
+
+    /// Returns true if the given message type has a handler registered in the map.
+    pub fn is_registered<T: Any + Send>(&self) -> bool {
+        self.map.is_registered::<T>()
+    }
+
+    /// Returns an iterator over the `TypeId`s of the message types with a handler registered in
+    /// the map, in an unspecified order.
+    pub fn registered_types<'b>(&'b self) -> impl Iterator<Item = TypeId> + use<'b> {
+        self.map.registered_types()
+    }
+
+    /// Calls the handler with the given message, returning whether the handler was registered.
+    ///
+    /// This can be called from several threads at once, even for the same message type.
+    pub fn call<T: Any + Send>(&self, msg: T) -> bool {
+        self.map.call(msg)
+    }
+}
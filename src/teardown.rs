@@ -0,0 +1,174 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for handlers with a teardown closure, which is run when the handler is removed.
+
+use std::any::{Any, TypeId};
+use std::hash::BuildHasher;
+use std::panic::{self, AssertUnwindSafe};
+
+use box_fn::BoxFn;
+use {Handler, HandlerMap, HandlerStore, Slot, SlotHandler};
+
+/// Teardown closure registered with `insert_with_teardown`.
+pub(crate) struct Teardown<'a> {
+    run: Option<Box<dyn FnOnce() + 'a>>,
+    /// Whether to run the closure when it's dropped without being run, as set by
+    /// `set_teardown_on_drop`.
+    pub(crate) on_drop: bool,
+}
+
+impl<'a> Teardown<'a> {
+    /// Runs the teardown closure.
+    pub(crate) fn run(mut self) {
+        if let Some(run) = self.run.take() {
+            run();
+        }
+    }
+}
+
+impl<'a> Drop for Teardown<'a> {
+    /// Runs the teardown closure if `on_drop` is set, and it wasn't already run.
+    ///
+    /// If the closure panics, the panic is caught here, after the panic hook has reported it. This
+    /// way, the map carries on dropping its other handlers, and a panicking teardown doesn't abort
+    /// the process when the map is dropped while unwinding from another panic. Since running the
+    /// teardown on drop has to be enabled with `set_teardown_on_drop`, this doesn't depend on
+    /// `set_catch_drop_panics`.
+    fn drop(&mut self) {
+        if self.on_drop {
+            if let Some(run) = self.run.take() {
+                let _ = panic::catch_unwind(AssertUnwindSafe(run));
+            }
+        }
+    }
+}
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Registers a new handler into the map, along with a teardown closure to run when the
+    /// handler is removed.
+    ///
+    /// The teardown is run by `remove` and `remove_val`, before the handler is dropped. By
+    /// default, it's only run by those explicit removals: if the handler is replaced by another
+    /// one, or dropped by `clear`, `retain`, or dropping the map, the teardown is dropped without
+    /// being run. Use `set_teardown_on_drop` to have it run in those cases as well. Taking the
+    /// handler out of the map with `take_handler` keeps the teardown with it.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::cell::Cell;
+    ///
+    /// struct Frame;
+    ///
+    /// let open = Cell::new(true);
+    /// let mut map = HandlerMap::new();
+    /// map.insert_with_teardown(|_: Frame| println!("drawing"), || open.set(false));
+    ///
+    /// assert!(map.call(Frame));
+    /// assert!(map.remove::<Frame>());
+    /// assert!(!open.get());
+    /// ```
+    pub fn insert_with_teardown<T, F, D>(&mut self, handler: F, teardown: D)
+    where
+        T: Any,
+        F: Handler<T> + 'a,
+        D: FnOnce() + 'a,
+    {
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        let mut slot = Slot::new::<T>(SlotHandler::Value(ptr));
+        slot.teardown = Some(Teardown {
+            run: Some(Box::new(teardown)),
+            on_drop: false,
+        });
+        self.insert_slot(id, slot);
+    }
+
+    /// Sets whether the teardown closure registered with the handler for the given message type
+    /// by `insert_with_teardown` also runs when the handler is dropped without being removed by
+    /// `remove`. Returns false if the handler for that type has no teardown closure.
+    ///
+    /// When enabled, the teardown runs however the handler leaves the map: when it's replaced by
+    /// another handler, when it's dropped by `clear` or `retain`, or when the map itself is
+    /// dropped. A teardown which panics in one of those cases has its panic caught and reported by
+    /// the panic hook, so that the rest of the map is still dropped. A panic from a teardown run
+    /// by `remove` is passed on to its caller as usual.
+    pub fn set_teardown_on_drop<T: Any>(&mut self, enabled: bool) -> bool {
+        let id = TypeId::of::<T>();
+        match self.slots.get_mut(&id).and_then(|slot| slot.teardown.as_mut()) {
+            Some(teardown) => {
+                teardown.on_drop = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use HandlerMap;
+
+    #[test]
+    fn insert_with_teardown() {
+        let log = RefCell::new(Vec::new());
+        let mut map = HandlerMap::new();
+        map.insert_with_teardown(|_: u8| {}, || log.borrow_mut().push("u8"));
+        map.insert_with_teardown(|_: u16| {}, || log.borrow_mut().push("u16"));
+        map.insert_with_teardown(|_: u32| {}, || log.borrow_mut().push("u32"));
+        map.insert_with_teardown(|_: u64| {}, || log.borrow_mut().push("u64"));
+        assert!(!map.set_teardown_on_drop::<bool>(true));
+
+        assert!(map.remove::<u8>());
+        assert!(!map.remove::<u8>());
+        assert_eq!(*log.borrow(), ["u8"]);
+
+        map.insert(|_: u16| {});
+        assert_eq!(*log.borrow(), ["u8"]);
+
+        assert!(map.set_teardown_on_drop::<u32>(true));
+        map.insert(|_: u32| {});
+        assert_eq!(*log.borrow(), ["u8", "u32"]);
+
+        assert!(map.set_teardown_on_drop::<u64>(true));
+        drop(map);
+        assert_eq!(*log.borrow(), ["u8", "u32", "u64"]);
+    }
+
+    #[test]
+    fn teardown_panics() {
+        use std::cell::Cell;
+        use std::panic::{self, AssertUnwindSafe};
+
+        let ran = Cell::new(0);
+        let make_map = || {
+            let mut map = HandlerMap::new();
+            map.insert_with_teardown(|_: u8| {}, || panic!("teardown"));
+            map.insert_with_teardown(|_: u16| {}, || ran.set(ran.get() + 1));
+            assert!(map.set_teardown_on_drop::<u8>(true));
+            assert!(map.set_teardown_on_drop::<u16>(true));
+            map
+        };
+
+        drop(make_map());
+        assert_eq!(ran.get(), 1);
+
+        // dropping the map while unwinding from another panic mustn't abort the process
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _map = make_map();
+            panic!("handler");
+        }));
+        assert!(res.is_err());
+        assert_eq!(ran.get(), 2);
+
+        let mut map = make_map();
+        map.insert(|_: u8| {});
+        map.clear();
+        assert_eq!(ran.get(), 3);
+    }
+}
@@ -0,0 +1,122 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Support for handlers which hold a `Weak` reference to the value they act on, and can be
+//! removed once that value is dropped.
+
+use std::any::{Any, TypeId};
+use std::cell::Cell;
+use std::hash::BuildHasher;
+use std::rc::Rc;
+use std::sync::{Arc, Weak};
+
+use box_fn::BoxFn;
+use {HandlerMap, HandlerStore, Slot, SlotHandler};
+
+impl<'a, S: BuildHasher, H: HandlerStore<'a>> HandlerMap<'a, S, H> {
+    /// Registers a new handler into the map which acts on the value behind the given `Weak`.
+    ///
+    /// Each time the handler is called, the `Weak` is upgraded, and the handler receives the
+    /// resulting `Arc` along with the message. If the upgrade fails because every `Arc` to the
+    /// value has been dropped, the message is dropped without calling the handler, and the handler
+    /// is marked as dead, so that the next call to `prune_dead` removes it from the map. Until
+    /// then, it's still registered, so `call` returns true.
+    ///
+    /// ```rust
+    /// use handler_map::HandlerMap;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// struct Label {
+    ///     text: Mutex<String>,
+    /// }
+    ///
+    /// struct SetText(String);
+    ///
+    /// let label = Arc::new(Label { text: Mutex::new(String::new()) });
+    /// let mut map = HandlerMap::new();
+    /// map.insert_weak(Arc::downgrade(&label), |label, msg: SetText| {
+    ///     *label.text.lock().unwrap() = msg.0;
+    /// });
+    ///
+    /// assert!(map.call(SetText("hello".into())));
+    /// assert_eq!(*label.text.lock().unwrap(), "hello");
+    ///
+    /// drop(label);
+    /// assert!(map.call(SetText("goodbye".into())));
+    /// assert_eq!(map.prune_dead(), 1);
+    /// assert!(!map.is_registered::<SetText>());
+    /// ```
+    pub fn insert_weak<T, W, F>(&mut self, weak: Weak<W>, handler: F)
+    where
+        T: Any,
+        W: ?Sized + 'a,
+        F: Fn(&Arc<W>, T) + 'a,
+    {
+        let dead = Rc::new(Cell::new(false));
+        let mark = dead.clone();
+        let handler = move |msg: T| match weak.upgrade() {
+            Some(target) => handler(&target, msg),
+            None => mark.set(true),
+        };
+        let ptr = BoxFn::from(Box::new(handler)).erase().erase_arg();
+        let id = TypeId::of::<T>();
+
+        let mut slot = Slot::new::<T>(SlotHandler::Value(ptr));
+        slot.dead = Some(dead);
+        self.insert_slot(id, slot);
+    }
+
+    /// Un-registers every handler registered with `insert_weak` which has been marked as dead,
+    /// returning how many were removed.
+    ///
+    /// A handler is only marked as dead when it's called after its value has been dropped, so a
+    /// handler which hasn't been called since then is kept. This also removes handlers
+    /// registered with `insert_scoped` whose `Subscription` was dropped while the map was
+    /// borrowed.
+    pub fn prune_dead(&mut self) -> usize {
+        let before = self.slots.len();
+        self.slots.retain(&mut |_, slot| !slot.dead.as_ref().is_some_and(|dead| dead.get()));
+        before - self.slots.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    use HandlerMap;
+
+    #[test]
+    fn prune_dead() {
+        struct Widget {
+            clicks: AtomicU32,
+        }
+
+        struct Click;
+        struct Hover;
+
+        let button = Arc::new(Widget { clicks: AtomicU32::new(0) });
+        let panel = Arc::new(Widget { clicks: AtomicU32::new(0) });
+        let mut map = HandlerMap::new();
+        map.insert_weak(Arc::downgrade(&button), |w, _: Click| {
+            w.clicks.fetch_add(1, Ordering::SeqCst);
+        });
+        map.insert_weak(Arc::downgrade(&panel), |_, _: Hover| {});
+        map.insert(|_: u32| {});
+
+        assert!(map.call(Click));
+        assert_eq!(button.clicks.load(Ordering::SeqCst), 1);
+        assert_eq!(map.prune_dead(), 0);
+
+        drop(button);
+        drop(panel);
+        assert!(map.call(Click));
+        assert_eq!(map.prune_dead(), 1);
+        assert!(!map.is_registered::<Click>());
+        assert!(map.is_registered::<Hover>());
+        assert_eq!(map.len(), 2);
+    }
+}
@@ -0,0 +1,89 @@
+// This Source Code Form is subject to the terms of the
+// Mozilla Public License, v. 2.0. If a copy of the MPL was
+// not distributed with this file, You can obtain one at
+// http://mozilla.org/MPL/2.0/.
+
+//! Tests of the `box_fn` module exposed by the `unsafe-internals` feature, used from outside the
+//! crate.
+
+#![cfg(feature = "unsafe-internals")]
+
+extern crate handler_map;
+
+use std::any::{Any, TypeId};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use handler_map::box_fn::{BoxFn, Opaque};
+
+/// A minimal dispatcher built on `BoxFn`, keyed by argument and return type together.
+struct Dispatch<'a> {
+    fns: HashMap<(TypeId, TypeId), BoxFn<'a, Opaque, Opaque>>,
+}
+
+impl<'a> Dispatch<'a> {
+    fn insert<A: Any, R: Any, F: Fn(A) -> R + 'a>(&mut self, f: F) {
+        let ptr = BoxFn::from(Box::new(f)).erase().erase_arg().erase_ret();
+        self.fns.insert((TypeId::of::<A>(), TypeId::of::<R>()), ptr);
+    }
+
+    fn call<A: Any, R: Any>(&self, arg: A) -> Option<R> {
+        let act = self.fns.get(&(TypeId::of::<A>(), TypeId::of::<R>()))?;
+        // the key guarantees that the closure was created with these argument and return types
+        Some(unsafe { act.call_erased_ret(arg) })
+    }
+}
+
+#[test]
+fn dispatch() {
+    let mut dispatch = Dispatch { fns: HashMap::new() };
+    dispatch.insert(|n: u32| n * 2);
+    dispatch.insert(|s: String| s.len());
+    dispatch.insert(|s: String| s + "!");
+
+    assert_eq!(dispatch.call::<u32, u32>(21), Some(42));
+    assert_eq!(dispatch.call::<String, usize>("four".into()), Some(4));
+    assert_eq!(dispatch.call::<String, String>("hi".into()), Some("hi!".to_string()));
+    assert_eq!(dispatch.call::<u32, u64>(21), None);
+}
+
+#[test]
+fn erase_steps() {
+    let calls = Rc::new(Cell::new(0));
+
+    let typed = {
+        let calls = calls.clone();
+        BoxFn::from(Box::new(move |n: u8| calls.set(calls.get() + n)))
+    };
+    typed.call(1);
+
+    let erased = typed.erase();
+    erased.call(2);
+
+    let erased = erased.erase_arg();
+    unsafe { erased.call_erased(3u8) };
+    assert_eq!(calls.get(), 6);
+
+    assert_eq!(Rc::strong_count(&calls), 2);
+    drop(erased);
+    assert_eq!(Rc::strong_count(&calls), 1);
+}
+
+#[test]
+fn try_clone() {
+    let count = Rc::new(Cell::new(0));
+
+    let cloneable = {
+        let count = count.clone();
+        BoxFn::cloneable(Box::new(move |()| count.set(count.get() + 1))).erase()
+    };
+    let copy = cloneable.try_clone().unwrap();
+    cloneable.call(());
+    copy.call(());
+    assert_eq!(count.get(), 2);
+    assert_eq!(Rc::strong_count(&count), 3);
+
+    let plain = BoxFn::from(Box::new(|()| {})).erase();
+    assert!(plain.try_clone().is_none());
+}